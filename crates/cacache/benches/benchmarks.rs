@@ -1,6 +1,7 @@
 use async_std::{fs as afs, task};
 use std::fs::{self, File};
 use std::io::prelude::*;
+use std::path::Path;
 
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 
@@ -73,103 +74,103 @@ fn baseline_read_many_async(c: &mut Criterion) {
 
 fn read_hash_sync(c: &mut Criterion) {
     let tmp = tempfile::tempdir().unwrap();
-    let cache = tmp.path().to_owned();
-    let data = b"hello world".to_vec();
-    let sri = cacache::write_sync(&cache, "hello", data).unwrap();
-    c.bench_function("get::data_hash_sync", move |b| {
-        b.iter(|| cacache::read_hash_sync(black_box(&cache), black_box(&sri)).unwrap())
-    });
+    for (name, store) in backends(tmp.path()) {
+        let sri = store.write_sync("hello", b"hello world").unwrap();
+        c.bench_function(&format!("get::data_hash_sync/{}", name), move |b| {
+            b.iter(|| store.read_hash_sync(black_box(&sri)).unwrap())
+        });
+    }
 }
 
 fn read_hash_many_sync(c: &mut Criterion) {
     let tmp = tempfile::tempdir().unwrap();
-    let cache = tmp.path().to_owned();
-    let data: Vec<_> = (0..)
-        .take(NUM_REPEATS)
-        .map(|i| format!("test_file_{}", i))
-        .collect();
-    let sris: Vec<_> = data
-        .iter()
-        .map(|datum| cacache::write_sync(&cache, "hello", datum).unwrap())
-        .collect();
-    c.bench_function("get::data_hash_many_sync", move |b| {
-        b.iter(|| {
-            for sri in sris.iter() {
-                cacache::read_hash_sync(black_box(&cache), black_box(&sri)).unwrap();
-            }
-        })
-    });
+    for (name, store) in backends(tmp.path()) {
+        let sris: Vec<_> = (0..)
+            .take(NUM_REPEATS)
+            .map(|i| {
+                store
+                    .write_sync("hello", format!("test_file_{}", i))
+                    .unwrap()
+            })
+            .collect();
+        c.bench_function(&format!("get::data_hash_many_sync/{}", name), move |b| {
+            b.iter(|| {
+                for sri in sris.iter() {
+                    store.read_hash_sync(black_box(sri)).unwrap();
+                }
+            })
+        });
+    }
 }
 
 fn read_sync(c: &mut Criterion) {
     let tmp = tempfile::tempdir().unwrap();
-    let cache = tmp.path().to_owned();
-    let data = b"hello world".to_vec();
-    cacache::write_sync(&cache, "hello", data).unwrap();
-    c.bench_function("get::data_sync", move |b| {
-        b.iter(|| cacache::read_sync(black_box(&cache), black_box(String::from("hello"))).unwrap())
-    });
+    for (name, store) in backends(tmp.path()) {
+        store.write_sync("hello", b"hello world").unwrap();
+        c.bench_function(&format!("get::data_sync/{}", name), move |b| {
+            b.iter(|| store.read_sync(black_box(String::from("hello"))).unwrap())
+        });
+    }
 }
 
 fn read_hash_sync_big_data(c: &mut Criterion) {
     let tmp = tempfile::tempdir().unwrap();
-    let cache = tmp.path().to_owned();
-    let data = vec![1; 1024 * 1024 * 5];
-    let sri = cacache::write_sync(&cache, "hello", data).unwrap();
-    c.bench_function("get_hash_big_data", move |b| {
-        b.iter(|| cacache::read_hash_sync(black_box(&cache), black_box(&sri)).unwrap())
-    });
+    for (name, store) in backends(tmp.path()) {
+        let sri = store.write_sync("hello", vec![1; 1024 * 1024 * 5]).unwrap();
+        c.bench_function(&format!("get_hash_big_data/{}", name), move |b| {
+            b.iter(|| store.read_hash_sync(black_box(&sri)).unwrap())
+        });
+    }
 }
 
 fn read_hash_many_async(c: &mut Criterion) {
     let tmp = tempfile::tempdir().unwrap();
-    let cache = tmp.path().to_owned();
-    let data: Vec<_> = (0..)
-        .take(NUM_REPEATS)
-        .map(|i| format!("test_file_{}", i))
-        .collect();
-    let sris: Vec<_> = data
-        .iter()
-        .map(|datum| cacache::write_sync(&cache, "hello", datum).unwrap())
-        .collect();
-    c.bench_function("get::data_hash_many", move |b| {
-        b.iter(|| {
-            let tasks = sris
-                .iter()
-                .map(|sri| cacache::read_hash(black_box(&cache), black_box(&sri)));
-            task::block_on(futures::future::join_all(tasks));
-        })
-    });
+    for (name, store) in backends(tmp.path()) {
+        let sris: Vec<_> = (0..)
+            .take(NUM_REPEATS)
+            .map(|i| {
+                store
+                    .write_sync("hello", format!("test_file_{}", i))
+                    .unwrap()
+            })
+            .collect();
+        c.bench_function(&format!("get::data_hash_many/{}", name), move |b| {
+            b.iter(|| {
+                let tasks = sris.iter().map(|sri| store.read_hash(black_box(sri)));
+                task::block_on(futures::future::join_all(tasks));
+            })
+        });
+    }
 }
 
 fn read_hash_async(c: &mut Criterion) {
     let tmp = tempfile::tempdir().unwrap();
-    let cache = tmp.path().to_owned();
-    let data = b"hello world".to_vec();
-    let sri = cacache::write_sync(&cache, "hello", data).unwrap();
-    c.bench_function("get::data_hash", move |b| {
-        b.iter(|| task::block_on(cacache::read_hash(black_box(&cache), black_box(&sri))).unwrap())
-    });
+    for (name, store) in backends(tmp.path()) {
+        let sri = store.write_sync("hello", b"hello world").unwrap();
+        c.bench_function(&format!("get::data_hash/{}", name), move |b| {
+            b.iter(|| task::block_on(store.read_hash(black_box(&sri))).unwrap())
+        });
+    }
 }
 
 fn read_async(c: &mut Criterion) {
     let tmp = tempfile::tempdir().unwrap();
-    let cache = tmp.path().to_owned();
-    let data = b"hello world".to_vec();
-    cacache::write_sync(&cache, "hello", data).unwrap();
-    c.bench_function("get::data", move |b| {
-        b.iter(|| task::block_on(cacache::read(black_box(&cache), black_box("hello"))).unwrap())
-    });
+    for (name, store) in backends(tmp.path()) {
+        store.write_sync("hello", b"hello world").unwrap();
+        c.bench_function(&format!("get::data/{}", name), move |b| {
+            b.iter(|| task::block_on(store.read(black_box("hello"))).unwrap())
+        });
+    }
 }
 
 fn read_hash_async_big_data(c: &mut Criterion) {
     let tmp = tempfile::tempdir().unwrap();
-    let cache = tmp.path().to_owned();
-    let data = vec![1; 1024 * 1024 * 5];
-    let sri = cacache::write_sync(&cache, "hello", data).unwrap();
-    c.bench_function("get::data_big_data", move |b| {
-        b.iter(|| task::block_on(cacache::read_hash(black_box(&cache), black_box(&sri))).unwrap())
-    });
+    for (name, store) in backends(tmp.path()) {
+        let sri = store.write_sync("hello", vec![1; 1024 * 1024 * 5]).unwrap();
+        c.bench_function(&format!("get::data_big_data/{}", name), move |b| {
+            b.iter(|| task::block_on(store.read_hash(black_box(&sri))).unwrap())
+        });
+    }
 }
 
 fn baseline_write_sync(c: &mut Criterion) {
@@ -248,40 +249,54 @@ fn baseline_write_many_async(c: &mut Criterion) {
 
 fn write_hash_async(c: &mut Criterion) {
     let tmp = tempfile::tempdir().unwrap();
-    let cache = tmp.path().to_owned();
-    c.bench_function("put::data", move |b| {
-        b.iter_custom(|iters| {
-            let start = std::time::Instant::now();
-            for i in 0..iters {
-                task::block_on(cacache::write_hash(&cache, format!("hello world{}", i))).unwrap();
-            }
-            start.elapsed()
-        })
-    });
+    for (name, store) in backends(tmp.path()) {
+        c.bench_function(&format!("put::data/{}", name), move |b| {
+            b.iter_custom(|iters| {
+                let start = std::time::Instant::now();
+                for i in 0..iters {
+                    task::block_on(store.write_hash(format!("hello world{}", i))).unwrap();
+                }
+                start.elapsed()
+            })
+        });
+    }
 }
 
 fn write_hash_many_async(c: &mut Criterion) {
     let tmp = tempfile::tempdir().unwrap();
-    let cache = tmp.path().to_owned();
-    c.bench_function("put::data_many", move |b| {
-        b.iter_custom(|iters| {
-            let start = std::time::Instant::now();
-            for i in 0..iters {
-                task::block_on(async {
-                    let mut tasks = Vec::new();
-                    for j in 0..NUM_REPEATS {
-                        let j = j;
-                        tasks.push(cacache::write_hash(
-                            &cache,
-                            format!("hello world{}-{}", i, j),
-                        ));
-                    }
-                    futures::future::join_all(tasks).await;
-                });
-            }
-            start.elapsed()
-        })
-    });
+    for (name, store) in backends(tmp.path()) {
+        c.bench_function(&format!("put::data_many/{}", name), move |b| {
+            b.iter_custom(|iters| {
+                let start = std::time::Instant::now();
+                for i in 0..iters {
+                    task::block_on(async {
+                        let mut tasks = Vec::new();
+                        for j in 0..NUM_REPEATS {
+                            tasks.push(store.write_hash(format!("hello world{}-{}", i, j)));
+                        }
+                        futures::future::join_all(tasks).await;
+                    });
+                }
+                start.elapsed()
+            })
+        });
+    }
+}
+
+/// Every backend a `Store` can run on, each with a fresh, empty cache. The
+/// `fs` one is the same on-disk layout the path-based functions use.
+fn backends(dir: &Path) -> Vec<(&'static str, cacache::Store)> {
+    #[allow(unused_mut)]
+    let mut backends = vec![
+        ("fs", cacache::Store::open(dir.join("fs"))),
+        ("memory", cacache::Store::new(cacache::MemoryBackend::new())),
+    ];
+    #[cfg(feature = "sled")]
+    backends.push((
+        "sled",
+        cacache::Store::new(cacache::SledBackend::open(dir.join("sled")).unwrap()),
+    ));
+    backends
 }
 
 criterion_group!(
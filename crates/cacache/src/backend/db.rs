@@ -0,0 +1,125 @@
+use std::path::{Path, PathBuf};
+
+use ssri::Integrity;
+
+use crate::backend::Backend;
+use crate::errors::{Internal, Result};
+use crate::index::{self, Metadata};
+
+/// A backend that keeps the whole cache in a single embedded
+/// [sled](https://crates.io/crates/sled) database, for platforms where
+/// millions of small files are slow or run into filesystem limits.
+///
+/// Entries are stored in the same format as index buckets. Writes are
+/// flushed to disk in the background, and when the backend is dropped.
+///
+/// Only available with the `sled` feature.
+#[derive(Clone, Debug)]
+pub struct SledBackend {
+    path: PathBuf,
+    index: sled::Tree,
+    // Keyed by each individual hash, so lookups can go by any of them.
+    content: sled::Tree,
+}
+
+impl SledBackend {
+    /// Opens the database at `path`, creating it if it doesn't exist yet.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_owned();
+        let db = sled::open(&path)
+            .with_context(|| format!("Failed to open cache database at {:?}", path))?;
+        let index = db
+            .open_tree("index")
+            .with_context(|| format!("Failed to open index in cache database at {:?}", path))?;
+        let content = db
+            .open_tree("content")
+            .with_context(|| format!("Failed to open content in cache database at {:?}", path))?;
+        Ok(SledBackend {
+            path,
+            index,
+            content,
+        })
+    }
+}
+
+impl Backend for SledBackend {
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn get_entry(&self, key: &str) -> Result<Option<Metadata>> {
+        let raw = self
+            .index
+            .get(key)
+            .with_context(|| format!("Failed to read index entry for {:?}", key))?;
+        Ok(raw.and_then(|raw| index::decode_entry(&raw)))
+    }
+
+    fn put_entry(&self, entry: &Metadata) -> Result<()> {
+        self.index
+            .insert(entry.key.as_bytes(), index::encode_entry(entry)?)
+            .with_context(|| format!("Failed to write index entry for {:?}", entry.key))?;
+        Ok(())
+    }
+
+    fn remove_entry(&self, key: &str) -> Result<()> {
+        self.index
+            .remove(key)
+            .with_context(|| format!("Failed to remove index entry for {:?}", key))?;
+        Ok(())
+    }
+
+    fn entries(&self) -> Result<Vec<Metadata>> {
+        let mut entries = Vec::new();
+        for item in self.index.iter() {
+            let (_, raw) =
+                item.with_context(|| format!("Failed to list index entries in {:?}", self.path))?;
+            entries.extend(index::decode_entry(&raw));
+        }
+        Ok(entries)
+    }
+
+    fn get_content(&self, sri: &Integrity) -> Result<Option<Vec<u8>>> {
+        for hash in sri.hashes.iter() {
+            let data = self
+                .content
+                .get(hash.to_string())
+                .with_context(|| format!("Failed to read content for {}", hash))?;
+            if let Some(data) = data {
+                return Ok(Some(data.to_vec()));
+            }
+        }
+        Ok(None)
+    }
+
+    fn put_content(&self, sri: &Integrity, data: &[u8]) -> Result<()> {
+        for hash in sri.hashes.iter() {
+            self.content
+                .insert(hash.to_string(), data)
+                .with_context(|| format!("Failed to write content for {}", hash))?;
+        }
+        Ok(())
+    }
+
+    fn has_content(&self, sri: &Integrity) -> Result<bool> {
+        for hash in sri.hashes.iter() {
+            let found = self
+                .content
+                .contains_key(hash.to_string())
+                .with_context(|| format!("Failed to look up content for {}", hash))?;
+            if found {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn remove_content(&self, sri: &Integrity) -> Result<()> {
+        for hash in sri.hashes.iter() {
+            self.content
+                .remove(hash.to_string())
+                .with_context(|| format!("Failed to remove content for {}", hash))?;
+        }
+        Ok(())
+    }
+}
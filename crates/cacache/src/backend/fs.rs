@@ -0,0 +1,84 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use ssri::Integrity;
+
+use crate::backend::Backend;
+use crate::content::{read, rm};
+use crate::errors::{Internal, Result};
+use crate::index::{self, Metadata};
+use crate::put::WriteOpts;
+
+/// The default backend: the usual on-disk cache layout, shared with every
+/// path-based function in this crate.
+#[derive(Clone, Debug)]
+pub struct FsBackend {
+    cache: PathBuf,
+}
+
+impl FsBackend {
+    /// Creates a backend for the cache directory at `cache`.
+    pub fn new<P: AsRef<Path>>(cache: P) -> Self {
+        FsBackend {
+            cache: cache.as_ref().to_owned(),
+        }
+    }
+}
+
+impl Backend for FsBackend {
+    fn path(&self) -> &Path {
+        &self.cache
+    }
+
+    fn get_entry(&self, key: &str) -> Result<Option<Metadata>> {
+        index::find(&self.cache, key)
+    }
+
+    fn put_entry(&self, entry: &Metadata) -> Result<()> {
+        index::insert(&self.cache, &entry.key, WriteOpts::from_metadata(entry))?;
+        Ok(())
+    }
+
+    fn remove_entry(&self, key: &str) -> Result<()> {
+        index::delete(&self.cache, key)
+    }
+
+    fn entries(&self) -> Result<Vec<Metadata>> {
+        index::ls(&self.cache).collect()
+    }
+
+    fn get_content(&self, sri: &Integrity) -> Result<Option<Vec<u8>>> {
+        if read::has_content(&self.cache, sri).is_none() {
+            return Ok(None);
+        }
+        read::Reader::consume(&self.cache, sri).map(Some)
+    }
+
+    fn put_content(&self, sri: &Integrity, data: &[u8]) -> Result<()> {
+        let mut writer = sri
+            .hashes
+            .iter()
+            .fold(WriteOpts::new(), |opts, hash| {
+                opts.algorithm(hash.algorithm)
+            })
+            .integrity(sri.clone())
+            .size(data.len())
+            .open_hash_sync(&self.cache)?;
+        writer
+            .write_all(data)
+            .with_context(|| format!("Failed to write content to cache at {:?}", self.cache))?;
+        writer.commit()?;
+        Ok(())
+    }
+
+    fn has_content(&self, sri: &Integrity) -> Result<bool> {
+        Ok(read::has_content(&self.cache, sri).is_some())
+    }
+
+    fn remove_content(&self, sri: &Integrity) -> Result<()> {
+        if read::has_content(&self.cache, sri).is_none() {
+            return Ok(());
+        }
+        rm::rm(&self.cache, sri)
+    }
+}
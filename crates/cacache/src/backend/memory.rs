@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use ssri::Integrity;
+
+use crate::backend::Backend;
+use crate::errors::Result;
+use crate::index::Metadata;
+
+/// A backend that keeps everything in memory and forgets it when dropped.
+/// Mostly useful for tests.
+#[derive(Debug, Default)]
+pub struct MemoryBackend {
+    entries: Mutex<HashMap<String, Metadata>>,
+    // Keyed by each individual hash, so lookups can go by any of them.
+    content: Mutex<HashMap<String, Arc<Vec<u8>>>>,
+}
+
+impl MemoryBackend {
+    /// Creates an empty in-memory backend.
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl Backend for MemoryBackend {
+    fn path(&self) -> &Path {
+        Path::new(":memory:")
+    }
+
+    fn get_entry(&self, key: &str) -> Result<Option<Metadata>> {
+        Ok(self.entries.lock().unwrap().get(key).cloned())
+    }
+
+    fn put_entry(&self, entry: &Metadata) -> Result<()> {
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(entry.key.clone(), entry.clone());
+        Ok(())
+    }
+
+    fn remove_entry(&self, key: &str) -> Result<()> {
+        self.entries.lock().unwrap().remove(key);
+        Ok(())
+    }
+
+    fn entries(&self) -> Result<Vec<Metadata>> {
+        Ok(self.entries.lock().unwrap().values().cloned().collect())
+    }
+
+    fn get_content(&self, sri: &Integrity) -> Result<Option<Vec<u8>>> {
+        let content = self.content.lock().unwrap();
+        Ok(sri
+            .hashes
+            .iter()
+            .find_map(|hash| content.get(&hash.to_string()))
+            .map(|data| data.to_vec()))
+    }
+
+    fn put_content(&self, sri: &Integrity, data: &[u8]) -> Result<()> {
+        let data = Arc::new(data.to_vec());
+        let mut content = self.content.lock().unwrap();
+        for hash in sri.hashes.iter() {
+            content.insert(hash.to_string(), data.clone());
+        }
+        Ok(())
+    }
+
+    fn has_content(&self, sri: &Integrity) -> Result<bool> {
+        let content = self.content.lock().unwrap();
+        Ok(sri
+            .hashes
+            .iter()
+            .any(|hash| content.contains_key(&hash.to_string())))
+    }
+
+    fn remove_content(&self, sri: &Integrity) -> Result<()> {
+        let mut content = self.content.lock().unwrap();
+        for hash in sri.hashes.iter() {
+            content.remove(&hash.to_string());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,57 @@
+//! Storage backends for `Store`.
+use std::path::Path;
+
+use ssri::Integrity;
+
+use crate::errors::Result;
+use crate::index::Metadata;
+
+#[cfg(feature = "sled")]
+mod db;
+mod fs;
+mod memory;
+
+#[cfg(feature = "sled")]
+pub use db::SledBackend;
+pub use fs::FsBackend;
+pub use memory::MemoryBackend;
+
+/// Where a `Store` keeps its index entries and content.
+///
+/// Only `Store` goes through backends. The path-based functions, along with
+/// streaming, compression, linking, and maintenance like `verify` and
+/// `evict`, work directly on the on-disk layout that `FsBackend` wraps.
+///
+/// Backends only store and fetch things. Hashing, integrity checks, and
+/// error reporting for missing entries are all handled by `Store`, so every
+/// backend behaves the same way from the outside.
+pub trait Backend: Send + Sync {
+    /// Where this backend keeps its data, for use in error messages.
+    fn path(&self) -> &Path;
+
+    /// Gets the current entry for `key`, if there is one.
+    fn get_entry(&self, key: &str) -> Result<Option<Metadata>>;
+
+    /// Stores `entry`, replacing any existing entry for the same key.
+    fn put_entry(&self, entry: &Metadata) -> Result<()>;
+
+    /// Removes the entry for `key`. Removing a missing entry is not an error.
+    fn remove_entry(&self, key: &str) -> Result<()>;
+
+    /// Lists every current entry, in no particular order.
+    fn entries(&self) -> Result<Vec<Metadata>>;
+
+    /// Gets the content stored for `sri`, going by any of its hashes.
+    fn get_content(&self, sri: &Integrity) -> Result<Option<Vec<u8>>>;
+
+    /// Stores `data`, which has already been checked against `sri`, so it
+    /// can be found by any of `sri`'s hashes.
+    fn put_content(&self, sri: &Integrity, data: &[u8]) -> Result<()>;
+
+    /// Returns true if there's content stored for `sri`.
+    fn has_content(&self, sri: &Integrity) -> Result<bool>;
+
+    /// Removes the content stored for `sri`. Removing missing content is not
+    /// an error.
+    fn remove_content(&self, sri: &Integrity) -> Result<()>;
+}
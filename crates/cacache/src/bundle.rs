@@ -0,0 +1,473 @@
+//! Functions for exporting parts of a cache into portable bundles, and
+//! importing them into other caches.
+//!
+//! A bundle is a single file holding index entries along with the content
+//! they point to. Its format is line-oriented, starting with a version line,
+//! followed by every piece of content and then every index entry:
+//!
+//! ```text
+//! cacache-bundle-v1
+//! content <integrity> <size>
+//! <size bytes of raw content>
+//! entry <json>
+//! ```
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use ssri::Integrity;
+use tempfile::NamedTempFile;
+
+use crate::content::{path, read};
+use crate::errors::{Error, Internal, Result};
+use crate::index::{self, Metadata};
+use crate::put::WriteOpts;
+
+const BUNDLE_HEADER: &str = "cacache-bundle-v1";
+
+/// Statistics about a completed export or import.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BundleStats {
+    /// Number of index entries that were exported or imported.
+    pub entries: usize,
+    /// Number of unique pieces of content that were exported or imported.
+    pub content: usize,
+    /// Total size, in bytes, of the content that was exported or imported.
+    pub content_size: u64,
+}
+
+#[derive(Deserialize, Serialize)]
+struct BundleEntry {
+    key: String,
+    integrity: String,
+    time: u128,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires: Option<u128>,
+    size: usize,
+    metadata: Value,
+}
+
+/// Exports every entry in `cache`, along with its content, into a single
+/// bundle file at `to`. The bundle can be loaded into another cache with
+/// `import`.
+///
+/// ## Example
+/// ```no_run
+/// use async_attributes;
+///
+/// #[async_attributes::main]
+/// async fn main() -> cacache::Result<()> {
+///     let stats = cacache::export("./my-cache", "./my-cache.bundle").await?;
+///     println!("Exported {} entries", stats.entries);
+///     Ok(())
+/// }
+/// ```
+pub async fn export<P, Q>(cache: P, to: Q) -> Result<BundleStats>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let cache = cache.as_ref().to_owned();
+    let to = to.as_ref().to_owned();
+    smol::unblock!(export_sync(&cache, &to))
+}
+
+/// Exports the entries for `keys` in `cache`, along with their content, into
+/// a single bundle file at `to`. Fails with `Error::EntryNotFound` if any of
+/// the keys aren't in the cache.
+///
+/// ## Example
+/// ```no_run
+/// use async_attributes;
+///
+/// #[async_attributes::main]
+/// async fn main() -> cacache::Result<()> {
+///     cacache::export_keys("./my-cache", &["key-a", "key-b"], "./my-cache.bundle").await?;
+///     Ok(())
+/// }
+/// ```
+pub async fn export_keys<P, I, K, Q>(cache: P, keys: I, to: Q) -> Result<BundleStats>
+where
+    P: AsRef<Path>,
+    I: IntoIterator<Item = K>,
+    K: AsRef<str>,
+    Q: AsRef<Path>,
+{
+    let cache = cache.as_ref().to_owned();
+    let keys: Vec<String> = keys.into_iter().map(|k| k.as_ref().to_owned()).collect();
+    let to = to.as_ref().to_owned();
+    smol::unblock!(export_keys_sync(&cache, &keys, &to))
+}
+
+/// Imports every entry in the bundle at `from` into `cache`. Content is
+/// checked against its integrity hash as it's written, and the import fails
+/// without adding any index entries if anything doesn't match. Entries keep
+/// the write time and metadata they had in the original cache.
+///
+/// ## Example
+/// ```no_run
+/// use async_attributes;
+///
+/// #[async_attributes::main]
+/// async fn main() -> cacache::Result<()> {
+///     let stats = cacache::import("./my-cache", "./my-cache.bundle").await?;
+///     println!("Imported {} entries", stats.entries);
+///     Ok(())
+/// }
+/// ```
+pub async fn import<P, Q>(cache: P, from: Q) -> Result<BundleStats>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let cache = cache.as_ref().to_owned();
+    let from = from.as_ref().to_owned();
+    smol::unblock!(import_sync(&cache, &from))
+}
+
+/// Synchronously exports every entry in `cache` into a bundle file at `to`.
+/// See `export` for details.
+///
+/// ## Example
+/// ```no_run
+/// fn main() -> cacache::Result<()> {
+///     let stats = cacache::export_sync("./my-cache", "./my-cache.bundle")?;
+///     println!("Exported {} entries", stats.entries);
+///     Ok(())
+/// }
+/// ```
+pub fn export_sync<P, Q>(cache: P, to: Q) -> Result<BundleStats>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let entries = index::ls(cache.as_ref()).collect::<Result<Vec<_>>>()?;
+    write_bundle(cache.as_ref(), &entries, to.as_ref())
+}
+
+/// Synchronously exports the entries for `keys` in `cache` into a bundle
+/// file at `to`. See `export_keys` for details.
+///
+/// ## Example
+/// ```no_run
+/// fn main() -> cacache::Result<()> {
+///     cacache::export_keys_sync("./my-cache", &["key-a", "key-b"], "./my-cache.bundle")?;
+///     Ok(())
+/// }
+/// ```
+pub fn export_keys_sync<P, I, K, Q>(cache: P, keys: I, to: Q) -> Result<BundleStats>
+where
+    P: AsRef<Path>,
+    I: IntoIterator<Item = K>,
+    K: AsRef<str>,
+    Q: AsRef<Path>,
+{
+    let cache = cache.as_ref();
+    let mut entries = Vec::new();
+    for key in keys {
+        let key = key.as_ref();
+        match index::find(cache, key)? {
+            Some(entry) => entries.push(entry),
+            None => return Err(Error::EntryNotFound(cache.to_path_buf(), key.into())),
+        }
+    }
+    write_bundle(cache, &entries, to.as_ref())
+}
+
+/// Synchronously imports every entry in the bundle at `from` into `cache`.
+/// See `import` for details.
+///
+/// ## Example
+/// ```no_run
+/// fn main() -> cacache::Result<()> {
+///     let stats = cacache::import_sync("./my-cache", "./my-cache.bundle")?;
+///     println!("Imported {} entries", stats.entries);
+///     Ok(())
+/// }
+/// ```
+pub fn import_sync<P, Q>(cache: P, from: Q) -> Result<BundleStats>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let cache = cache.as_ref();
+    let from = from.as_ref();
+    let file = File::open(from).with_context(|| format!("Failed to open bundle at {:?}", from))?;
+    let mut reader = BufReader::new(file);
+    let mut stats = BundleStats::default();
+
+    let mut line = String::new();
+    read_line(&mut reader, &mut line, from)?;
+    if line != BUNDLE_HEADER {
+        return Err(invalid(from, "not a cacache bundle"));
+    }
+
+    // Entries are only added once all content has made it in, so a bad
+    // bundle never leaves entries behind that point at nothing.
+    let mut imported = HashSet::new();
+    let mut entries = Vec::new();
+    loop {
+        if read_line(&mut reader, &mut line, from)? == 0 {
+            break;
+        }
+        if let Some(header) = line.strip_prefix("content ") {
+            // Integrity strings with multiple hashes contain spaces, so the
+            // size is split off the end.
+            let mut parts = header.rsplitn(2, ' ');
+            let size = parts
+                .next()
+                .and_then(|size| size.parse::<usize>().ok())
+                .ok_or_else(|| invalid(from, "bad content size"))?;
+            let sri = parts
+                .next()
+                .and_then(|sri| sri.parse::<Integrity>().ok())
+                .ok_or_else(|| invalid(from, "bad content integrity"))?;
+            import_content(cache, &sri, size, &mut reader, from)?;
+            let mut newline = [0u8; 1];
+            reader
+                .read_exact(&mut newline)
+                .with_context(|| format!("Failed to read bundle at {:?}", from))?;
+            imported.insert(path::content_path(cache, &sri));
+            stats.content += 1;
+            stats.content_size += size as u64;
+        } else if let Some(json) = line.strip_prefix("entry ") {
+            let entry: BundleEntry = serde_json::from_str(json)
+                .with_context(|| format!("Failed to parse bundle entry in {:?}", from))?;
+            entries.push(entry);
+        } else {
+            return Err(invalid(from, "unrecognized bundle record"));
+        }
+    }
+
+    for entry in entries {
+        let sri = entry
+            .integrity
+            .parse::<Integrity>()
+            .map_err(|_| invalid(from, "bad entry integrity"))?;
+        if !imported.contains(&path::content_path(cache, &sri)) {
+            return Err(invalid(
+                from,
+                "entry refers to content missing from the bundle",
+            ));
+        }
+        let entry = Metadata {
+            key: entry.key,
+            integrity: sri,
+            time: entry.time,
+            atime: entry.time,
+            expires: entry.expires,
+            size: entry.size,
+            metadata: entry.metadata,
+        };
+        index::insert(cache, &entry.key, WriteOpts::from_metadata(&entry))?;
+        stats.entries += 1;
+    }
+    Ok(stats)
+}
+
+fn write_bundle(cache: &Path, entries: &[Metadata], to: &Path) -> Result<BundleStats> {
+    let dir = match to.parent() {
+        Some(parent) if parent != Path::new("") => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    // The bundle is assembled next to its destination and moved into place
+    // once complete, so a failed export doesn't leave a truncated bundle.
+    let tmpfile = NamedTempFile::new_in(&dir)
+        .with_context(|| format!("Failed to create temporary bundle file in {:?}", dir))?;
+    let mut out = BufWriter::new(tmpfile);
+    let mut stats = BundleStats::default();
+    let context = || format!("Failed to write bundle to {:?}", to);
+
+    writeln!(out, "{}", BUNDLE_HEADER).with_context(context)?;
+    let mut exported = HashSet::new();
+    for entry in entries {
+        if !exported.insert(path::content_path(cache, &entry.integrity)) {
+            continue;
+        }
+        // Content is streamed straight into the bundle, and only checked
+        // once it's all been written. A failed check discards the bundle.
+        let mut reader = read::Reader::new(cache, &entry.integrity)?;
+        let size = reader.size() as u64;
+        writeln!(out, "content {} {}", entry.integrity, size).with_context(context)?;
+        let copied = io::copy(&mut (&mut reader).take(size), &mut out).with_context(context)?;
+        if copied != size {
+            return Err(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                format!("content for {} ended early", entry.integrity),
+            ))
+            .with_context(context)?;
+        }
+        reader.check()?;
+        out.write_all(b"\n").with_context(context)?;
+        stats.content += 1;
+        stats.content_size += size;
+    }
+    for entry in entries {
+        let json = serde_json::to_string(&BundleEntry {
+            key: entry.key.clone(),
+            integrity: entry.integrity.to_string(),
+            time: entry.time,
+            expires: entry.expires,
+            size: entry.size,
+            metadata: entry.metadata.clone(),
+        })
+        .with_context(context)?;
+        writeln!(out, "entry {}", json).with_context(context)?;
+        stats.entries += 1;
+    }
+
+    let tmpfile = out
+        .into_inner()
+        .map_err(|err| io::Error::new(err.error().kind(), err.error().to_string()))
+        .with_context(context)?;
+    tmpfile.persist(to).with_context(context)?;
+    Ok(stats)
+}
+
+fn import_content(
+    cache: &Path,
+    sri: &Integrity,
+    size: usize,
+    reader: &mut BufReader<File>,
+    from: &Path,
+) -> Result<()> {
+    let mut data = reader.take(size as u64);
+    if read::has_content(cache, sri).is_some() {
+        io::copy(&mut data, &mut io::sink())
+            .with_context(|| format!("Failed to read bundle at {:?}", from))?;
+        return Ok(());
+    }
+    let mut writer = sri
+        .hashes
+        .iter()
+        .fold(WriteOpts::new(), |opts, hash| {
+            opts.algorithm(hash.algorithm)
+        })
+        .integrity(sri.clone())
+        .size(size)
+        .open_hash_sync(cache)?;
+    io::copy(&mut data, &mut writer)
+        .with_context(|| format!("Failed to read bundle at {:?}", from))?;
+    writer.commit()?;
+    Ok(())
+}
+
+/// Reads a single line into `line`, without its trailing newline. Returns
+/// the number of bytes read, which is 0 at the end of the bundle.
+fn read_line(reader: &mut BufReader<File>, line: &mut String, from: &Path) -> Result<usize> {
+    line.clear();
+    let read = reader
+        .read_line(line)
+        .with_context(|| format!("Failed to read bundle at {:?}", from))?;
+    if line.ends_with('\n') {
+        line.pop();
+    }
+    Ok(read)
+}
+
+fn invalid(from: &Path, msg: &str) -> Error {
+    let err = io::Error::new(ErrorKind::InvalidData, msg);
+    Error::from(
+        Err::<(), _>(err)
+            .with_context(|| format!("Invalid bundle at {:?}", from))
+            .unwrap_err(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    use crate::content::path;
+
+    #[test]
+    fn test_round_trip_sync() {
+        let tmp = tempfile::tempdir().unwrap();
+        let src = tmp.path().join("src");
+        let dest = tmp.path().join("dest");
+        let bundle = tmp.path().join("my.bundle");
+        crate::WriteOpts::new()
+            .metadata(json!({"hello": "world"}))
+            .time(1234)
+            .open_sync(&src, "a")
+            .and_then(|mut fd| {
+                std::io::Write::write_all(&mut fd, b"shared").unwrap();
+                fd.commit()
+            })
+            .unwrap();
+        crate::write_sync(&src, "b", b"shared").unwrap();
+        crate::write_sync(&src, "c", b"other").unwrap();
+
+        let stats = crate::export_sync(&src, &bundle).unwrap();
+        assert_eq!(stats.entries, 3);
+        assert_eq!(stats.content, 2);
+        assert_eq!(stats.content_size, 11);
+
+        let stats = crate::import_sync(&dest, &bundle).unwrap();
+        assert_eq!(stats.entries, 3);
+        assert_eq!(stats.content, 2);
+        assert_eq!(crate::read_sync(&dest, "a").unwrap(), b"shared");
+        assert_eq!(crate::read_sync(&dest, "b").unwrap(), b"shared");
+        assert_eq!(crate::read_sync(&dest, "c").unwrap(), b"other");
+        let entry = crate::metadata_sync(&dest, "a").unwrap().unwrap();
+        assert_eq!(entry.time, 1234);
+        assert_eq!(entry.metadata, json!({"hello": "world"}));
+    }
+
+    #[test]
+    fn test_export_keys_sync() {
+        let tmp = tempfile::tempdir().unwrap();
+        let src = tmp.path().join("src");
+        let dest = tmp.path().join("dest");
+        let bundle = tmp.path().join("my.bundle");
+        crate::write_sync(&src, "a", b"aaa").unwrap();
+        let skipped = crate::write_sync(&src, "b", b"bbb").unwrap();
+
+        let stats = crate::export_keys_sync(&src, vec!["a"], &bundle).unwrap();
+        assert_eq!(stats.entries, 1);
+        crate::import_sync(&dest, &bundle).unwrap();
+        assert_eq!(crate::read_sync(&dest, "a").unwrap(), b"aaa");
+        assert_eq!(crate::metadata_sync(&dest, "b").unwrap(), None);
+        assert_eq!(crate::exists_sync(&dest, &skipped), false);
+
+        assert!(crate::export_keys_sync(&src, vec!["missing"], &bundle).is_err());
+    }
+
+    #[test]
+    fn test_import_sync_corrupted() {
+        let tmp = tempfile::tempdir().unwrap();
+        let src = tmp.path().join("src");
+        let dest = tmp.path().join("dest");
+        let bundle = tmp.path().join("my.bundle");
+        let sri = crate::write_sync(&src, "a", b"my-data").unwrap();
+        crate::export_sync(&src, &bundle).unwrap();
+        let tampered = fs::read_to_string(&bundle)
+            .unwrap()
+            .replace("my-data", "my-dat4");
+        fs::write(&bundle, tampered).unwrap();
+
+        assert!(crate::import_sync(&dest, &bundle).is_err());
+        assert_eq!(crate::metadata_sync(&dest, "a").unwrap(), None);
+        assert!(!path::content_path(&dest, &sri).exists());
+    }
+
+    #[async_attributes::test]
+    async fn test_round_trip() {
+        let tmp = tempfile::tempdir().unwrap();
+        let src = tmp.path().join("src");
+        let dest = tmp.path().join("dest");
+        let bundle = tmp.path().join("my.bundle");
+        crate::write(&src, "a", b"my-data").await.unwrap();
+
+        crate::export(&src, &bundle).await.unwrap();
+        let stats = crate::import(&dest, &bundle).await.unwrap();
+        assert_eq!(stats.entries, 1);
+        assert_eq!(crate::read(&dest, "a").await.unwrap(), b"my-data");
+    }
+}
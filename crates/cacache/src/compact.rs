@@ -0,0 +1,146 @@
+//! Functions for compacting the cache index.
+use std::path::Path;
+
+use crate::errors::Result;
+use crate::index;
+
+/// Statistics about a completed `compact` run.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CompactStats {
+    /// Number of index buckets that were looked at.
+    pub buckets: usize,
+    /// Number of index buckets that were rewritten.
+    pub compacted_buckets: usize,
+    /// Number of superseded, deleted, or corrupted index lines removed.
+    pub removed_lines: usize,
+}
+
+/// Compacts the cache index. Index buckets are append-only, so keys that get
+/// written repeatedly leave a trail of stale lines behind them. This rewrites
+/// every bucket to hold only the latest entry for each key, dropping
+/// superseded entries, deleted keys, and lines that fail their hash check.
+///
+/// Buckets are replaced atomically, so concurrent readers see either the old
+/// bucket or the compacted one, never a half-written file. Content is left
+/// alone.
+///
+/// ## Example
+/// ```no_run
+/// use async_attributes;
+///
+/// #[async_attributes::main]
+/// async fn main() -> cacache::Result<()> {
+///     let stats = cacache::compact("./my-cache").await?;
+///     println!("Removed {} stale index lines", stats.removed_lines);
+///     Ok(())
+/// }
+/// ```
+pub async fn compact<P: AsRef<Path>>(cache: P) -> Result<CompactStats> {
+    let cache = cache.as_ref().to_owned();
+    smol::unblock!(compact_sync(&cache))
+}
+
+/// Compacts the cache index synchronously. See `compact` for details.
+///
+/// ## Example
+/// ```no_run
+/// fn main() -> cacache::Result<()> {
+///     let stats = cacache::compact_sync("./my-cache")?;
+///     println!("Removed {} stale index lines", stats.removed_lines);
+///     Ok(())
+/// }
+/// ```
+pub fn compact_sync<P: AsRef<Path>>(cache: P) -> Result<CompactStats> {
+    let cache = cache.as_ref();
+    let mut stats = CompactStats::default();
+    for bucket in index::bucket_paths(cache) {
+        let bucket = bucket?;
+        stats.buckets += 1;
+        let removed = index::compact_bucket(cache, &bucket)?;
+        if removed > 0 {
+            stats.compacted_buckets += 1;
+            stats.removed_lines += removed;
+        }
+    }
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+
+    use pretty_assertions::assert_eq;
+
+    use crate::index;
+
+    fn bucket_lines(dir: &std::path::Path) -> usize {
+        index::bucket_paths(dir)
+            .map(|bucket| fs::read_to_string(bucket.unwrap()).unwrap())
+            .map(|text| text.lines().filter(|line| !line.is_empty()).count())
+            .sum()
+    }
+
+    #[test]
+    fn test_compact_sync() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        crate::write_sync(&dir, "key", b"one").unwrap();
+        crate::write_sync(&dir, "key", b"two").unwrap();
+        crate::write_sync(&dir, "key", b"three").unwrap();
+        crate::write_sync(&dir, "other", b"other").unwrap();
+        assert_eq!(bucket_lines(&dir), 4);
+
+        let stats = crate::compact_sync(&dir).unwrap();
+        assert_eq!(stats.buckets, 2);
+        assert_eq!(stats.compacted_buckets, 1);
+        assert_eq!(stats.removed_lines, 2);
+        assert_eq!(bucket_lines(&dir), 2);
+        assert_eq!(crate::read_sync(&dir, "key").unwrap(), b"three");
+        assert_eq!(crate::read_sync(&dir, "other").unwrap(), b"other");
+
+        let stats = crate::compact_sync(&dir).unwrap();
+        assert_eq!(stats.removed_lines, 0);
+    }
+
+    #[test]
+    fn test_compact_sync_deleted() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        crate::write_sync(&dir, "key", b"my-data").unwrap();
+        crate::remove_sync(&dir, "key").unwrap();
+
+        let stats = crate::compact_sync(&dir).unwrap();
+        assert_eq!(stats.removed_lines, 2);
+        assert_eq!(index::bucket_paths(&dir).count(), 0);
+        assert_eq!(crate::metadata_sync(&dir, "key").unwrap(), None);
+    }
+
+    #[test]
+    fn test_compact_sync_bad_lines() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        crate::write_sync(&dir, "key", b"my-data").unwrap();
+        let bucket = index::bucket_paths(&dir).next().unwrap().unwrap();
+        let mut fd = OpenOptions::new().append(true).open(&bucket).unwrap();
+        fd.write_all(b"\ndeadbeef\t{\"key\":\"key\"}").unwrap();
+        drop(fd);
+
+        let stats = crate::compact_sync(&dir).unwrap();
+        assert_eq!(stats.removed_lines, 1);
+        assert_eq!(bucket_lines(&dir), 1);
+        assert_eq!(crate::read_sync(&dir, "key").unwrap(), b"my-data");
+    }
+
+    #[async_attributes::test]
+    async fn test_compact() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        crate::write(&dir, "key", b"one").await.unwrap();
+        crate::write(&dir, "key", b"two").await.unwrap();
+
+        let stats = crate::compact(&dir).await.unwrap();
+        assert_eq!(stats.removed_lines, 1);
+        assert_eq!(crate::read(&dir, "key").await.unwrap(), b"two");
+    }
+}
@@ -0,0 +1,79 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+use ssri::Integrity;
+
+use crate::content::{path, read};
+use crate::errors::{Internal, Result};
+use crate::get::LinkStrategy;
+
+pub fn link(cache: &Path, sri: &Integrity, to: &Path) -> Result<LinkStrategy> {
+    let cpath = path::content_path(cache, sri);
+    if !cpath.exists() && path::compressed_content_path(cache, sri).exists() {
+        // Compressed content can't be shared, so it gets unpacked instead.
+        remove_existing(to)?;
+        read::copy(cache, sri, to)?;
+        return Ok(LinkStrategy::Copy);
+    }
+
+    // Links share the cache's copy of the data as-is, so checking it would
+    // mean reading the whole thing for every link. Corruption there is
+    // `verify`'s job.
+    remove_existing(to)?;
+    if reflink(&cpath, to).is_ok() {
+        return Ok(LinkStrategy::Reflink);
+    }
+    if fs::hard_link(&cpath, to).is_ok() {
+        return Ok(LinkStrategy::Hardlink);
+    }
+    // A copy reads all the data anyway, so it's checked on the way.
+    if let Err(err) = read::copy(cache, sri, to) {
+        let _ = fs::remove_file(to);
+        return Err(err);
+    }
+    Ok(LinkStrategy::Copy)
+}
+
+pub async fn link_async(cache: &Path, sri: &Integrity, to: &Path) -> Result<LinkStrategy> {
+    let cache = cache.to_owned();
+    let sri = sri.to_owned();
+    let to = to.to_owned();
+    smol::unblock!(link(&cache, &sri, &to))
+}
+
+fn remove_existing(to: &Path) -> Result<()> {
+    match fs::remove_file(to) {
+        Err(err) if err.kind() != ErrorKind::NotFound => {
+            Err(err).with_context(|| format!("Failed to remove existing file at {:?}", to))?
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn reflink(from: &Path, to: &Path) -> std::io::Result<()> {
+    use std::fs::{File, OpenOptions};
+    use std::os::unix::io::AsRawFd;
+
+    // _IOW(0x94, 9, int), from linux/fs.h
+    const FICLONE: u64 = 0x4004_9409;
+
+    let src = File::open(from)?;
+    let dest = OpenOptions::new().write(true).create_new(true).open(to)?;
+    if unsafe { libc::ioctl(dest.as_raw_fd(), FICLONE as _, src.as_raw_fd()) } == -1 {
+        let err = std::io::Error::last_os_error();
+        drop(dest);
+        fs::remove_file(to)?;
+        return Err(err);
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn reflink(_from: &Path, _to: &Path) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        ErrorKind::Other,
+        "reflinks are not supported on this platform",
+    ))
+}
@@ -1,3 +1,4 @@
+pub mod link;
 pub mod path;
 pub mod read;
 pub mod rm;
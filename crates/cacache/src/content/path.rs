@@ -1,12 +1,17 @@
 use ssri::Integrity;
 use std::path::{Path, PathBuf};
 
-const CONTENT_VERSION: &str = "2";
+const CONTENT_VERSION: &str = "3";
 
 // Current format of content file path:
 //
 // sha512-BaSE64Hex= ->
-// ~/.my-cache/content-v2/sha512/ba/da/55deadbeefc0ffee
+// ~/.my-cache/content-v3/sha512/ba/da/55deadbeefc0ffee
+//
+// Content written with compression enabled lives next to it instead, with an
+// `.sz` extension:
+//
+// ~/.my-cache/content-v3/sha512/ba/da/55deadbeefc0ffee.sz
 //
 pub fn content_path(cache: &Path, sri: &Integrity) -> PathBuf {
     let mut path = PathBuf::new();
@@ -20,6 +25,53 @@ pub fn content_path(cache: &Path, sri: &Integrity) -> PathBuf {
     path
 }
 
+/// Path to the compressed copy of a piece of content.
+pub fn compressed_content_path(cache: &Path, sri: &Integrity) -> PathBuf {
+    content_path(cache, sri).with_extension("sz")
+}
+
+/// Path to whichever copy of a piece of content is actually on disk, if any.
+/// Uncompressed copies take precedence.
+pub fn stored_content_path(cache: &Path, sri: &Integrity) -> Option<PathBuf> {
+    let cpath = content_path(cache, sri);
+    if cpath.exists() {
+        return Some(cpath);
+    }
+    let zpath = cpath.with_extension("sz");
+    if zpath.exists() {
+        Some(zpath)
+    } else {
+        None
+    }
+}
+
+/// Single-hash `Integrity`s for every hash in `sri` other than the one its
+/// content is stored under. Content written with several algorithms is also
+/// made available under each of these.
+pub fn aliases(sri: &Integrity) -> impl Iterator<Item = Integrity> + '_ {
+    sri.hashes.iter().skip(1).map(|hash| Integrity {
+        hashes: vec![hash.clone()],
+    })
+}
+
+/// Every path content for `sri` might be stored at, whether compressed or
+/// not, including aliases.
+pub fn all_content_paths(cache: &Path, sri: &Integrity) -> Vec<PathBuf> {
+    std::iter::once(sri.clone())
+        .chain(aliases(sri))
+        .flat_map(|sri| {
+            let cpath = content_path(cache, &sri);
+            let zpath = cpath.with_extension("sz");
+            vec![cpath, zpath]
+        })
+        .collect()
+}
+
+/// Root directory under which all content files for `cache` are stored.
+pub fn content_dir(cache: &Path) -> PathBuf {
+    cache.join(format!("content-v{}", CONTENT_VERSION))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1,5 +1,5 @@
 use std::fs::{self, File};
-use std::io::{BufReader, Read};
+use std::io::{BufReader, ErrorKind, Read, Seek, SeekFrom};
 use std::path::{Path, PathBuf};
 
 use memmap::{Mmap, MmapMut};
@@ -36,64 +36,178 @@ impl std::io::Read for MaybeMmap {
     }
 }
 
+enum Content {
+    Raw(MaybeMmap),
+    Snappy(snap::read::FrameDecoder<MaybeMmap>),
+}
+
+impl std::io::Read for Content {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Content::Raw(fd) => fd.read(buf),
+            Content::Snappy(fd) => fd.read(buf),
+        }
+    }
+}
+
+enum Check {
+    /// Whole-content reads are checked as they go.
+    Streaming(IntegrityChecker),
+    /// Range reads only see part of the content, so checking them means
+    /// reading all of it again.
+    Range {
+        cache: PathBuf,
+        sri: Integrity,
+        remaining: u64,
+    },
+}
+
 pub struct Reader {
-    fd: snap::read::FrameDecoder<MaybeMmap>,
-    checker: IntegrityChecker,
+    fd: Content,
+    check: Check,
     expected_size: usize,
 }
 
 impl std::io::Read for Reader {
     #[inline]
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        let amt = self.fd.read(buf)?;
-        self.checker.input(&buf[..amt]);
-        Ok(amt)
+        match &mut self.check {
+            Check::Streaming(checker) => {
+                let amt = self.fd.read(buf)?;
+                checker.input(&buf[..amt]);
+                Ok(amt)
+            }
+            Check::Range { remaining, .. } => {
+                let max = std::cmp::min(buf.len() as u64, *remaining) as usize;
+                let amt = self.fd.read(&mut buf[..max])?;
+                *remaining -= amt as u64;
+                Ok(amt)
+            }
+        }
     }
 }
 
 impl Reader {
+    /// Number of bytes this reader will produce.
+    pub fn size(&self) -> usize {
+        self.expected_size
+    }
+
     pub fn check(self) -> Result<Algorithm> {
-        Ok(self.checker.result()?)
+        match self.check {
+            Check::Streaming(checker) => Ok(checker.result()?),
+            Check::Range { cache, sri, .. } => {
+                let mut full = Self::instantiate(cache, sri, None)?;
+                std::io::copy(&mut full, &mut std::io::sink()).to_internal()?;
+                full.check()
+            }
+        }
     }
 
-    fn instantiate(cpath: PathBuf, sri: Integrity) -> Result<Self> {
-        let mut reader = File::open(cpath).to_internal()?;
-        let mut bytes = [0u8; 8];
-        reader.read_exact(&mut bytes).to_internal()?;
-        let expected_size = u64::from_be_bytes(bytes) as usize;
+    /// Opens the content for `sri`. If `range` is given, as an offset and a
+    /// length, reads start at the offset and stop after the length, or at the
+    /// end of the content, whichever comes first.
+    fn instantiate(cache: PathBuf, sri: Integrity, range: Option<(u64, u64)>) -> Result<Self> {
+        let cpath = path::content_path(&cache, &sri);
+        let (mut reader, compressed) = match File::open(&cpath) {
+            Ok(reader) => (reader, false),
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                match File::open(path::compressed_content_path(&cache, &sri)) {
+                    Ok(reader) => (reader, true),
+                    // Report the uncompressed path, since that's the usual one.
+                    Err(_) => return Err(err).to_internal()?,
+                }
+            }
+            Err(err) => return Err(err).to_internal()?,
+        };
+        let disk_size = reader.metadata().to_internal()?.len() as usize;
+
+        // Compressed content starts with its uncompressed size.
+        let (expected_size, offset) = if compressed {
+            let mut bytes = [0u8; 8];
+            reader.read_exact(&mut bytes).to_internal()?;
+            (u64::from_be_bytes(bytes) as usize, 8)
+        } else {
+            (disk_size, 0)
+        };
+
+        let start = range.map(|(start, _)| start).unwrap_or(0);
+        if start > expected_size as u64 {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Range starts at {}, past the end of {} bytes of content",
+                    start, expected_size
+                ),
+            ))
+            .with_context(|| format!("Failed to open range of content for {}", sri))?;
+        }
+        // Uncompressed content can skip straight to the start of the range.
+        let offset = if compressed {
+            offset
+        } else {
+            reader.seek(SeekFrom::Start(start)).to_internal()?;
+            start as usize
+        };
 
         let fd = MaybeMmap {
-            mmap: if expected_size >= MIN_MMAP_READ_SIZE && expected_size <= MAX_MMAP_READ_SIZE {
-                unsafe { Mmap::map(&reader) }.ok().map(|mmap| (mmap, 8))
+            mmap: if (MIN_MMAP_READ_SIZE..=MAX_MMAP_READ_SIZE).contains(&disk_size) {
+                unsafe { Mmap::map(&reader) }
+                    .ok()
+                    .map(|mmap| (mmap, offset))
             } else {
                 None
             },
             file: BufReader::new(reader),
         };
 
+        let mut fd = if compressed {
+            Content::Snappy(snap::read::FrameDecoder::new(fd))
+        } else {
+            Content::Raw(fd)
+        };
+
+        let (check, expected_size) = match range {
+            None => (Check::Streaming(IntegrityChecker::new(sri)), expected_size),
+            Some((start, len)) => {
+                if compressed {
+                    // Snappy frames can't be seeked into, so decompress our
+                    // way up to the start of the range.
+                    std::io::copy(&mut (&mut fd).take(start), &mut std::io::sink())
+                        .to_internal()?;
+                }
+                let len = std::cmp::min(len, expected_size as u64 - start);
+                (
+                    Check::Range {
+                        cache,
+                        sri,
+                        remaining: len,
+                    },
+                    len as usize,
+                )
+            }
+        };
+
         Ok(Reader {
-            fd: snap::read::FrameDecoder::new(fd),
-            checker: IntegrityChecker::new(sri),
+            fd,
+            check,
             expected_size,
         })
     }
 
     pub fn new(cache: &Path, sri: &Integrity) -> Result<Self> {
-        let cpath = path::content_path(&cache, &sri);
-        let sri = sri.clone();
-        Self::instantiate(cpath, sri)
+        Self::instantiate(cache.to_owned(), sri.clone(), None)
     }
 
     pub async fn new_async(cache: &Path, sri: &Integrity) -> Result<Self> {
-        let cpath = path::content_path(&cache, &sri);
+        let cache = cache.to_owned();
         let sri = sri.clone();
-        smol::unblock!(Self::instantiate(cpath, sri))
+        smol::unblock!(Self::instantiate(cache, sri, None))
     }
 
     pub fn consume(cache: &Path, sri: &Integrity) -> Result<Vec<u8>> {
-        let cpath = path::content_path(&cache, &sri);
-        let sri = sri.clone();
-        let mut reader = Self::instantiate(cpath, sri)?;
+        let mut reader = Self::instantiate(cache.to_owned(), sri.clone(), None)?;
 
         let mut v = Vec::with_capacity(reader.expected_size);
         reader.read_to_end(&mut v).to_internal()?;
@@ -101,12 +215,27 @@ impl Reader {
         Ok(v)
     }
 
+    pub fn new_range(cache: &Path, sri: &Integrity, offset: u64, len: u64) -> Result<Self> {
+        Self::instantiate(cache.to_owned(), sri.clone(), Some((offset, len)))
+    }
+
+    pub async fn new_range_async(
+        cache: &Path,
+        sri: &Integrity,
+        offset: u64,
+        len: u64,
+    ) -> Result<Self> {
+        let cache = cache.to_owned();
+        let sri = sri.clone();
+        smol::unblock!(Self::instantiate(cache, sri, Some((offset, len))))
+    }
+
     #[inline]
     pub async fn consume_async(cache: &Path, sri: &Integrity) -> Result<Vec<u8>> {
-        let cpath = path::content_path(&cache, &sri);
+        let cache = cache.to_owned();
         let sri = sri.clone();
         async_std::task::spawn_blocking(|| {
-            let mut reader = Self::instantiate(cpath, sri)?;
+            let mut reader = Self::instantiate(cache, sri, None)?;
 
             let mut v = Vec::with_capacity(reader.expected_size);
             reader.read_to_end(&mut v).to_internal()?;
@@ -125,6 +254,19 @@ pub async fn open_async(cache: &Path, sri: Integrity) -> Result<Reader> {
     Reader::new_async(cache, &sri).await
 }
 
+pub fn open_range(cache: &Path, sri: Integrity, offset: u64, len: u64) -> Result<Reader> {
+    Reader::new_range(cache, &sri, offset, len)
+}
+
+pub async fn open_range_async(
+    cache: &Path,
+    sri: Integrity,
+    offset: u64,
+    len: u64,
+) -> Result<Reader> {
+    Reader::new_range_async(cache, &sri, offset, len).await
+}
+
 pub fn copy(cache: &Path, sri: &Integrity, to: &Path) -> Result<u64> {
     let mut reader = Reader::new(cache, sri)?;
     let mut target = fs::OpenOptions::new()
@@ -158,7 +300,7 @@ pub async fn copy_async<'a>(cache: &'a Path, sri: &'a Integrity, to: &'a Path) -
 }
 
 pub fn has_content(cache: &Path, sri: &Integrity) -> Option<Integrity> {
-    if path::content_path(&cache, &sri).exists() {
+    if path::stored_content_path(cache, sri).is_some() {
         Some(sri.clone())
     } else {
         None
@@ -166,9 +308,12 @@ pub fn has_content(cache: &Path, sri: &Integrity) -> Option<Integrity> {
 }
 
 pub async fn has_content_async(cache: &Path, sri: &Integrity) -> Option<Integrity> {
-    if async_std::fs::metadata(path::content_path(&cache, &sri))
+    if async_std::fs::metadata(path::content_path(cache, sri))
         .await
         .is_ok()
+        || async_std::fs::metadata(path::compressed_content_path(cache, sri))
+            .await
+            .is_ok()
     {
         Some(sri.clone())
     } else {
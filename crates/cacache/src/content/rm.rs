@@ -1,4 +1,5 @@
 use std::fs;
+use std::io::ErrorKind;
 use std::path::Path;
 
 use async_std::fs as afs;
@@ -6,15 +7,63 @@ use ssri::Integrity;
 
 use crate::content::path;
 use crate::errors::{Internal, Result};
+use crate::index;
+use crate::lock;
 
 pub fn rm(cache: &Path, sri: &Integrity) -> Result<()> {
-    fs::remove_file(path::content_path(&cache, &sri)).to_internal()?;
-    Ok(())
+    let _lock = lock::lock(cache, &path::content_path(cache, sri))?;
+    let cpath = path::content_path(cache, sri);
+    let removed = fs::remove_file(&cpath).is_ok();
+    let removed = fs::remove_file(cpath.with_extension("sz")).is_ok() || removed;
+    if !removed {
+        // Nothing was there. Report it against the usual path.
+        fs::remove_file(&cpath).to_internal()?;
+    }
+    rm_unused_aliases(cache, sri)
 }
 
 pub async fn rm_async(cache: &Path, sri: &Integrity) -> Result<()> {
-    afs::remove_file(path::content_path(&cache, &sri))
-        .await
-        .to_internal()?;
+    let _lock = lock::lock_async(cache, &path::content_path(cache, sri)).await?;
+    let cpath = path::content_path(cache, sri);
+    let removed = afs::remove_file(&cpath).await.is_ok();
+    let removed = afs::remove_file(cpath.with_extension("sz")).await.is_ok() || removed;
+    if !removed {
+        afs::remove_file(&cpath).await.to_internal()?;
+    }
+    let cache = cache.to_owned();
+    let sri = sri.clone();
+    smol::unblock!(rm_unused_aliases(&cache, &sri))
+}
+
+/// Removes the copies of `sri`'s content stored under its other hashes,
+/// except for ones that a live index entry reads its content from. Those
+/// belong to that entry now, e.g. when it was written with only one of the
+/// hashes.
+pub fn rm_unused_aliases(cache: &Path, sri: &Integrity) -> Result<()> {
+    for alias in path::aliases(sri) {
+        if alias_in_use(cache, &alias)? {
+            continue;
+        }
+        let cpath = path::content_path(cache, &alias);
+        for stored in [cpath.with_extension("sz"), cpath].iter() {
+            match fs::remove_file(stored) {
+                Err(err) if err.kind() != ErrorKind::NotFound => {
+                    return Err(err).with_context(|| {
+                        format!("Failed to remove content alias at {:?}", stored)
+                    })?;
+                }
+                _ => {}
+            }
+        }
+    }
     Ok(())
 }
+
+fn alias_in_use(cache: &Path, alias: &Integrity) -> Result<bool> {
+    for entry in index::ls_hash(cache, alias) {
+        if entry?.integrity.hashes.first() == alias.hashes.first() {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
@@ -1,5 +1,5 @@
-use std::fs::{DirBuilder, OpenOptions};
-use std::io::{Cursor, Seek, Write};
+use std::fs::{DirBuilder, File};
+use std::io::{Cursor, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
 
 use memmap::MmapMut;
@@ -8,6 +8,8 @@ use tempfile::NamedTempFile;
 
 use crate::content::path;
 use crate::errors::{Internal, Result};
+use crate::lock;
+use crate::put::Compression;
 
 pub const MAX_MMAP_WRITE_SIZE: usize = 1024 * 1024 * 10;
 #[cfg(not(target_os = "windows"))]
@@ -18,7 +20,7 @@ pub const MIN_MMAP_WRITE_SIZE: usize = 1;
 pub struct Writer {
     cache: PathBuf,
     builder: IntegrityOpts,
-    target: snap::write::FrameEncoder<MaybeCursed>,
+    target: Target,
     expected_size: Option<usize>,
     written: usize,
 }
@@ -29,18 +31,6 @@ struct MaybeCursed {
     tmpfile: Option<NamedTempFile>,
 }
 
-impl Seek for MaybeCursed {
-    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
-        if let Some(cursor) = self.cursor.as_mut() {
-            cursor.seek(pos)
-        } else if let Some(tmpfile) = self.tmpfile.as_mut() {
-            tmpfile.seek(pos)
-        } else {
-            unreachable!()
-        }
-    }
-}
-
 impl Write for MaybeCursed {
     #[inline]
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
@@ -63,9 +53,58 @@ impl Write for MaybeCursed {
     }
 }
 
+enum Target {
+    Raw(MaybeCursed),
+    // Compressed content is prefixed with an 8-byte, big-endian header
+    // holding its uncompressed size, which gets filled in on close.
+    Snappy(Box<snap::write::FrameEncoder<NamedTempFile>>),
+}
+
+impl Write for Target {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Target::Raw(target) => target.write(buf),
+            Target::Snappy(target) => target.write(buf),
+        }
+    }
+
+    #[inline]
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Target::Raw(target) => target.flush(),
+            Target::Snappy(target) => target.flush(),
+        }
+    }
+}
+
 impl Writer {
-    pub fn new(cache: PathBuf, algo: Algorithm, size: Option<usize>) -> Result<Self> {
+    pub fn new(
+        cache: PathBuf,
+        algos: &[Algorithm],
+        size: Option<usize>,
+        compression: Option<Compression>,
+    ) -> Result<Self> {
         let cache_path = cache;
+        let builder = if algos.is_empty() {
+            IntegrityOpts::new().algorithm(Algorithm::Sha256)
+        } else {
+            algos.iter().fold(IntegrityOpts::new(), |builder, algo| {
+                builder.algorithm(*algo)
+            })
+        };
+
+        if let Some(Compression::Snappy) = compression {
+            let mut tmpfile = tmpfile(&cache_path)?;
+            tmpfile.write_all(&[0u8; 8]).to_internal()?;
+            return Ok(Writer {
+                cache: cache_path,
+                builder,
+                target: Target::Snappy(Box::new(snap::write::FrameEncoder::new(tmpfile))),
+                expected_size: size,
+                written: 0,
+            });
+        }
 
         let cursor = size.and_then(|size| {
             if size >= MIN_MMAP_WRITE_SIZE && size <= MAX_MMAP_WRITE_SIZE {
@@ -76,26 +115,15 @@ impl Writer {
         });
 
         let tmpfile = if cursor.is_none() {
-            let mut tmp_path = cache_path.clone();
-            tmp_path.push("tmp");
-            DirBuilder::new()
-                .recursive(true)
-                .create(&tmp_path)
-                .to_internal()?;
-            Some(NamedTempFile::new_in(tmp_path).to_internal()?)
+            Some(tmpfile(&cache_path)?)
         } else {
             None
         };
 
-        let mut writer = MaybeCursed { tmpfile, cursor };
-
-        size.and_then(|size| writer.write(&size.to_be_bytes()).ok())
-            .or_else(|| writer.write(&0u64.to_be_bytes()).ok());
-
         Ok(Writer {
             cache: cache_path,
-            builder: IntegrityOpts::new().algorithm(algo),
-            target: snap::write::FrameEncoder::new(writer),
+            builder,
+            target: Target::Raw(MaybeCursed { tmpfile, cursor }),
             expected_size: size,
             written: 0,
         })
@@ -110,20 +138,35 @@ impl Writer {
             .create(cpath.parent().unwrap())
             .to_internal()?;
 
-        let mut maybe_mmap = self.target.into_inner().to_internal()?;
-
-        match self.expected_size {
-            None => {
-                maybe_mmap.seek(std::io::SeekFrom::Start(0)).to_internal()?;
-                let bytes = (self.written as u64).to_be_bytes();
+        let mut target = self.target;
+        target.flush().to_internal()?;
 
-                maybe_mmap.write(&bytes).to_internal()?;
-                maybe_mmap.flush().to_internal()?;
+        if let Some(size) = self.expected_size {
+            if size != self.written {
+                return Err(crate::errors::Error::SizeError(size, self.written));
             }
-            Some(size) => {
-                if size != self.written {
-                    return Err(crate::errors::Error::SizeError(size, self.written));
+        }
+
+        let _lock = lock::lock(&self.cache, &cpath)?;
+
+        let mut maybe_mmap = match target {
+            Target::Raw(maybe_mmap) => maybe_mmap,
+            Target::Snappy(encoder) => {
+                let mut tmpfile = encoder
+                    .into_inner()
+                    .map_err(|err| std::io::Error::new(err.error().kind(), err.error().to_string()))
+                    .to_internal()?;
+                tmpfile.seek(SeekFrom::Start(0)).to_internal()?;
+                tmpfile
+                    .write_all(&(self.written as u64).to_be_bytes())
+                    .to_internal()?;
+                tmpfile.flush().to_internal()?;
+                let zpath = path::compressed_content_path(&self.cache, &sri);
+                if tmpfile.persist(&zpath).to_internal().is_err() {
+                    std::fs::metadata(&zpath).to_internal()?;
                 }
+                link_aliases(&self.cache, &sri, &zpath)?;
+                return Ok(sri);
             }
         };
 
@@ -132,23 +175,26 @@ impl Writer {
                 // We might run into conflicts sometimes when persisting files.
                 // This is ok. We can deal. Let's just make sure the destination
                 // file actually exists, and we can move on.
-                std::fs::metadata(cpath).to_internal()?;
+                std::fs::metadata(&cpath).to_internal()?;
             }
         } else if let Some(cursor) = maybe_mmap.cursor.take() {
-            if persist_cursor(cursor, &cpath).is_err() {
+            if persist_cursor(&self.cache, cursor, &cpath).is_err() {
                 // Same as above
-                std::fs::metadata(cpath).to_internal()?;
+                std::fs::metadata(&cpath).to_internal()?;
             }
         }
+        link_aliases(&self.cache, &sri, &cpath)?;
         Ok(sri)
     }
 
     pub async fn new_async(
         cache: PathBuf,
-        algo: Algorithm,
+        algos: &[Algorithm],
         size: Option<usize>,
+        compression: Option<Compression>,
     ) -> Result<smol::Unblock<Self>> {
-        smol::unblock!(Writer::new(cache, algo, size)).map(smol::Unblock::new)
+        let algos = algos.to_vec();
+        smol::unblock!(Writer::new(cache, &algos, size, compression)).map(smol::Unblock::new)
     }
 
     pub async fn close_async(self) -> Result<Integrity> {
@@ -156,18 +202,59 @@ impl Writer {
     }
 }
 
-fn persist_cursor(cursor: Cursor<Vec<u8>>, cpath: impl AsRef<Path>) -> Result<()> {
-    let buf = cursor.into_inner();
-    let file = OpenOptions::new()
-        .create(true)
-        .write(true)
-        .read(true)
-        .open(cpath.as_ref())
+/// Makes content written with several hashes available under each of them,
+/// by linking the file stored under the strongest hash into place for the
+/// others.
+fn link_aliases(cache: &Path, sri: &Integrity, stored: &Path) -> Result<()> {
+    for alias in path::aliases(sri) {
+        let mut apath = path::content_path(cache, &alias);
+        if let Some(ext) = stored.extension() {
+            apath.set_extension(ext);
+        }
+        if apath.exists() {
+            continue;
+        }
+        DirBuilder::new()
+            .recursive(true)
+            // Safe unwrap. apath always has multiple segments
+            .create(apath.parent().unwrap())
+            .to_internal()?;
+        if std::fs::hard_link(stored, &apath).is_err()
+            && copy_into_place(cache, stored, &apath).is_err()
+        {
+            // Someone else may have beaten us to it.
+            std::fs::metadata(&apath).to_internal()?;
+        }
+    }
+    Ok(())
+}
+
+fn copy_into_place(cache: &Path, from: &Path, to: &Path) -> Result<()> {
+    let mut tmpfile = tmpfile(cache)?;
+    std::io::copy(&mut File::open(from).to_internal()?, &mut tmpfile).to_internal()?;
+    tmpfile.persist(to).to_internal()?;
+    Ok(())
+}
+
+fn tmpfile(cache: &Path) -> Result<NamedTempFile> {
+    let tmp_path = cache.join("tmp");
+    DirBuilder::new()
+        .recursive(true)
+        .create(&tmp_path)
         .to_internal()?;
-    file.set_len(buf.len() as u64).to_internal()?;
-    let mut mmap = unsafe { MmapMut::map_mut(&file).to_internal()? };
+    Ok(NamedTempFile::new_in(tmp_path).to_internal()?)
+}
+
+fn persist_cursor(cache: &Path, cursor: Cursor<Vec<u8>>, cpath: impl AsRef<Path>) -> Result<()> {
+    let buf = cursor.into_inner();
+    // Write to a temporary file first, so the content only becomes visible
+    // once it's complete.
+    let tmpfile = tmpfile(cache)?;
+    tmpfile.as_file().set_len(buf.len() as u64).to_internal()?;
+    let mut mmap = unsafe { MmapMut::map_mut(tmpfile.as_file()).to_internal()? };
     mmap.copy_from_slice(&buf);
     mmap.flush_async().to_internal()?;
+    tmpfile.persist(cpath.as_ref()).to_internal()?;
     Ok(())
 }
 
@@ -187,31 +274,18 @@ impl Write for Writer {
 mod tests {
     use super::*;
 
-    use std::io::Read;
-
     use pretty_assertions::assert_eq;
 
     #[test]
     fn basic_write() {
         let tmp = tempfile::tempdir().unwrap();
         let dir = tmp.path().to_owned();
-        let mut writer = Writer::new(dir.clone(), Algorithm::Sha256, None).unwrap();
+        let mut writer = Writer::new(dir.clone(), &[Algorithm::Sha256], None, None).unwrap();
         writer.write_all(b"hello world").unwrap();
         let sri = writer.close().unwrap();
         assert_eq!(sri.to_string(), Integrity::from(b"hello world").to_string());
 
-        let mut reader = std::fs::File::open(path::content_path(&dir, &sri)).unwrap();
-        let mut size_bytes = [0u8; 8];
-        reader.read_exact(&mut size_bytes).unwrap();
-        let size = u64::from_be_bytes(size_bytes) as usize;
-        let mut data = Vec::new();
-        snap::read::FrameDecoder::new(reader)
-            .read_to_end(&mut data)
-            .unwrap();
-
-        // we wrote the correct value.
-        assert_eq!(size, 11);
-
+        let data = std::fs::read(path::content_path(&dir, &sri)).unwrap();
         assert_eq!(data, b"hello world");
     }
 
@@ -220,22 +294,35 @@ mod tests {
         let tmp = tempfile::tempdir().unwrap();
         let dir = tmp.path().to_owned();
         let input = b"hello world, how are you";
-        let mut writer = Writer::new(dir.clone(), Algorithm::Sha256, Some(input.len())).unwrap();
+        let mut writer =
+            Writer::new(dir.clone(), &[Algorithm::Sha256], Some(input.len()), None).unwrap();
         writer.write_all(input).unwrap();
         let sri = writer.close().unwrap();
         assert_eq!(sri.to_string(), Integrity::from(input).to_string());
 
-        let mut reader = std::fs::File::open(path::content_path(&dir, &sri)).unwrap();
-        let mut size_bytes = [0u8; 8];
-        reader.read_exact(&mut size_bytes).unwrap();
-        let size = u64::from_be_bytes(size_bytes) as usize;
-        let mut data = Vec::new();
-        snap::read::FrameDecoder::new(reader)
-            .read_to_end(&mut data)
-            .unwrap();
+        let data = std::fs::read(path::content_path(&dir, &sri)).unwrap();
+        assert_eq!(data, input);
+    }
 
-        assert_eq!(data.len(), size);
+    #[test]
+    fn compressed_write() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let input = b"hello world".repeat(100);
+        let mut writer = Writer::new(
+            dir.clone(),
+            &[Algorithm::Sha256],
+            None,
+            Some(Compression::Snappy),
+        )
+        .unwrap();
+        writer.write_all(&input).unwrap();
+        let sri = writer.close().unwrap();
+        assert_eq!(sri.to_string(), Integrity::from(&input).to_string());
 
-        assert_eq!(data, input);
+        assert!(!path::content_path(&dir, &sri).exists());
+        let data = std::fs::read(path::compressed_content_path(&dir, &sri)).unwrap();
+        assert!(data.len() < input.len());
+        assert_eq!(&data[..8], &(input.len() as u64).to_be_bytes());
     }
 }
@@ -0,0 +1,296 @@
+//! Functions for keeping the cache within a size budget.
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use crate::content::{path, rm};
+use crate::errors::{Internal, Result};
+use crate::index;
+
+/// Statistics about a completed eviction run.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EvictStats {
+    /// Number of index entries that were evicted.
+    pub evicted_entries: usize,
+    /// Number of content files removed because no remaining entry pointed
+    /// to them.
+    pub evicted_content: usize,
+    /// Bytes on disk freed by removing content files.
+    pub reclaimed_size: u64,
+    /// Number of index entries left in the cache.
+    pub remaining_entries: usize,
+    /// Bytes on disk taken up by content referenced by the remaining entries.
+    pub remaining_size: u64,
+}
+
+/// Evicts least-recently-used entries until the content referenced by the
+/// index takes up no more than `max_size` bytes on disk.
+/// See `EvictOpts` for how entries are ordered.
+///
+/// ## Example
+/// ```no_run
+/// use async_attributes;
+///
+/// #[async_attributes::main]
+/// async fn main() -> cacache::Result<()> {
+///     let stats = cacache::evict("./my-cache", 1024 * 1024 * 1024).await?;
+///     println!("Evicted {} entries", stats.evicted_entries);
+///     Ok(())
+/// }
+/// ```
+pub async fn evict<P: AsRef<Path>>(cache: P, max_size: u64) -> Result<EvictStats> {
+    EvictOpts::new().max_size(max_size).evict(cache).await
+}
+
+/// Synchronously evicts least-recently-used entries until the content
+/// referenced by the index takes up no more than `max_size` bytes on disk.
+/// See `EvictOpts` for how entries are ordered.
+///
+/// ## Example
+/// ```no_run
+/// fn main() -> cacache::Result<()> {
+///     let stats = cacache::evict_sync("./my-cache", 1024 * 1024 * 1024)?;
+///     println!("Evicted {} entries", stats.evicted_entries);
+///     Ok(())
+/// }
+/// ```
+pub fn evict_sync<P: AsRef<Path>>(cache: P, max_size: u64) -> Result<EvictStats> {
+    EvictOpts::new().max_size(max_size).evict_sync(cache)
+}
+
+/// Builder for options and limits used when evicting entries from the cache.
+///
+/// Entries are evicted in least-recently-used order, going by the last time
+/// they were read by key, or the time they were written if they were never
+/// read. A content file is removed once no remaining entry points to it.
+///
+/// This order is approximate. Read times are only recorded once a minute,
+/// and they're kept per index bucket rather than per key, so keys that share
+/// a bucket all take the read time of whichever of them was read last, and
+/// may be evicted later than their own reads alone would warrant.
+/// Content that was never indexed (as with `write_hash`) is neither counted
+/// nor removed; use `verify` to clean that up.
+#[derive(Clone, Debug, Default)]
+pub struct EvictOpts {
+    pub(crate) max_size: Option<u64>,
+    pub(crate) max_entries: Option<usize>,
+}
+
+impl EvictOpts {
+    /// Creates a blank set of eviction options. With no limits set,
+    /// eviction won't remove anything.
+    pub fn new() -> EvictOpts {
+        Default::default()
+    }
+
+    /// Sets the maximum number of bytes that indexed content may take up on
+    /// disk.
+    pub fn max_size(mut self, max_size: u64) -> Self {
+        self.max_size = Some(max_size);
+        self
+    }
+
+    /// Sets the maximum number of index entries to keep.
+    pub fn max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = Some(max_entries);
+        self
+    }
+
+    /// Evicts entries from `cache` until it fits within the configured
+    /// limits.
+    pub async fn evict<P: AsRef<Path>>(self, cache: P) -> Result<EvictStats> {
+        let cache = cache.as_ref().to_owned();
+        smol::unblock!(self.evict_sync(&cache))
+    }
+
+    /// Synchronously evicts entries from `cache` until it fits within the
+    /// configured limits.
+    pub fn evict_sync<P: AsRef<Path>>(self, cache: P) -> Result<EvictStats> {
+        let cache = cache.as_ref();
+        let mut entries = Vec::new();
+        for bucket in index::bucket_paths(cache) {
+            entries.extend(index::bucket_metadata(&bucket?)?);
+        }
+
+        // Reference counts and on-disk sizes for every piece of indexed
+        // content, so content shared between keys is only removed once the
+        // last of them is gone.
+        let mut refs: HashMap<PathBuf, (usize, u64)> = HashMap::new();
+        for entry in entries.iter() {
+            let cpath = path::content_path(cache, &entry.integrity);
+            if let Some((count, _)) = refs.get_mut(&cpath) {
+                *count += 1;
+            } else {
+                let size = path::stored_content_path(cache, &entry.integrity)
+                    .and_then(|stored| fs::metadata(stored).ok())
+                    .map(|m| m.len())
+                    .unwrap_or(0);
+                refs.insert(cpath, (1, size));
+            }
+        }
+        let mut size: u64 = refs.values().map(|(_, size)| size).sum();
+        let mut count = entries.len();
+
+        entries.sort_by_key(|entry| (entry.atime, entry.time));
+        let mut stats = EvictStats::default();
+        for entry in entries {
+            if !self.over_budget(size, count) {
+                break;
+            }
+            index::delete(cache, &entry.key)?;
+            count -= 1;
+            stats.evicted_entries += 1;
+            let cpath = path::content_path(cache, &entry.integrity);
+            if let Some((refcount, content_size)) = refs.get_mut(&cpath) {
+                *refcount -= 1;
+                if *refcount == 0 {
+                    for stored in [cpath.with_extension("sz"), cpath.clone()].iter() {
+                        match fs::remove_file(stored) {
+                            Err(err) if err.kind() != ErrorKind::NotFound => {
+                                return Err(err).with_context(|| {
+                                    format!("Failed to remove evicted content at {:?}", stored)
+                                })?;
+                            }
+                            _ => {}
+                        }
+                    }
+                    rm::rm_unused_aliases(cache, &entry.integrity)?;
+                    size -= *content_size;
+                    stats.evicted_content += 1;
+                    stats.reclaimed_size += *content_size;
+                }
+            }
+        }
+        stats.remaining_entries = count;
+        stats.remaining_size = size;
+        Ok(stats)
+    }
+
+    fn over_budget(&self, size: u64, count: usize) -> bool {
+        matches!(self.max_size, Some(max) if size > max)
+            || matches!(self.max_entries, Some(max) if count > max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::Write;
+    use std::path::Path;
+
+    use pretty_assertions::assert_eq;
+    use ssri::Integrity;
+
+    use crate::content::path;
+    use crate::WriteOpts;
+
+    fn write_at(dir: &Path, key: &str, data: &[u8], time: u128) -> Integrity {
+        let mut fd = WriteOpts::new().time(time).open_sync(dir, key).unwrap();
+        fd.write_all(data).unwrap();
+        fd.commit().unwrap()
+    }
+
+    fn disk_size(dir: &Path, sri: &Integrity) -> u64 {
+        fs::metadata(path::content_path(dir, sri)).unwrap().len()
+    }
+
+    #[test]
+    fn test_evict_sync_lru() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let a = write_at(&dir, "a", b"aaaaaaaa", 1);
+        let b = write_at(&dir, "b", b"bbbbbbbb", 2);
+        let c = write_at(&dir, "c", b"cccccccc", 3);
+        // Reading `a` makes it the most recently used entry.
+        crate::read_sync(&dir, "a").unwrap();
+        let budget = disk_size(&dir, &a);
+
+        let stats = crate::evict_sync(&dir, budget).unwrap();
+        assert_eq!(stats.evicted_entries, 2);
+        assert_eq!(stats.evicted_content, 2);
+        assert_eq!(stats.remaining_entries, 1);
+        assert_eq!(stats.remaining_size, budget);
+        assert_eq!(crate::read_sync(&dir, "a").unwrap(), b"aaaaaaaa");
+        assert_eq!(crate::metadata_sync(&dir, "b").unwrap(), None);
+        assert_eq!(crate::exists_sync(&dir, &b), false);
+        assert_eq!(crate::exists_sync(&dir, &c), false);
+    }
+
+    #[test]
+    fn test_evict_sync_shared_content() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri = write_at(&dir, "old", b"shared", 1);
+        write_at(&dir, "new", b"shared", 2);
+
+        let stats = crate::EvictOpts::new()
+            .max_entries(1)
+            .evict_sync(&dir)
+            .unwrap();
+        assert_eq!(stats.evicted_entries, 1);
+        assert_eq!(stats.evicted_content, 0);
+        assert_eq!(crate::metadata_sync(&dir, "old").unwrap(), None);
+        assert_eq!(crate::exists_sync(&dir, &sri), true);
+        assert_eq!(crate::read_sync(&dir, "new").unwrap(), b"shared");
+    }
+
+    #[test]
+    fn test_evict_sync_shared_alias() {
+        use ssri::Algorithm;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let mut fd = WriteOpts::new()
+            .time(1)
+            .algorithm(Algorithm::Sha512)
+            .algorithm(Algorithm::Sha1)
+            .open_sync(&dir, "both")
+            .unwrap();
+        fd.write_all(b"shared").unwrap();
+        fd.commit().unwrap();
+        let mut fd = WriteOpts::new()
+            .time(2)
+            .algorithm(Algorithm::Sha1)
+            .open_sync(&dir, "sha1")
+            .unwrap();
+        fd.write_all(b"shared").unwrap();
+        fd.commit().unwrap();
+
+        let stats = crate::EvictOpts::new()
+            .max_entries(1)
+            .evict_sync(&dir)
+            .unwrap();
+        assert_eq!(stats.evicted_entries, 1);
+        assert_eq!(crate::metadata_sync(&dir, "both").unwrap(), None);
+        assert_eq!(crate::read_sync(&dir, "sha1").unwrap(), b"shared");
+    }
+
+    #[test]
+    fn test_evict_sync_within_budget() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        crate::write_sync(&dir, "key", b"my-data").unwrap();
+
+        let stats = crate::evict_sync(&dir, 1024 * 1024).unwrap();
+        assert_eq!(stats.evicted_entries, 0);
+        assert_eq!(stats.remaining_entries, 1);
+    }
+
+    #[async_attributes::test]
+    async fn test_evict() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        write_at(&dir, "old", b"old-data", 1);
+        crate::write(&dir, "new", b"new-data").await.unwrap();
+
+        let stats = crate::EvictOpts::new()
+            .max_entries(1)
+            .evict(&dir)
+            .await
+            .unwrap();
+        assert_eq!(stats.evicted_entries, 1);
+        assert_eq!(crate::metadata(&dir, "old").await.unwrap(), None);
+        assert_eq!(crate::read(&dir, "new").await.unwrap(), b"new-data");
+    }
+}
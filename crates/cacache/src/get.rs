@@ -7,7 +7,7 @@ use futures::prelude::*;
 
 use ssri::{Algorithm, Integrity};
 
-use crate::content::read;
+use crate::content::{link, read};
 use crate::errors::{Error, Result};
 use crate::index::{self, Metadata};
 
@@ -84,6 +84,7 @@ impl Reader {
         K: AsRef<str>,
     {
         if let Some(entry) = index::find_async(cache.as_ref(), key.as_ref()).await? {
+            touch_async(cache.as_ref(), &entry).await;
             Reader::open_hash(cache, entry.integrity).await
         } else {
             Err(Error::EntryNotFound(
@@ -119,6 +120,45 @@ impl Reader {
             reader: smol::Unblock::new(read::open_async(cache.as_ref(), sri).await?),
         })
     }
+
+    /// Opens a new file handle into the cache that reads `len` bytes of
+    /// content starting at `offset`, without reading anything before them.
+    /// Reading stops early if the range runs past the end of the content, and
+    /// opening fails if `offset` itself is past the end.
+    ///
+    /// Integrity can't be verified from part of the content, so `.check()`
+    /// on a range handle reads and verifies the content in full.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// use async_std::prelude::*;
+    /// use async_attributes;
+    ///
+    /// #[async_attributes::main]
+    /// async fn main() -> cacache::Result<()> {
+    ///     let sri = cacache::write("./my-cache", "key", b"hello world").await?;
+    ///     let mut fd = cacache::Reader::open_hash_range("./my-cache", sri, 6, 5).await?;
+    ///     let mut str = String::new();
+    ///     fd.read_to_string(&mut str).await.expect("Failed to read to string");
+    ///     assert_eq!(str, "world");
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn open_hash_range<P>(
+        cache: P,
+        sri: Integrity,
+        offset: u64,
+        len: u64,
+    ) -> Result<Reader>
+    where
+        P: AsRef<Path>,
+    {
+        Ok(Reader {
+            reader: smol::Unblock::new(
+                read::open_range_async(cache.as_ref(), sri, offset, len).await?,
+            ),
+        })
+    }
 }
 
 /// Reads the entire contents of a cache file into a bytes vector, looking the
@@ -141,6 +181,7 @@ where
     K: AsRef<str>,
 {
     if let Some(entry) = index::find_async(cache.as_ref(), key.as_ref()).await? {
+        touch_async(cache.as_ref(), &entry).await;
         read_hash(cache, &entry.integrity).await
     } else {
         Err(Error::EntryNotFound(
@@ -193,6 +234,7 @@ where
     Q: AsRef<Path>,
 {
     if let Some(entry) = index::find_async(cache.as_ref(), key.as_ref()).await? {
+        touch_async(cache.as_ref(), &entry).await;
         copy_hash(cache, &entry.integrity, to).await
     } else {
         Err(Error::EntryNotFound(
@@ -225,6 +267,82 @@ where
     read::copy_async(cache.as_ref(), sri, to.as_ref()).await
 }
 
+/// How cached data was put in place by `link` and its variants.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinkStrategy {
+    /// A copy-on-write clone that shares the cached data's blocks on disk
+    /// until either side is modified. Only available on filesystems that
+    /// support it, such as btrfs and xfs.
+    Reflink,
+    /// A hard link to the cached file itself. The linked file and the cache
+    /// share the same data, so writing to it will corrupt the cache. Treat
+    /// it as read-only.
+    Hardlink,
+    /// A plain byte-for-byte copy.
+    Copy,
+}
+
+/// Puts cache data at a specified location, looking it up by key. Tries a
+/// copy-on-write reflink first, then a hard link, and falls back to a plain
+/// copy if neither is possible. Returns the strategy that was used.
+///
+/// Only copied data is verified, since links share the cache's own file.
+/// Use `verify` to catch corruption in the cache. Any existing file at `to`
+/// is replaced. Content that was written with compression is always copied
+/// out.
+///
+/// ## Example
+/// ```no_run
+/// use async_std::prelude::*;
+/// use async_attributes;
+///
+/// #[async_attributes::main]
+/// async fn main() -> cacache::Result<()> {
+///     let strategy = cacache::link("./my-cache", "my-key", "./data.txt").await?;
+///     println!("Linked with {:?}", strategy);
+///     Ok(())
+/// }
+/// ```
+pub async fn link<P, K, Q>(cache: P, key: K, to: Q) -> Result<LinkStrategy>
+where
+    P: AsRef<Path>,
+    K: AsRef<str>,
+    Q: AsRef<Path>,
+{
+    if let Some(entry) = index::find_async(cache.as_ref(), key.as_ref()).await? {
+        touch_async(cache.as_ref(), &entry).await;
+        link_hash(cache, &entry.integrity, to).await
+    } else {
+        Err(Error::EntryNotFound(
+            cache.as_ref().to_path_buf(),
+            key.as_ref().into(),
+        ))
+    }
+}
+
+/// Puts cache data at a specified location, looking it up by its content
+/// address. See `link` for details.
+///
+/// ## Example
+/// ```no_run
+/// use async_std::prelude::*;
+/// use async_attributes;
+///
+/// #[async_attributes::main]
+/// async fn main() -> cacache::Result<()> {
+///     let sri = cacache::write("./my-cache", "my-key", b"hello world").await?;
+///     cacache::link_hash("./my-cache", &sri, "./data.txt").await?;
+///     Ok(())
+/// }
+/// ```
+pub async fn link_hash<P, Q>(cache: P, sri: &Integrity, to: Q) -> Result<LinkStrategy>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    link::link_async(cache.as_ref(), sri, to.as_ref()).await
+}
+
 /// Gets the metadata entry for a certain key.
 ///
 /// Note that the existence of a metadata entry is not a guarantee that the
@@ -245,6 +363,114 @@ pub async fn exists<P: AsRef<Path>>(cache: P, sri: &Integrity) -> bool {
         .is_some()
 }
 
+/// Result of a freshness-aware lookup, as returned by `read_fresh`,
+/// `metadata_fresh`, and their variants.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Freshness<T> {
+    /// The entry exists, and either has no TTL or hasn't outlived it yet.
+    Fresh(T),
+    /// The entry exists, but has outlived its TTL.
+    Stale(T),
+    /// There's no entry for the key.
+    Missing,
+}
+
+impl<T> Freshness<T> {
+    /// Returns the value only if it's fresh.
+    pub fn fresh(self) -> Option<T> {
+        match self {
+            Freshness::Fresh(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns the value whether it's fresh or stale.
+    pub fn value(self) -> Option<T> {
+        match self {
+            Freshness::Fresh(value) | Freshness::Stale(value) => Some(value),
+            Freshness::Missing => None,
+        }
+    }
+
+    fn of(entry: &Metadata, value: T) -> Self {
+        if entry.is_stale() {
+            Freshness::Stale(value)
+        } else {
+            Freshness::Fresh(value)
+        }
+    }
+}
+
+/// Reads the entire contents of a cache file into a bytes vector, looking
+/// the data up by key, and reports whether the entry is still fresh. Entries
+/// without a TTL (see `WriteOpts::ttl`) are always fresh. Stale data is
+/// still returned, so callers can fall back to it when nothing better is
+/// available. A missing entry isn't an error, but missing content is.
+///
+/// ## Example
+/// ```no_run
+/// use async_attributes;
+///
+/// #[async_attributes::main]
+/// async fn main() -> cacache::Result<()> {
+///     match cacache::read_fresh("./my-cache", "my-key").await? {
+///         cacache::Freshness::Fresh(data) => println!("fresh: {:?}", data),
+///         cacache::Freshness::Stale(data) => println!("stale: {:?}", data),
+///         cacache::Freshness::Missing => println!("not cached"),
+///     }
+///     Ok(())
+/// }
+/// ```
+pub async fn read_fresh<P, K>(cache: P, key: K) -> Result<Freshness<Vec<u8>>>
+where
+    P: AsRef<Path>,
+    K: AsRef<str>,
+{
+    if let Some(entry) = index::find_async(cache.as_ref(), key.as_ref()).await? {
+        touch_async(cache.as_ref(), &entry).await;
+        let data = read_hash(cache, &entry.integrity).await?;
+        Ok(Freshness::of(&entry, data))
+    } else {
+        Ok(Freshness::Missing)
+    }
+}
+
+/// Gets the metadata entry for a certain key, and reports whether it's still
+/// fresh. See `read_fresh` for details.
+///
+/// ## Example
+/// ```no_run
+/// use async_attributes;
+///
+/// #[async_attributes::main]
+/// async fn main() -> cacache::Result<()> {
+///     match cacache::metadata_fresh("./my-cache", "my-key").await? {
+///         cacache::Freshness::Fresh(entry) => println!("fresh: {}", entry.integrity),
+///         cacache::Freshness::Stale(entry) => println!("stale: {}", entry.integrity),
+///         cacache::Freshness::Missing => println!("not cached"),
+///     }
+///     Ok(())
+/// }
+/// ```
+pub async fn metadata_fresh<P, K>(cache: P, key: K) -> Result<Freshness<Metadata>>
+where
+    P: AsRef<Path>,
+    K: AsRef<str>,
+{
+    Ok(
+        match index::find_async(cache.as_ref(), key.as_ref()).await? {
+            Some(entry) => Freshness::of(&entry, entry.clone()),
+            None => Freshness::Missing,
+        },
+    )
+}
+
+// Recording access times is best-effort: a cache we can't write to should
+// still be readable.
+async fn touch_async(cache: &Path, entry: &Metadata) {
+    let _ = index::touch_async(cache, entry).await;
+}
+
 // ---------------
 // Synchronous API
 // ---------------
@@ -308,6 +534,7 @@ impl SyncReader {
         K: AsRef<str>,
     {
         if let Some(entry) = index::find(cache.as_ref(), key.as_ref())? {
+            touch(cache.as_ref(), &entry);
             SyncReader::open_hash(cache, entry.integrity)
         } else {
             Err(Error::EntryNotFound(
@@ -341,6 +568,36 @@ impl SyncReader {
             reader: read::open(cache.as_ref(), sri)?,
         })
     }
+
+    /// Opens a new synchronous file handle into the cache that reads `len`
+    /// bytes of content starting at `offset`, without reading anything before
+    /// them. Reading stops early if the range runs past the end of the
+    /// content, and opening fails if `offset` itself is past the end.
+    ///
+    /// Integrity can't be verified from part of the content, so `.check()`
+    /// on a range handle reads and verifies the content in full.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// use std::io::Read;
+    ///
+    /// fn main() -> cacache::Result<()> {
+    ///     let sri = cacache::write_sync("./my-cache", "key", b"hello world")?;
+    ///     let mut fd = cacache::SyncReader::open_hash_range("./my-cache", sri, 6, 5)?;
+    ///     let mut str = String::new();
+    ///     fd.read_to_string(&mut str).expect("Failed to read to string");
+    ///     assert_eq!(str, "world");
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn open_hash_range<P>(cache: P, sri: Integrity, offset: u64, len: u64) -> Result<SyncReader>
+    where
+        P: AsRef<Path>,
+    {
+        Ok(SyncReader {
+            reader: read::open_range(cache.as_ref(), sri, offset, len)?,
+        })
+    }
 }
 
 /// Reads the entire contents of a cache file synchronously into a bytes
@@ -361,6 +618,7 @@ where
     K: AsRef<str>,
 {
     if let Some(entry) = index::find(cache.as_ref(), key.as_ref())? {
+        touch(cache.as_ref(), &entry);
         read_hash_sync(cache, &entry.integrity)
     } else {
         Err(Error::EntryNotFound(
@@ -409,6 +667,7 @@ where
     Q: AsRef<Path>,
 {
     if let Some(entry) = index::find(cache.as_ref(), key.as_ref())? {
+        touch(cache.as_ref(), &entry);
         copy_hash_sync(cache, &entry.integrity, to)
     } else {
         Err(Error::EntryNotFound(
@@ -439,6 +698,63 @@ where
     read::copy(cache.as_ref(), sri, to.as_ref())
 }
 
+/// Synchronously puts cache data at a specified location, looking it up by
+/// key. Tries a copy-on-write reflink first, then a hard link, and falls
+/// back to a plain copy if neither is possible. Returns the strategy that was
+/// used.
+///
+/// Only copied data is verified, since links share the cache's own file.
+/// Use `verify_sync` to catch corruption in the cache. Any existing file at
+/// `to` is replaced.
+///
+/// ## Example
+/// ```no_run
+/// use std::io::Read;
+///
+/// fn main() -> cacache::Result<()> {
+///     let strategy = cacache::link_sync("./my-cache", "my-key", "./my-hello.txt")?;
+///     println!("Linked with {:?}", strategy);
+///     Ok(())
+/// }
+/// ```
+pub fn link_sync<P, K, Q>(cache: P, key: K, to: Q) -> Result<LinkStrategy>
+where
+    P: AsRef<Path>,
+    K: AsRef<str>,
+    Q: AsRef<Path>,
+{
+    if let Some(entry) = index::find(cache.as_ref(), key.as_ref())? {
+        touch(cache.as_ref(), &entry);
+        link_hash_sync(cache, &entry.integrity, to)
+    } else {
+        Err(Error::EntryNotFound(
+            cache.as_ref().to_path_buf(),
+            key.as_ref().into(),
+        ))
+    }
+}
+
+/// Synchronously puts cache data at a specified location, looking it up by
+/// its content address. See `link_sync` for details.
+///
+/// ## Example
+/// ```no_run
+/// use std::io::Read;
+///
+/// fn main() -> cacache::Result<()> {
+///     let sri = cacache::write_sync("./my-cache", "my-key", b"hello")?;
+///     cacache::link_hash_sync("./my-cache", &sri, "./my-hello.txt")?;
+///     Ok(())
+/// }
+/// ```
+pub fn link_hash_sync<P, Q>(cache: P, sri: &Integrity, to: Q) -> Result<LinkStrategy>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    link::link(cache.as_ref(), sri, to.as_ref())
+}
+
 /// Gets metadata for a certain key.
 ///
 /// Note that the existence of a metadata entry is not a guarantee that the
@@ -457,6 +773,56 @@ pub fn exists_sync<P: AsRef<Path>>(cache: P, sri: &Integrity) -> bool {
     read::has_content(cache.as_ref(), &sri).is_some()
 }
 
+/// Synchronously reads the entire contents of a cache file into a bytes
+/// vector, looking the data up by key, and reports whether the entry is
+/// still fresh. See `read_fresh` for details.
+///
+/// ## Example
+/// ```no_run
+/// fn main() -> cacache::Result<()> {
+///     let data = cacache::read_fresh_sync("./my-cache", "my-key")?.fresh();
+///     Ok(())
+/// }
+/// ```
+pub fn read_fresh_sync<P, K>(cache: P, key: K) -> Result<Freshness<Vec<u8>>>
+where
+    P: AsRef<Path>,
+    K: AsRef<str>,
+{
+    if let Some(entry) = index::find(cache.as_ref(), key.as_ref())? {
+        touch(cache.as_ref(), &entry);
+        let data = read_hash_sync(cache, &entry.integrity)?;
+        Ok(Freshness::of(&entry, data))
+    } else {
+        Ok(Freshness::Missing)
+    }
+}
+
+/// Synchronously gets the metadata entry for a certain key, and reports
+/// whether it's still fresh. See `read_fresh` for details.
+///
+/// ## Example
+/// ```no_run
+/// fn main() -> cacache::Result<()> {
+///     let entry = cacache::metadata_fresh_sync("./my-cache", "my-key")?.fresh();
+///     Ok(())
+/// }
+/// ```
+pub fn metadata_fresh_sync<P, K>(cache: P, key: K) -> Result<Freshness<Metadata>>
+where
+    P: AsRef<Path>,
+    K: AsRef<str>,
+{
+    Ok(match index::find(cache.as_ref(), key.as_ref())? {
+        Some(entry) => Freshness::of(&entry, entry.clone()),
+        None => Freshness::Missing,
+    })
+}
+
+fn touch(cache: &Path, entry: &Metadata) {
+    let _ = index::touch(cache, entry);
+}
+
 #[cfg(test)]
 mod tests {
     use std::fs;
@@ -491,6 +857,75 @@ mod tests {
         assert_eq!(str, String::from("hello world"));
     }
 
+    #[async_attributes::test]
+    async fn test_open_hash_range() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri = crate::write(&dir, "my-key", b"hello world").await.unwrap();
+
+        let mut handle = crate::Reader::open_hash_range(&dir, sri, 6, 3)
+            .await
+            .unwrap();
+        let mut str = String::new();
+        handle.read_to_string(&mut str).await.unwrap();
+        handle.check().await.unwrap();
+        assert_eq!(str, String::from("wor"));
+    }
+
+    #[test]
+    fn test_open_hash_range_sync() {
+        use std::io::{Read, Write};
+
+        fn read_range(
+            dir: &std::path::Path,
+            sri: &ssri::Integrity,
+            offset: u64,
+            len: u64,
+        ) -> Vec<u8> {
+            let mut handle =
+                crate::SyncReader::open_hash_range(dir, sri.clone(), offset, len).unwrap();
+            let mut read = Vec::new();
+            handle.read_to_end(&mut read).unwrap();
+            handle.check().unwrap();
+            read
+        }
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        // Big enough to be memory-mapped.
+        let data: Vec<u8> = (0..2 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+        let big = crate::write_hash_sync(dir, &data).unwrap();
+        let small = crate::write_hash_sync(dir, &data[..1000]).unwrap();
+        let mut fd = crate::WriteOpts::new()
+            .compression(crate::Compression::Snappy)
+            .open_hash_sync(dir)
+            .unwrap();
+        fd.write_all(&data[..300_000]).unwrap();
+        let compressed = fd.commit().unwrap();
+
+        assert_eq!(
+            read_range(dir, &big, 1_500_000, 100),
+            &data[1_500_000..1_500_100]
+        );
+        assert_eq!(read_range(dir, &small, 10, 20), &data[10..30]);
+        assert_eq!(
+            read_range(dir, &compressed, 200_000, 5),
+            &data[200_000..200_005]
+        );
+        // Ranges running past the end stop at the end.
+        assert_eq!(read_range(dir, &small, 990, 100), &data[990..1000]);
+        assert_eq!(read_range(dir, &small, 1000, 100), Vec::<u8>::new());
+        assert!(crate::SyncReader::open_hash_range(dir, small.clone(), 1001, 1).is_err());
+
+        // Checks still cover the whole content.
+        fs::write(crate::content::path::content_path(dir, &small), b"garbage").unwrap();
+        let mut handle = crate::SyncReader::open_hash_range(dir, small, 0, 3).unwrap();
+        let mut read = Vec::new();
+        handle.read_to_end(&mut read).unwrap();
+        assert_eq!(read, b"gar");
+        assert!(handle.check().is_err());
+    }
+
     #[test]
     fn test_open_sync() {
         use std::io::prelude::*;
@@ -606,4 +1041,190 @@ mod tests {
         let data = fs::read(&dest).unwrap();
         assert_eq!(data, b"hello world");
     }
+
+    #[async_attributes::test]
+    async fn test_link() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let dest = dir.join("data");
+        crate::write(dir, "my-key", b"hello world").await.unwrap();
+
+        crate::link(dir, "my-key", &dest).await.unwrap();
+        let data = afs::read(&dest).await.unwrap();
+        assert_eq!(data, b"hello world");
+    }
+
+    #[async_attributes::test]
+    async fn test_link_hash() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let dest = dir.join("data");
+        let sri = crate::write(dir, "my-key", b"hello world").await.unwrap();
+
+        crate::link_hash(dir, &sri, &dest).await.unwrap();
+        let data = afs::read(&dest).await.unwrap();
+        assert_eq!(data, b"hello world");
+    }
+
+    #[test]
+    fn test_link_sync() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let dest = dir.join("data");
+        crate::write_sync(dir, "my-key", b"hello world").unwrap();
+        fs::write(&dest, b"existing data").unwrap();
+
+        crate::link_sync(dir, "my-key", &dest).unwrap();
+        let data = fs::read(&dest).unwrap();
+        assert_eq!(data, b"hello world");
+    }
+
+    #[test]
+    fn test_link_hash_sync_missing() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let dest = dir.join("data");
+        let sri = crate::write_sync(dir, "my-key", b"hello world").unwrap();
+        fs::remove_file(crate::content::path::content_path(dir, &sri)).unwrap();
+
+        assert!(crate::link_hash_sync(dir, &sri, &dest).is_err());
+        assert!(!dest.exists());
+    }
+
+    #[async_attributes::test]
+    async fn test_compressed_round_trip() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let data = b"hello world".repeat(100);
+        let mut fd = crate::WriteOpts::new()
+            .compression(crate::Compression::Snappy)
+            .open(dir, "my-key")
+            .await
+            .unwrap();
+        fd.write_all(&data).await.unwrap();
+        let sri = fd.commit().await.unwrap();
+        assert_eq!(sri, ssri::Integrity::from(&data));
+
+        assert_eq!(crate::read(dir, "my-key").await.unwrap(), data);
+        assert_eq!(crate::read_hash(dir, &sri).await.unwrap(), data);
+        let mut handle = crate::Reader::open_hash(dir, sri.clone()).await.unwrap();
+        let mut read = Vec::new();
+        handle.read_to_end(&mut read).await.unwrap();
+        handle.check().await.unwrap();
+        assert_eq!(read, data);
+
+        let dest = dir.join("data");
+        crate::copy(dir, "my-key", &dest).await.unwrap();
+        assert_eq!(afs::read(&dest).await.unwrap(), data);
+        assert_eq!(
+            crate::link_hash(dir, &sri, &dest).await.unwrap(),
+            crate::LinkStrategy::Copy
+        );
+        assert_eq!(afs::read(&dest).await.unwrap(), data);
+
+        assert!(crate::exists(dir, &sri).await);
+        crate::remove_hash(dir, &sri).await.unwrap();
+        assert!(!crate::exists(dir, &sri).await);
+    }
+
+    #[test]
+    fn test_compressed_round_trip_sync() {
+        use std::io::{Read, Write};
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let data = b"hello world".repeat(100);
+        let mut fd = crate::WriteOpts::new()
+            .compression(crate::Compression::Snappy)
+            .open_sync(dir, "my-key")
+            .unwrap();
+        fd.write_all(&data).unwrap();
+        let sri = fd.commit().unwrap();
+
+        let stored = crate::content::path::compressed_content_path(dir, &sri);
+        assert!(fs::metadata(stored).unwrap().len() < data.len() as u64);
+        assert_eq!(crate::read_sync(dir, "my-key").unwrap(), data);
+        let mut handle = crate::SyncReader::open_hash(dir, sri.clone()).unwrap();
+        let mut read = Vec::new();
+        handle.read_to_end(&mut read).unwrap();
+        handle.check().unwrap();
+        assert_eq!(read, data);
+        assert!(crate::exists_sync(dir, &sri));
+        crate::remove_hash_sync(dir, &sri).unwrap();
+        assert!(!crate::exists_sync(dir, &sri));
+    }
+
+    #[async_attributes::test]
+    async fn test_read_fresh() {
+        use std::time::Duration;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let mut fd = crate::WriteOpts::new()
+            .ttl(Duration::from_secs(60))
+            .open(dir, "fresh")
+            .await
+            .unwrap();
+        fd.write_all(b"fresh data").await.unwrap();
+        fd.commit().await.unwrap();
+        let mut fd = crate::WriteOpts::new()
+            .time(1)
+            .ttl(Duration::from_secs(60))
+            .open(dir, "stale")
+            .await
+            .unwrap();
+        fd.write_all(b"stale data").await.unwrap();
+        fd.commit().await.unwrap();
+        crate::write(dir, "forever", b"forever").await.unwrap();
+
+        assert_eq!(
+            crate::read_fresh(dir, "fresh").await.unwrap(),
+            crate::Freshness::Fresh(b"fresh data".to_vec())
+        );
+        assert_eq!(
+            crate::read_fresh(dir, "stale").await.unwrap(),
+            crate::Freshness::Stale(b"stale data".to_vec())
+        );
+        assert_eq!(
+            crate::read_fresh(dir, "forever").await.unwrap(),
+            crate::Freshness::Fresh(b"forever".to_vec())
+        );
+        assert_eq!(
+            crate::read_fresh(dir, "missing").await.unwrap(),
+            crate::Freshness::Missing
+        );
+        let entry = crate::metadata_fresh(dir, "stale").await.unwrap();
+        assert_eq!(entry.clone().fresh(), None);
+        assert_eq!(entry.value().unwrap().expires, Some(60_001));
+    }
+
+    #[test]
+    fn test_metadata_fresh_sync() {
+        use std::io::Write;
+        use std::time::Duration;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let mut fd = crate::WriteOpts::new()
+            .time(1)
+            .ttl(Duration::from_millis(10))
+            .open_sync(dir, "stale")
+            .unwrap();
+        fd.write_all(b"stale data").unwrap();
+        fd.commit().unwrap();
+
+        let entry = crate::metadata_fresh_sync(dir, "stale").unwrap();
+        assert!(matches!(entry, crate::Freshness::Stale(_)));
+        assert_eq!(
+            crate::read_fresh_sync(dir, "stale")
+                .unwrap()
+                .value()
+                .unwrap(),
+            b"stale data"
+        );
+        assert_eq!(
+            crate::metadata_fresh_sync(dir, "missing").unwrap(),
+            crate::Freshness::Missing
+        );
+    }
 }
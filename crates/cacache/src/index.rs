@@ -1,6 +1,4 @@
-use std::collections::HashSet;
 use std::fs::{self, OpenOptions};
-use std::hash::{Hash, Hasher};
 use std::io::{ErrorKind, Write};
 use std::path::{Path, PathBuf};
 use std::time::{SystemTime, UNIX_EPOCH};
@@ -19,12 +17,28 @@ use ssri::Integrity;
 use walkdir::WalkDir;
 
 use crate::errors::{Internal, InternalResult, Result};
+use crate::lock;
 use crate::put::WriteOpts;
 
-const INDEX_VERSION: &str = "5";
+// Bumped along with the content version, so entries from older caches don't
+// point at content that's no longer where they expect. `verify` cleans up
+// the old directories.
+const INDEX_VERSION: &str = "6";
+
+/// How stale, in milliseconds, an entry's access time has to be before
+/// reading it records a new one.
+const ATIME_RESOLUTION: u128 = 60 * 1000;
+
+/// Extension of the file next to each bucket that holds the time its entries
+/// were last read.
+const ATIME_EXTENSION: &str = "atime";
 
 /// Represents a cache index entry, which points to content.
-#[derive(PartialEq, Debug)]
+///
+/// New fields may be added in later versions, so this can't be built with a
+/// struct literal outside of this crate.
+#[derive(Clone, PartialEq, Debug)]
+#[non_exhaustive]
 pub struct Metadata {
     /// Key this entry is stored under.
     pub key: String,
@@ -32,63 +46,66 @@ pub struct Metadata {
     pub integrity: Integrity,
     /// Timestamp in unix milliseconds when this entry was written.
     pub time: u128,
+    /// Timestamp in unix milliseconds when this entry was last read. Same as
+    /// `time` if the entry has never been read by key. Keys that share an
+    /// index bucket share their access time.
+    pub atime: u128,
+    /// Timestamp in unix milliseconds after which this entry is considered
+    /// stale, if it was written with a TTL.
+    pub expires: Option<u128>,
     /// Size of data associated with this entry.
     pub size: usize,
     /// Arbitrary JSON  associated with this entry.
     pub metadata: Value,
 }
 
+impl Metadata {
+    /// Returns true if this entry was written with a TTL that has since run
+    /// out.
+    pub fn is_stale(&self) -> bool {
+        matches!(self.expires, Some(expires) if now() >= expires)
+    }
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 struct SerializableMetadata {
     key: String,
     integrity: Option<String>,
     time: u128,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    atime: Option<u128>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires: Option<u128>,
     size: usize,
     metadata: Value,
 }
 
-impl PartialEq for SerializableMetadata {
-    fn eq(&self, other: &Self) -> bool {
-        self.key == other.key
-    }
-}
-
-impl Eq for SerializableMetadata {}
-
-impl Hash for SerializableMetadata {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        self.key.hash(state);
-    }
-}
-
 pub fn insert(cache: &Path, key: &str, opts: WriteOpts) -> Result<Integrity> {
     let bucket = bucket_path(&cache, &key);
+    let time = opts.time.unwrap_or_else(now);
+    if let Some(sri) = &opts.sri {
+        // Before the entry itself, so `ls_hash` never misses a live entry.
+        add_hash_refs(cache, key, sri)?;
+    }
     fs::create_dir_all(bucket.parent().unwrap()).with_context(|| {
         format!(
             "Failed to create index bucket directory: {:?}",
             bucket.parent().unwrap()
         )
     })?;
-    let stringified = serde_json::to_string(&SerializableMetadata {
-        key: key.to_owned(),
-        integrity: opts.sri.clone().map(|x| x.to_string()),
-        time: opts.time.unwrap_or_else(now),
-        size: opts.size.unwrap_or(0),
-        metadata: opts.metadata.unwrap_or(json!(null)),
-    })
-    .with_context(|| format!("Failed to serialize entry with key `{}`", key))?;
-
-    let mut buck = OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(&bucket)
-        .with_context(|| format!("Failed to create or open index bucket at {:?}", bucket))?;
-
-    let out = format!("\n{}\t{}", hash_entry(&stringified), stringified);
-    buck.write_all(out.as_bytes())
-        .with_context(|| format!("Failed to write to index bucket at {:?}", bucket))?;
-    buck.flush()
-        .with_context(|| format!("Failed to flush bucket at {:?}", bucket))?;
+    append_entry(
+        cache,
+        &bucket,
+        &SerializableMetadata {
+            key: key.to_owned(),
+            integrity: opts.sri.clone().map(|x| x.to_string()),
+            time,
+            atime: None,
+            expires: opts.ttl.map(|ttl| time + ttl),
+            size: opts.size.unwrap_or(0),
+            metadata: opts.metadata.unwrap_or(json!(null)),
+        },
+    )?;
     Ok(opts
         .sri
         .or_else(|| "sha1-deadbeef".parse::<Integrity>().ok())
@@ -97,6 +114,13 @@ pub fn insert(cache: &Path, key: &str, opts: WriteOpts) -> Result<Integrity> {
 
 pub async fn insert_async<'a>(cache: &'a Path, key: &'a str, opts: WriteOpts) -> Result<Integrity> {
     let bucket = bucket_path(&cache, &key);
+    let time = opts.time.unwrap_or_else(now);
+    if let Some(sri) = &opts.sri {
+        let cache = cache.to_owned();
+        let key = key.to_owned();
+        let sri = sri.clone();
+        smol::unblock!(add_hash_refs(&cache, &key, &sri))?;
+    }
     afs::create_dir_all(bucket.parent().unwrap())
         .await
         .with_context(|| {
@@ -105,29 +129,20 @@ pub async fn insert_async<'a>(cache: &'a Path, key: &'a str, opts: WriteOpts) ->
                 bucket.parent().unwrap()
             )
         })?;
-    let stringified = serde_json::to_string(&SerializableMetadata {
-        key: key.to_owned(),
-        integrity: opts.sri.clone().map(|x| x.to_string()),
-        time: opts.time.unwrap_or_else(now),
-        size: opts.size.unwrap_or(0),
-        metadata: opts.metadata.unwrap_or(json!(null)),
-    })
-    .with_context(|| format!("Failed to serialize entry with key `{}`", key))?;
-
-    let mut buck = async_std::fs::OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(&bucket)
-        .await
-        .with_context(|| format!("Failed to create or open index bucket at {:?}", bucket))?;
-
-    let out = format!("\n{}\t{}", hash_entry(&stringified), stringified);
-    buck.write_all(out.as_bytes())
-        .await
-        .with_context(|| format!("Failed to write to index bucket at {:?}", bucket))?;
-    buck.flush()
-        .await
-        .with_context(|| format!("Failed to flush bucket at {:?}", bucket))?;
+    append_entry_async(
+        cache,
+        &bucket,
+        &SerializableMetadata {
+            key: key.to_owned(),
+            integrity: opts.sri.clone().map(|x| x.to_string()),
+            time,
+            atime: None,
+            expires: opts.ttl.map(|ttl| time + ttl),
+            size: opts.size.unwrap_or(0),
+            metadata: opts.metadata.unwrap_or(json!(null)),
+        },
+    )
+    .await?;
     Ok(opts
         .sri
         .or_else(|| "sha1-deadbeef".parse::<Integrity>().ok())
@@ -136,7 +151,7 @@ pub async fn insert_async<'a>(cache: &'a Path, key: &'a str, opts: WriteOpts) ->
 
 pub fn find(cache: &Path, key: &str) -> Result<Option<Metadata>> {
     let bucket = bucket_path(cache, &key);
-    Ok(bucket_entries(&bucket)
+    let entry = bucket_entries(&bucket)
         .with_context(|| format!("Failed to read index bucket entries from {:?}", bucket))?
         .into_iter()
         .fold(None, |acc, entry| {
@@ -151,6 +166,8 @@ pub fn find(cache: &Path, key: &str) -> Result<Option<Metadata>> {
                         integrity,
                         size: entry.size,
                         time: entry.time,
+                        atime: entry.atime.unwrap_or(entry.time),
+                        expires: entry.expires,
                         metadata: entry.metadata,
                     })
                 } else {
@@ -159,12 +176,18 @@ pub fn find(cache: &Path, key: &str) -> Result<Option<Metadata>> {
             } else {
                 acc
             }
-        }))
+        });
+    Ok(entry.map(|mut entry| {
+        if let Some(atime) = read_atime(&bucket) {
+            entry.atime = entry.atime.max(atime);
+        }
+        entry
+    }))
 }
 
 pub async fn find_async(cache: &Path, key: &str) -> Result<Option<Metadata>> {
     let bucket = bucket_path(cache, &key);
-    Ok(bucket_entries_async(&bucket)
+    let entry = bucket_entries_async(&bucket)
         .await
         .with_context(|| format!("Failed to read index bucket entries from {:?}", bucket))?
         .into_iter()
@@ -180,6 +203,8 @@ pub async fn find_async(cache: &Path, key: &str) -> Result<Option<Metadata>> {
                         integrity,
                         size: entry.size,
                         time: entry.time,
+                        atime: entry.atime.unwrap_or(entry.time),
+                        expires: entry.expires,
                         metadata: entry.metadata,
                     })
                 } else {
@@ -188,7 +213,16 @@ pub async fn find_async(cache: &Path, key: &str) -> Result<Option<Metadata>> {
             } else {
                 acc
             }
-        }))
+        });
+    Ok(match entry {
+        Some(mut entry) => {
+            if let Some(atime) = read_atime_async(&bucket).await {
+                entry.atime = entry.atime.max(atime);
+            }
+            Some(entry)
+        }
+        None => None,
+    })
 }
 
 pub fn delete(cache: &Path, key: &str) -> Result<()> {
@@ -196,11 +230,13 @@ pub fn delete(cache: &Path, key: &str) -> Result<()> {
         cache,
         key,
         WriteOpts {
-            algorithm: None,
+            algorithms: Vec::new(),
             size: None,
             sri: None,
             time: None,
             metadata: None,
+            ttl: None,
+            compression: None,
         },
     )
     .map(|_| ())
@@ -211,60 +247,441 @@ pub async fn delete_async(cache: &Path, key: &str) -> Result<()> {
         cache,
         key,
         WriteOpts {
-            algorithm: None,
+            algorithms: Vec::new(),
             size: None,
             sri: None,
             time: None,
             metadata: None,
+            ttl: None,
+            compression: None,
         },
     )
     .map(|_| ())
 }
 
-pub fn ls(cache: &Path) -> impl Iterator<Item = Result<Metadata>> {
-    WalkDir::new(cache.join(format!("index-v{}", INDEX_VERSION)))
-        .into_iter()
-        .map(|bucket| {
-            let bucket = bucket.to_internal()?;
+/// Records that `entry` was just read, so eviction can tell recently-used
+/// entries apart from stale ones. Updates are skipped if the entry was
+/// already touched within the last `ATIME_RESOLUTION` milliseconds.
+///
+/// Access times live in a small file next to the entry's bucket rather than
+/// in the bucket itself, so reads never append to the index, and can't race
+/// with writers to it.
+pub fn touch(cache: &Path, entry: &Metadata) -> Result<()> {
+    let time = now();
+    if time.saturating_sub(entry.atime) < ATIME_RESOLUTION {
+        return Ok(());
+    }
+    let bucket = bucket_path(cache, &entry.key);
+    let atime = atime_path(&bucket);
+    let tmp_path = cache.join("tmp");
+    fs::create_dir_all(&tmp_path)
+        .with_context(|| format!("Failed to create tmp directory at {:?}", tmp_path))?;
+    let mut tmp = tempfile::NamedTempFile::new_in(&tmp_path)
+        .with_context(|| format!("Failed to create temporary file in {:?}", tmp_path))?;
+    tmp.write_all(time.to_string().as_bytes())
+        .with_context(|| format!("Failed to write access time for {:?}", bucket))?;
+    // Written to a temporary file first, so readers never see a partial
+    // timestamp.
+    tmp.persist(&atime)
+        .with_context(|| format!("Failed to write access time to {:?}", atime))?;
+    Ok(())
+}
 
-            if bucket.file_type().is_dir() {
-                return Ok(Vec::new());
-            }
+/// Asynchronous version of `touch`.
+pub async fn touch_async(cache: &Path, entry: &Metadata) -> Result<()> {
+    let cache = cache.to_owned();
+    let entry = entry.clone();
+    smol::unblock!(touch(&cache, &entry))
+}
 
-            Ok(bucket_entries(bucket.path())?
-                .into_iter()
-                .collect::<HashSet<SerializableMetadata>>()
+pub fn ls(cache: &Path) -> impl Iterator<Item = Result<Metadata>> {
+    ls_prefix(cache, "")
+}
+
+/// Lists the live entries whose keys start with `prefix`. Index lines for
+/// other keys are skipped without being hashed or parsed.
+pub fn ls_prefix(cache: &Path, prefix: &str) -> impl Iterator<Item = Result<Metadata>> {
+    let prefix = prefix.to_owned();
+    let needle = key_needle(&prefix);
+    bucket_paths(cache)
+        .map(move |bucket| {
+            let bucket = bucket?;
+            let entries = live_entries(bucket_entries_matching(&bucket, &needle)?);
+            let entries = with_atime(&bucket, entries);
+            Ok(entries
                 .into_iter()
-                .filter_map(|se| {
-                    if let Some(i) = se.integrity {
-                        Some(Metadata {
-                            key: se.key,
-                            integrity: i.parse().unwrap(),
-                            time: se.time,
-                            size: se.size,
-                            metadata: se.metadata,
-                        })
-                    } else {
-                        None
-                    }
-                })
-                .collect())
+                .filter(|entry| entry.key.starts_with(&prefix))
+                .collect::<Vec<_>>())
         })
-        .flat_map(|res| match res {
+        .flat_map(|res: Result<Vec<Metadata>>| match res {
             Ok(it) => Left(it.into_iter().map(Ok)),
             Err(err) => Right(std::iter::once(Err(err))),
         })
 }
 
+/// Lists the live entries that point to the content for `sri`, going by any
+/// of its hashes. Only the keys recorded for those hashes are looked up, so
+/// this doesn't depend on the size of the cache.
+pub fn ls_hash(cache: &Path, sri: &Integrity) -> impl Iterator<Item = Result<Metadata>> {
+    let cache = cache.to_owned();
+    let sri = sri.clone();
+    let keys = match hash_ref_keys(&cache, &sri) {
+        Ok(keys) => keys,
+        Err(err) => return Left(std::iter::once(Err(err))),
+    };
+    Right(keys.into_iter().filter_map(move |key| {
+        // A key stays recorded after it's pointed somewhere else, so check
+        // its current entry.
+        match find(&cache, &key) {
+            Ok(Some(entry))
+                if entry
+                    .integrity
+                    .hashes
+                    .iter()
+                    .any(|h| sri.hashes.contains(h)) =>
+            {
+                Some(Ok(entry))
+            }
+            Ok(_) => None,
+            Err(err) => Some(Err(err)),
+        }
+    }))
+}
+
+/// Returns the directory holding the cache's hash-to-key index, which
+/// `ls_hash` uses to find entries by their content.
+pub fn hash_index_dir(cache: &Path) -> PathBuf {
+    cache.join(format!("hashes-v{}", INDEX_VERSION))
+}
+
+/// Drops keys from the hash-to-key index whose entries no longer point to
+/// the hash they're listed under, and removes hash buckets left empty.
+/// Returns the number of keys dropped.
+pub fn compact_hash_index(cache: &Path) -> InternalResult<usize> {
+    let dir = hash_index_dir(cache);
+    if !dir.exists() {
+        return Ok(0);
+    }
+    let mut dropped = 0;
+    for bucket in WalkDir::new(&dir) {
+        let bucket = bucket.to_internal()?;
+        if bucket.file_type().is_dir() {
+            continue;
+        }
+        let bucket = bucket.into_path();
+        let _lock = lock::lock(cache, &bucket)?;
+        let keys = read_hash_bucket(&bucket)?;
+        let mut live = Vec::with_capacity(keys.len());
+        for key in keys {
+            let listed = match find(cache, &key).to_internal()? {
+                Some(entry) => entry
+                    .integrity
+                    .hashes
+                    .iter()
+                    .any(|hash| hash_bucket_path(cache, hash) == bucket),
+                None => false,
+            };
+            if listed {
+                live.push(key);
+            } else {
+                dropped += 1;
+            }
+        }
+        if live.is_empty() {
+            remove_bucket(&bucket)?;
+            continue;
+        }
+        let mut out = String::new();
+        for key in live {
+            out.push_str(&serialize_hash_ref(&key)?);
+        }
+        let tmp_path = cache.join("tmp");
+        fs::create_dir_all(&tmp_path)
+            .with_context(|| format!("Failed to create tmp directory at {:?}", tmp_path))?;
+        let mut tmp = tempfile::NamedTempFile::new_in(&tmp_path)
+            .with_context(|| format!("Failed to create temporary bucket in {:?}", tmp_path))?;
+        tmp.write_all(out.as_bytes())
+            .with_context(|| format!("Failed to write temporary bucket for {:?}", bucket))?;
+        tmp.persist(&bucket)
+            .with_context(|| format!("Failed to replace hash bucket at {:?}", bucket))?;
+    }
+    Ok(dropped)
+}
+
+/// Serializes `entry` the same way index buckets store it, for backends that
+/// keep entries somewhere else.
+#[cfg(feature = "sled")]
+pub fn encode_entry(entry: &Metadata) -> InternalResult<Vec<u8>> {
+    serde_json::to_vec(&SerializableMetadata::from(entry)).to_internal()
+}
+
+/// Parses an entry serialized by `encode_entry`. Returns `None` if it's
+/// corrupted.
+#[cfg(feature = "sled")]
+pub fn decode_entry(raw: &[u8]) -> Option<Metadata> {
+    let entry: SerializableMetadata = serde_json::from_slice(raw).ok()?;
+    live_entries(vec![entry]).pop()
+}
+
+/// Returns the directory holding the cache's index buckets.
+pub fn index_dir(cache: &Path) -> PathBuf {
+    cache.join(format!("index-v{}", INDEX_VERSION))
+}
+
+/// Returns the paths of every index bucket in the cache. A cache that
+/// hasn't been written to yet has none.
+pub fn bucket_paths(cache: &Path) -> impl Iterator<Item = InternalResult<PathBuf>> {
+    WalkDir::new(index_dir(cache))
+        .into_iter()
+        .filter_map(|entry| match entry {
+            Err(err)
+                if err.depth() == 0
+                    && err.io_error().map(|err| err.kind()) == Some(ErrorKind::NotFound) =>
+            {
+                None
+            }
+            Ok(entry) if entry.file_type().is_dir() => None,
+            Ok(entry) if is_atime_path(entry.path()) => None,
+            Ok(entry) => Some(Ok(entry.into_path())),
+            Err(err) => Some(Err(err).to_internal()),
+        })
+}
+
+/// Reads the current, live entry for every key in a bucket, in the order
+/// they were last written. Deleted keys and entries with unparseable
+/// integrity strings are left out.
+pub fn bucket_metadata(bucket: &Path) -> InternalResult<Vec<Metadata>> {
+    Ok(with_atime(bucket, live_entries(bucket_entries(bucket)?)))
+}
+
+/// Atomically replaces the contents of `bucket` with `entries`. Readers will
+/// either see the old bucket or the new one, never a partial write. If
+/// `entries` is empty, the bucket is removed altogether.
+///
+/// Callers should hold the bucket's lock from before they read it until this
+/// returns, so entries appended in the meantime aren't lost.
+pub fn write_bucket(cache: &Path, bucket: &Path, entries: &[Metadata]) -> InternalResult<()> {
+    if entries.is_empty() {
+        return remove_bucket(bucket);
+    }
+    tmp_bucket(cache, bucket, entries)?
+        .persist(bucket)
+        .with_context(|| format!("Failed to replace index bucket at {:?}", bucket))?;
+    Ok(())
+}
+
+/// Rewrites `bucket` so it only holds the live entry for each of its keys,
+/// dropping superseded, deleted, and corrupted lines. Returns the number of
+/// lines removed.
+///
+/// The bucket stays locked throughout, so entries appended by other writers,
+/// including ones in other processes, aren't lost.
+pub fn compact_bucket(cache: &Path, bucket: &Path) -> InternalResult<usize> {
+    let _lock = lock::lock(cache, bucket)?;
+    let text = match fs::read(bucket) {
+        Ok(raw) => String::from_utf8_lossy(&raw).into_owned(),
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(0),
+        Err(err) => {
+            return Err(err).with_context(|| format!("Failed to read index bucket at {:?}", bucket))
+        }
+    };
+    let lines = text.lines().filter(|line| !line.is_empty()).count();
+    let live = with_atime(
+        bucket,
+        live_entries(text.lines().filter_map(parse_entry).collect()),
+    );
+    if live.len() == lines {
+        return Ok(0);
+    }
+    write_bucket(cache, bucket, &live)?;
+    Ok(lines - live.len())
+}
+
+/// Reads the live entries in `bucket`, along with the number of lines that
+/// compacting it would remove.
+pub fn bucket_usage(bucket: &Path) -> InternalResult<(Vec<Metadata>, usize)> {
+    let text = match fs::read(bucket) {
+        Ok(raw) => String::from_utf8_lossy(&raw).into_owned(),
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok((Vec::new(), 0)),
+        Err(err) => {
+            return Err(err).with_context(|| format!("Failed to read index bucket at {:?}", bucket))
+        }
+    };
+    let lines = text.lines().filter(|line| !line.is_empty()).count();
+    let live = with_atime(
+        bucket,
+        live_entries(text.lines().filter_map(parse_entry).collect()),
+    );
+    let stale = lines - live.len();
+    Ok((live, stale))
+}
+
+fn live_entries(entries: Vec<SerializableMetadata>) -> Vec<Metadata> {
+    let mut latest: Vec<SerializableMetadata> = Vec::new();
+    for entry in entries {
+        latest.retain(|existing| existing.key != entry.key);
+        latest.push(entry);
+    }
+    latest
+        .into_iter()
+        .filter_map(|se| {
+            let integrity = se.integrity?.parse().ok()?;
+            Some(Metadata {
+                key: se.key,
+                integrity,
+                time: se.time,
+                atime: se.atime.unwrap_or(se.time),
+                expires: se.expires,
+                size: se.size,
+                metadata: se.metadata,
+            })
+        })
+        .collect()
+}
+
+/// Fills in the access time recorded next to `bucket` for its entries.
+fn with_atime(bucket: &Path, mut entries: Vec<Metadata>) -> Vec<Metadata> {
+    if entries.is_empty() {
+        return entries;
+    }
+    if let Some(atime) = read_atime(bucket) {
+        for entry in entries.iter_mut() {
+            entry.atime = entry.atime.max(atime);
+        }
+    }
+    entries
+}
+
+fn atime_path(bucket: &Path) -> PathBuf {
+    bucket.with_extension(ATIME_EXTENSION)
+}
+
+fn is_atime_path(path: &Path) -> bool {
+    path.extension().and_then(|extension| extension.to_str()) == Some(ATIME_EXTENSION)
+}
+
+fn read_atime(bucket: &Path) -> Option<u128> {
+    fs::read_to_string(atime_path(bucket)).ok()?.parse().ok()
+}
+
+async fn read_atime_async(bucket: &Path) -> Option<u128> {
+    afs::read_to_string(atime_path(bucket))
+        .await
+        .ok()?
+        .parse()
+        .ok()
+}
+
+fn remove_bucket(bucket: &Path) -> InternalResult<()> {
+    let _ = fs::remove_file(atime_path(bucket));
+    match fs::remove_file(bucket) {
+        Err(err) if err.kind() != ErrorKind::NotFound => {
+            Err(err).with_context(|| format!("Failed to remove index bucket at {:?}", bucket))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn tmp_bucket(
+    cache: &Path,
+    bucket: &Path,
+    entries: &[Metadata],
+) -> InternalResult<tempfile::NamedTempFile> {
+    let mut out = String::new();
+    for entry in entries {
+        out.push_str(&serialize_entry(&SerializableMetadata::from(entry))?);
+    }
+    let tmp_path = cache.join("tmp");
+    fs::create_dir_all(&tmp_path)
+        .with_context(|| format!("Failed to create tmp directory at {:?}", tmp_path))?;
+    let mut tmp = tempfile::NamedTempFile::new_in(&tmp_path)
+        .with_context(|| format!("Failed to create temporary bucket in {:?}", tmp_path))?;
+    tmp.write_all(out.as_bytes())
+        .with_context(|| format!("Failed to write temporary bucket for {:?}", bucket))?;
+    tmp.flush()
+        .with_context(|| format!("Failed to flush temporary bucket for {:?}", bucket))?;
+    Ok(tmp)
+}
+
 fn bucket_path(cache: &Path, key: &str) -> PathBuf {
     let hashed = hash_key(&key);
-    cache
-        .join(format!("index-v{}", INDEX_VERSION))
+    index_dir(cache)
+        .join(&hashed[0..2])
+        .join(&hashed[2..4])
+        .join(&hashed[4..])
+}
+
+fn hash_bucket_path(cache: &Path, hash: &ssri::Hash) -> PathBuf {
+    let hashed = hash_key(&hash.to_string());
+    hash_index_dir(cache)
         .join(&hashed[0..2])
         .join(&hashed[2..4])
         .join(&hashed[4..])
 }
 
+/// Records that `key` points to content with each of `sri`'s hashes. Keys
+/// that are already listed under a hash aren't added again.
+fn add_hash_refs(cache: &Path, key: &str, sri: &Integrity) -> Result<()> {
+    for hash in sri.hashes.iter() {
+        let bucket = hash_bucket_path(cache, hash);
+        fs::create_dir_all(bucket.parent().unwrap()).with_context(|| {
+            format!(
+                "Failed to create hash bucket directory: {:?}",
+                bucket.parent().unwrap()
+            )
+        })?;
+        let _lock = lock::lock(cache, &bucket)?;
+        if read_hash_bucket(&bucket)?
+            .iter()
+            .any(|listed| listed == key)
+        {
+            continue;
+        }
+        let mut buck = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&bucket)
+            .with_context(|| format!("Failed to create or open hash bucket at {:?}", bucket))?;
+        buck.write_all(serialize_hash_ref(key)?.as_bytes())
+            .with_context(|| format!("Failed to write to hash bucket at {:?}", bucket))?;
+    }
+    Ok(())
+}
+
+/// Every key recorded under any of `sri`'s hashes, without duplicates.
+fn hash_ref_keys(cache: &Path, sri: &Integrity) -> Result<Vec<String>> {
+    let mut keys: Vec<String> = Vec::new();
+    for hash in sri.hashes.iter() {
+        for key in read_hash_bucket(&hash_bucket_path(cache, hash))? {
+            if !keys.contains(&key) {
+                keys.push(key);
+            }
+        }
+    }
+    Ok(keys)
+}
+
+fn read_hash_bucket(bucket: &Path) -> InternalResult<Vec<String>> {
+    let text = match fs::read_to_string(bucket) {
+        Ok(text) => text,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => {
+            return Err(err).with_context(|| format!("Failed to read hash bucket at {:?}", bucket))
+        }
+    };
+    // Keys are JSON strings, so newlines in them can't split a line.
+    Ok(text
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+fn serialize_hash_ref(key: &str) -> InternalResult<String> {
+    let key =
+        serde_json::to_string(key).with_context(|| format!("Failed to serialize key `{}`", key))?;
+    Ok(format!("\n{}", key))
+}
+
 fn hash_key(key: &str) -> String {
     let mut hasher = Sha1::new();
     hasher.input(&key);
@@ -277,28 +694,110 @@ fn hash_entry(key: &str) -> String {
     hex::encode(hasher.result())
 }
 
-fn now() -> u128 {
+pub fn now() -> u128 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap()
         .as_millis()
 }
 
+impl From<&Metadata> for SerializableMetadata {
+    fn from(entry: &Metadata) -> Self {
+        SerializableMetadata {
+            key: entry.key.clone(),
+            integrity: Some(entry.integrity.to_string()),
+            time: entry.time,
+            atime: if entry.atime == entry.time {
+                None
+            } else {
+                Some(entry.atime)
+            },
+            expires: entry.expires,
+            size: entry.size,
+            metadata: entry.metadata.clone(),
+        }
+    }
+}
+
+fn serialize_entry(entry: &SerializableMetadata) -> InternalResult<String> {
+    let stringified = serde_json::to_string(entry)
+        .with_context(|| format!("Failed to serialize entry with key `{}`", entry.key))?;
+    Ok(format!("\n{}\t{}", hash_entry(&stringified), stringified))
+}
+
+fn append_entry(cache: &Path, bucket: &Path, entry: &SerializableMetadata) -> Result<()> {
+    let out = serialize_entry(entry)?;
+    let _lock = lock::lock(cache, bucket)?;
+    let mut buck = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(bucket)
+        .with_context(|| format!("Failed to create or open index bucket at {:?}", bucket))?;
+    buck.write_all(out.as_bytes())
+        .with_context(|| format!("Failed to write to index bucket at {:?}", bucket))?;
+    buck.flush()
+        .with_context(|| format!("Failed to flush bucket at {:?}", bucket))?;
+    Ok(())
+}
+
+async fn append_entry_async(
+    cache: &Path,
+    bucket: &Path,
+    entry: &SerializableMetadata,
+) -> Result<()> {
+    let out = serialize_entry(entry)?;
+    let _lock = lock::lock_async(cache, bucket).await?;
+    let mut buck = async_std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(bucket)
+        .await
+        .with_context(|| format!("Failed to create or open index bucket at {:?}", bucket))?;
+    buck.write_all(out.as_bytes())
+        .await
+        .with_context(|| format!("Failed to write to index bucket at {:?}", bucket))?;
+    buck.flush()
+        .await
+        .with_context(|| format!("Failed to flush bucket at {:?}", bucket))?;
+    Ok(())
+}
+
+fn parse_entry(entry: &str) -> Option<SerializableMetadata> {
+    let entry_str = match entry.split('\t').collect::<Vec<&str>>()[..] {
+        [hash, entry_str] if hash_entry(entry_str) == hash => entry_str,
+        // Something's wrong with the entry. Abort.
+        _ => return None,
+    };
+    serde_json::from_str::<SerializableMetadata>(entry_str).ok()
+}
+
+/// Serialized entries always start with their key, so this prefix of the
+/// JSON is enough to tell whether a line might belong to a key starting with
+/// `prefix`, without hashing or parsing it.
+fn key_needle(prefix: &str) -> String {
+    let quoted = serde_json::to_string(prefix).expect("strings always serialize");
+    format!("{{\"key\":{}", &quoted[..quoted.len() - 1])
+}
+
 fn bucket_entries(bucket: &Path) -> InternalResult<Vec<SerializableMetadata>> {
+    bucket_entries_matching(bucket, &key_needle(""))
+}
+
+fn bucket_entries_matching(
+    bucket: &Path,
+    needle: &str,
+) -> InternalResult<Vec<SerializableMetadata>> {
     use std::io::{BufRead, BufReader};
     fs::File::open(bucket)
         .map(|file| {
             BufReader::new(file)
                 .lines()
                 .filter_map(std::result::Result::ok)
-                .filter_map(|entry| {
-                    let entry_str = match entry.split('\t').collect::<Vec<&str>>()[..] {
-                        [hash, entry_str] if hash_entry(entry_str) == hash => entry_str,
-                        // Something's wrong with the entry. Abort.
-                        _ => return None,
-                    };
-                    serde_json::from_str::<SerializableMetadata>(entry_str).ok()
+                .filter(|entry| match entry.split('\t').nth(1) {
+                    Some(entry_str) => entry_str.starts_with(needle),
+                    None => false,
                 })
+                .filter_map(|entry| parse_entry(&entry))
                 .collect()
         })
         .or_else(|err| {
@@ -324,15 +823,8 @@ async fn bucket_entries_async(bucket: &Path) -> InternalResult<Vec<SerializableM
     let mut vec = Vec::new();
     let mut lines = BufReader::new(file).lines();
     while let Some(line) = lines.next().await {
-        if let Ok(entry) = line {
-            let entry_str = match entry.split('\t').collect::<Vec<&str>>()[..] {
-                [hash, entry_str] if hash_entry(entry_str) == hash => entry_str,
-                // Something's wrong with the entry. Abort.
-                _ => continue,
-            };
-            if let Ok(serialized) = serde_json::from_str::<SerializableMetadata>(entry_str) {
-                vec.push(serialized);
-            }
+        if let Some(serialized) = line.ok().and_then(|entry| parse_entry(&entry)) {
+            vec.push(serialized);
         }
     }
     Ok(vec)
@@ -388,6 +880,8 @@ mod tests {
                 key: String::from("hello"),
                 integrity: sri,
                 time,
+                atime: time,
+                expires: None,
                 size: 0,
                 metadata: json!(null)
             }
@@ -442,6 +936,8 @@ mod tests {
                 key: String::from("hello"),
                 integrity: sri,
                 time,
+                atime: time,
+                expires: None,
                 size: 0,
                 metadata: json!(null)
             }
@@ -465,12 +961,55 @@ mod tests {
                 key: String::from("hello"),
                 integrity: sri,
                 time,
+                atime: time,
+                expires: None,
                 size: 0,
                 metadata: json!(null)
             }
         );
     }
 
+    #[test]
+    fn touch_basic() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri: Integrity = "sha1-deadbeef".parse().unwrap();
+        let opts = WriteOpts::new().integrity(sri).time(1_234_567);
+        insert(&dir, "hello", opts).unwrap();
+        let entry = find(&dir, "hello").unwrap().unwrap();
+        assert_eq!(entry.atime, entry.time);
+
+        touch(&dir, &entry).unwrap();
+        let touched = find(&dir, "hello").unwrap().unwrap();
+        assert!(touched.atime > entry.time);
+        assert_eq!(touched.time, entry.time);
+        assert_eq!(touched.integrity, entry.integrity);
+
+        // Touching again right away is a no-op.
+        touch(&dir, &touched).unwrap();
+        assert_eq!(find(&dir, "hello").unwrap().unwrap().atime, touched.atime);
+
+        // Reads never add to the index, and don't show up as buckets.
+        let lines = std::fs::read_to_string(bucket_path(&dir, "hello")).unwrap();
+        assert_eq!(lines.lines().filter(|l| !l.is_empty()).count(), 1);
+        assert_eq!(bucket_paths(&dir).count(), 1);
+        assert_eq!(ls(&dir).next().unwrap().unwrap().atime, touched.atime);
+    }
+
+    #[test]
+    fn touch_doesnt_revert_insert() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let old: Integrity = "sha1-deadbeef".parse().unwrap();
+        let new: Integrity = "sha1-badc0ffee".parse().unwrap();
+        insert(&dir, "hello", WriteOpts::new().integrity(old).time(1)).unwrap();
+        let seen = find(&dir, "hello").unwrap().unwrap();
+        // Another writer updates the key between the read and its touch.
+        insert(&dir, "hello", WriteOpts::new().integrity(new.clone())).unwrap();
+        touch(&dir, &seen).unwrap();
+        assert_eq!(find(&dir, "hello").unwrap().unwrap().integrity, new);
+    }
+
     #[test]
     fn ls_basic() {
         let tmp = tempfile::tempdir().unwrap();
@@ -489,4 +1028,42 @@ mod tests {
         entries.sort();
         assert_eq!(entries, vec![String::from("hello"), String::from("world")])
     }
+
+    #[test]
+    fn bucket_paths_missing_cache() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("missing");
+        assert_eq!(bucket_paths(&dir).count(), 0);
+        assert_eq!(ls(&dir).count(), 0);
+    }
+
+    #[test]
+    fn ls_hash_basic() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri: Integrity = "sha1-deadbeef".parse().unwrap();
+        let other: Integrity = "sha1-badc0ffee".parse().unwrap();
+        for key in &["hello", "world", "moved"] {
+            insert(&dir, key, WriteOpts::new().integrity(sri.clone())).unwrap();
+        }
+        insert(&dir, "hello", WriteOpts::new().integrity(sri.clone())).unwrap();
+        insert(&dir, "moved", WriteOpts::new().integrity(other.clone())).unwrap();
+        insert(&dir, "unrelated", WriteOpts::new().integrity(other)).unwrap();
+        delete(&dir, "world").unwrap();
+
+        let keys = ls_hash(&dir, &sri)
+            .map(|x| Ok(x?.key))
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(keys, vec![String::from("hello")]);
+
+        // Rewriting a key doesn't list it twice.
+        let bucket = hash_bucket_path(&dir, &sri.hashes[0]);
+        assert_eq!(read_hash_bucket(&bucket).unwrap().len(), 3);
+        assert_eq!(compact_hash_index(&dir).unwrap(), 2);
+        assert_eq!(read_hash_bucket(&bucket).unwrap(), vec!["hello"]);
+        delete(&dir, "hello").unwrap();
+        compact_hash_index(&dir).unwrap();
+        assert!(!bucket.exists());
+    }
 }
@@ -0,0 +1,347 @@
+//! Reading through a stack of caches.
+use std::path::{Path, PathBuf};
+
+use ssri::{Algorithm, Integrity};
+
+use crate::content::read;
+use crate::errors::{Error, Internal, Result};
+use crate::get::{Reader, SyncReader};
+use crate::index::{self, Metadata};
+use crate::put::WriteOpts;
+
+/// A writable cache layered in front of any number of read-only fallback
+/// caches, such as a team cache on a network mount or one baked into a CI
+/// image.
+///
+/// Lookups check the writable cache first, then each fallback in the order
+/// they were added, and use the first one that has what they're after.
+/// Fallbacks are never written to, not even to record access times. Writes
+/// only ever go to the writable cache.
+///
+/// With `copy_hits` set, anything found in a fallback is copied into the
+/// writable cache before it's returned, so later lookups don't need the
+/// fallback anymore.
+///
+/// ## Example
+/// ```no_run
+/// use async_attributes;
+///
+/// #[async_attributes::main]
+/// async fn main() -> cacache::Result<()> {
+///     let cache = cacache::LayeredCache::new("./my-cache")
+///         .fallback("/mnt/team-cache")
+///         .copy_hits(true);
+///     let data = cache.read("my-key").await?;
+///     Ok(())
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct LayeredCache {
+    writable: PathBuf,
+    fallbacks: Vec<PathBuf>,
+    copy_hits: bool,
+}
+
+impl LayeredCache {
+    /// Creates a layered cache that writes to `writable`, with no fallbacks.
+    pub fn new<P: AsRef<Path>>(writable: P) -> Self {
+        LayeredCache {
+            writable: writable.as_ref().to_owned(),
+            fallbacks: Vec::new(),
+            copy_hits: false,
+        }
+    }
+
+    /// Adds a read-only cache to check after the writable cache and any
+    /// fallbacks added before it.
+    pub fn fallback<P: AsRef<Path>>(mut self, cache: P) -> Self {
+        self.fallbacks.push(cache.as_ref().to_owned());
+        self
+    }
+
+    /// Sets whether data found in a fallback cache should be copied into the
+    /// writable cache. Defaults to false.
+    pub fn copy_hits(mut self, copy_hits: bool) -> Self {
+        self.copy_hits = copy_hits;
+        self
+    }
+
+    /// Returns the path of the writable cache.
+    pub fn writable(&self) -> &Path {
+        &self.writable
+    }
+
+    /// Reads the entire contents of a cache file into a bytes vector, looking
+    /// the data up by key in each layer.
+    pub async fn read<K: AsRef<str>>(&self, key: K) -> Result<Vec<u8>> {
+        let (cache, entry) = self.clone().find_key_async(key.as_ref()).await?;
+        crate::read_hash(cache, &entry.integrity).await
+    }
+
+    /// Reads the entire contents of a cache file into a bytes vector, looking
+    /// the data up by its content address in each layer.
+    pub async fn read_hash(&self, sri: &Integrity) -> Result<Vec<u8>> {
+        let cache = self.clone().find_hash_async(sri).await?;
+        crate::read_hash(cache, sri).await
+    }
+
+    /// Opens a new file handle into the first layer with an entry for `key`.
+    pub async fn open<K: AsRef<str>>(&self, key: K) -> Result<Reader> {
+        let (cache, entry) = self.clone().find_key_async(key.as_ref()).await?;
+        Reader::open_hash(cache, entry.integrity).await
+    }
+
+    /// Opens a new file handle into the first layer with content for `sri`.
+    pub async fn open_hash(&self, sri: Integrity) -> Result<Reader> {
+        let cache = self.clone().find_hash_async(&sri).await?;
+        Reader::open_hash(cache, sri).await
+    }
+
+    /// Gets the metadata entry for `key` from the first layer that has one.
+    /// Nothing is copied between layers.
+    pub async fn metadata<K: AsRef<str>>(&self, key: K) -> Result<Option<Metadata>> {
+        let this = self.clone();
+        let key = key.as_ref().to_owned();
+        smol::unblock!(this.metadata_sync(&key))
+    }
+
+    /// Returns true if any layer has content for `sri`.
+    pub async fn exists(&self, sri: &Integrity) -> bool {
+        for cache in self.layers() {
+            if read::has_content_async(cache, sri).await.is_some() {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Writes `data` to the writable cache, indexing it under `key`.
+    pub async fn write<D, K>(&self, key: K, data: D) -> Result<Integrity>
+    where
+        D: AsRef<[u8]>,
+        K: AsRef<str>,
+    {
+        crate::write(&self.writable, key, data).await
+    }
+
+    /// Synchronously reads the entire contents of a cache file into a bytes
+    /// vector, looking the data up by key in each layer.
+    pub fn read_sync<K: AsRef<str>>(&self, key: K) -> Result<Vec<u8>> {
+        let (cache, entry) = self.find_key(key.as_ref())?;
+        crate::read_hash_sync(cache, &entry.integrity)
+    }
+
+    /// Synchronously reads the entire contents of a cache file into a bytes
+    /// vector, looking the data up by its content address in each layer.
+    pub fn read_hash_sync(&self, sri: &Integrity) -> Result<Vec<u8>> {
+        let cache = self.find_hash(sri)?;
+        crate::read_hash_sync(cache, sri)
+    }
+
+    /// Opens a new synchronous file handle into the first layer with an entry
+    /// for `key`.
+    pub fn open_sync<K: AsRef<str>>(&self, key: K) -> Result<SyncReader> {
+        let (cache, entry) = self.find_key(key.as_ref())?;
+        SyncReader::open_hash(cache, entry.integrity)
+    }
+
+    /// Opens a new synchronous file handle into the first layer with content
+    /// for `sri`.
+    pub fn open_hash_sync(&self, sri: Integrity) -> Result<SyncReader> {
+        let cache = self.find_hash(&sri)?;
+        SyncReader::open_hash(cache, sri)
+    }
+
+    /// Synchronously gets the metadata entry for `key` from the first layer
+    /// that has one.
+    pub fn metadata_sync<K: AsRef<str>>(&self, key: K) -> Result<Option<Metadata>> {
+        for cache in self.layers() {
+            if let Some(entry) = index::find(cache, key.as_ref())? {
+                return Ok(Some(entry));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns true if any layer has content for `sri`.
+    pub fn exists_sync(&self, sri: &Integrity) -> bool {
+        self.layers()
+            .any(|cache| read::has_content(cache, sri).is_some())
+    }
+
+    /// Synchronously writes `data` to the writable cache, indexing it under
+    /// `key`.
+    pub fn write_sync<D, K>(&self, key: K, data: D) -> Result<Integrity>
+    where
+        D: AsRef<[u8]>,
+        K: AsRef<str>,
+    {
+        crate::write_sync(&self.writable, key, data)
+    }
+
+    fn layers(&self) -> impl Iterator<Item = &Path> {
+        std::iter::once(self.writable.as_path()).chain(self.fallbacks.iter().map(|p| p.as_path()))
+    }
+
+    /// Finds the first layer with an entry for `key`, returning the cache its
+    /// content should be read from.
+    fn find_key(&self, key: &str) -> Result<(PathBuf, Metadata)> {
+        if let Some(entry) = index::find(&self.writable, key)? {
+            // Best-effort, same as plain reads.
+            let _ = index::touch(&self.writable, &entry);
+            return Ok((self.writable.clone(), entry));
+        }
+        for cache in self.fallbacks.iter() {
+            if let Some(entry) = index::find(cache, key)? {
+                if !self.copy_hits {
+                    return Ok((cache.clone(), entry));
+                }
+                self.copy_content(cache, &entry.integrity)?;
+                index::insert(&self.writable, key, WriteOpts::from_metadata(&entry))?;
+                return Ok((self.writable.clone(), entry));
+            }
+        }
+        Err(Error::EntryNotFound(self.writable.clone(), key.into()))
+    }
+
+    async fn find_key_async(self, key: &str) -> Result<(PathBuf, Metadata)> {
+        let key = key.to_owned();
+        smol::unblock!(self.find_key(&key))
+    }
+
+    /// Finds the first layer with content for `sri`. If none has it, the
+    /// writable cache is returned, so reading from it reports it missing.
+    fn find_hash(&self, sri: &Integrity) -> Result<PathBuf> {
+        if read::has_content(&self.writable, sri).is_some() {
+            return Ok(self.writable.clone());
+        }
+        for cache in self.fallbacks.iter() {
+            if read::has_content(cache, sri).is_some() {
+                if !self.copy_hits {
+                    return Ok(cache.clone());
+                }
+                self.copy_content(cache, sri)?;
+                return Ok(self.writable.clone());
+            }
+        }
+        Ok(self.writable.clone())
+    }
+
+    async fn find_hash_async(self, sri: &Integrity) -> Result<PathBuf> {
+        let sri = sri.clone();
+        smol::unblock!(self.find_hash(&sri))
+    }
+
+    /// Copies content from a fallback into the writable cache, checking its
+    /// integrity along the way.
+    fn copy_content(&self, from: &Path, sri: &Integrity) -> Result<()> {
+        let algorithms: Vec<Algorithm> = sri.hashes.iter().map(|hash| hash.algorithm).collect();
+        let mut writer = algorithms
+            .into_iter()
+            .fold(WriteOpts::new(), |opts, algo| opts.algorithm(algo))
+            .integrity(sri.clone())
+            .open_hash_sync(&self.writable)?;
+        let mut reader = read::Reader::new(from, sri)?;
+        std::io::copy(&mut reader, &mut writer)
+            .with_context(|| format!("Failed to copy content from fallback cache {:?}", from))?;
+        reader.check()?;
+        writer.commit()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use pretty_assertions::assert_eq;
+
+    use crate::index;
+
+    use super::LayeredCache;
+
+    fn bucket_contents(cache: &std::path::Path) -> Vec<String> {
+        index::bucket_paths(cache)
+            .map(|bucket| fs::read_to_string(bucket.unwrap()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_layered_sync() {
+        let tmp = tempfile::tempdir().unwrap();
+        let writable = tmp.path().join("writable");
+        let team = tmp.path().join("team");
+        let image = tmp.path().join("image");
+        crate::write_sync(&team, "team-key", b"team-data").unwrap();
+        crate::write_sync(&image, "team-key", b"shadowed").unwrap();
+        let sri = crate::write_sync(&image, "image-key", b"image-data").unwrap();
+        let team_index = bucket_contents(&team);
+
+        let cache = LayeredCache::new(&writable)
+            .fallback(&team)
+            .fallback(&image);
+        assert_eq!(cache.read_sync("team-key").unwrap(), b"team-data");
+        assert_eq!(cache.read_sync("image-key").unwrap(), b"image-data");
+        assert_eq!(cache.read_hash_sync(&sri).unwrap(), b"image-data");
+        assert!(cache.exists_sync(&sri));
+        assert!(cache.metadata_sync("image-key").unwrap().is_some());
+        assert!(cache.read_sync("missing").is_err());
+
+        // Fallbacks are left alone, and nothing gets copied by default.
+        assert_eq!(bucket_contents(&team), team_index);
+        assert_eq!(crate::metadata_sync(&writable, "team-key").unwrap(), None);
+
+        cache.write_sync("new-key", b"new-data").unwrap();
+        assert_eq!(crate::read_sync(&writable, "new-key").unwrap(), b"new-data");
+        assert_eq!(crate::metadata_sync(&team, "new-key").unwrap(), None);
+    }
+
+    #[test]
+    fn test_layered_sync_copy_hits() {
+        use std::io::Read;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let writable = tmp.path().join("writable");
+        let team = tmp.path().join("team");
+        let sri = crate::write_sync(&team, "key", b"team-data").unwrap();
+        let hash_only = crate::write_hash_sync(&team, b"hash-only").unwrap();
+
+        let cache = LayeredCache::new(&writable).fallback(&team).copy_hits(true);
+        let mut fd = cache.open_sync("key").unwrap();
+        let mut data = Vec::new();
+        fd.read_to_end(&mut data).unwrap();
+        fd.check().unwrap();
+        assert_eq!(data, b"team-data");
+        assert_eq!(crate::read_sync(&writable, "key").unwrap(), b"team-data");
+        assert_eq!(
+            crate::metadata_sync(&writable, "key")
+                .unwrap()
+                .unwrap()
+                .integrity,
+            sri
+        );
+
+        assert_eq!(cache.read_hash_sync(&hash_only).unwrap(), b"hash-only");
+        assert!(crate::exists_sync(&writable, &hash_only));
+    }
+
+    #[async_attributes::test]
+    async fn test_layered() {
+        use async_std::prelude::*;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let writable = tmp.path().join("writable");
+        let team = tmp.path().join("team");
+        let sri = crate::write(&team, "key", b"team-data").await.unwrap();
+
+        let cache = LayeredCache::new(&writable).fallback(&team);
+        assert_eq!(cache.read("key").await.unwrap(), b"team-data");
+        assert!(cache.exists(&sri).await);
+        let mut fd = cache.open_hash(sri).await.unwrap();
+        let mut data = Vec::new();
+        fd.read_to_end(&mut data).await.unwrap();
+        fd.check().await.unwrap();
+        assert_eq!(data, b"team-data");
+        assert_eq!(crate::metadata(&writable, "key").await.unwrap(), None);
+    }
+}
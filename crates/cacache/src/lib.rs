@@ -121,24 +121,57 @@
 //!   Ok(())
 //! }
 //! ```
+//!
+//! ### Storage backends
+//!
+//! The functions above always use the on-disk layout. `Store` offers the
+//! same basic operations on top of any `Backend`: the on-disk layout
+//! (`FsBackend`), an in-memory one for tests (`MemoryBackend`), or, with the
+//! `sled` feature, a single-file embedded database (`SledBackend`).
+//!
+//! ```no_run
+//! fn main() -> cacache::Result<()> {
+//!   let store = cacache::Store::new(cacache::MemoryBackend::new());
+//!   store.write_sync("key", b"my-data")?;
+//!   assert_eq!(store.read_sync("key")?, b"my-data");
+//!   Ok(())
+//! }
+//! ```
 #![warn(missing_docs, missing_doc_code_examples)]
 
 pub use serde_json::Value;
 pub use ssri::Algorithm;
 
+mod backend;
+mod bundle;
+mod compact;
 mod content;
 mod errors;
+mod evict;
 mod index;
+mod layered;
 
 mod get;
+mod lock;
 mod ls;
 mod put;
 mod rm;
+mod stats;
+mod store;
+mod verify;
 
 pub use errors::{Error, Result};
 pub use index::Metadata;
 
+pub use backend::*;
+pub use bundle::*;
+pub use compact::*;
+pub use evict::*;
 pub use get::*;
+pub use layered::*;
 pub use ls::*;
 pub use put::*;
 pub use rm::*;
+pub use stats::*;
+pub use store::*;
+pub use verify::*;
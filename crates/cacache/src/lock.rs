@@ -0,0 +1,226 @@
+//! Advisory locks shared between every process using a cache.
+//!
+//! Index buckets are appended to in place and rewritten wholesale by
+//! compaction and verification, and content is moved into place and linked
+//! under its aliases in several steps. Each of those operations holds an
+//! exclusive lock on the file it touches, so writers in separate processes
+//! can't interleave with each other.
+//!
+//! Locks are taken on a fixed set of lock files under `{cache}/locks`, picked
+//! by hashing the path being locked. Unrelated paths may occasionally share a
+//! lock file, which only costs a little extra waiting. Locks are released
+//! when the returned `Lock` is dropped. Callers must not take a second lock
+//! while holding one, since the two may share a lock file.
+//!
+//! Locking is only implemented on unix platforms. Elsewhere it's a no-op,
+//! so separate processes writing to the same cache can interleave their
+//! index appends and compactions.
+use std::ffi::OsStr;
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+
+use digest::Digest;
+use sha1::Sha1;
+
+use crate::errors::{Internal, InternalResult};
+
+const LOCK_DIR: &str = "locks";
+
+/// An exclusive lock, held until dropped.
+#[derive(Debug)]
+pub struct Lock {
+    _file: File,
+}
+
+/// Takes an exclusive lock on `target`, which must be a path inside `cache`,
+/// blocking until it's available.
+///
+/// On platforms other than unix this returns immediately without locking
+/// anything, and only the usual atomic content writes protect concurrent
+/// processes.
+pub fn lock(cache: &Path, target: &Path) -> InternalResult<Lock> {
+    let path = lock_path(cache, target);
+    // Safe unwrap. Lock paths always have a parent.
+    fs::create_dir_all(path.parent().unwrap())
+        .with_context(|| format!("Failed to create lock directory for {:?}", path))?;
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)
+        .with_context(|| format!("Failed to open lock file at {:?}", path))?;
+    flock(&file).with_context(|| format!("Failed to lock {:?}", path))?;
+    Ok(Lock { _file: file })
+}
+
+/// Asynchronous version of `lock`.
+pub async fn lock_async(cache: &Path, target: &Path) -> InternalResult<Lock> {
+    let cache = cache.to_owned();
+    let target = target.to_owned();
+    smol::unblock!(lock(&cache, &target))
+}
+
+/// Whether `path`, an entry directly inside a cache directory, holds the
+/// cache's lock files. These need to survive `clear`, or processes waiting
+/// on a lock would stop excluding each other.
+pub fn is_lock_dir(path: &Path) -> bool {
+    path.file_name() == Some(OsStr::new(LOCK_DIR))
+}
+
+fn lock_path(cache: &Path, target: &Path) -> PathBuf {
+    let relative = target.strip_prefix(cache).unwrap_or(target);
+    let mut hasher = Sha1::new();
+    hasher.input(relative.to_string_lossy().as_bytes());
+    let hashed = hex::encode(hasher.result());
+    cache.join(LOCK_DIR).join(&hashed[0..2])
+}
+
+#[cfg(unix)]
+fn flock(file: &File) -> std::io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    loop {
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } == 0 {
+            return Ok(());
+        }
+        let err = std::io::Error::last_os_error();
+        if err.kind() != std::io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn flock(_file: &File) -> std::io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::Path;
+    use std::process::Command;
+
+    use digest::Digest;
+    use pretty_assertions::assert_eq;
+    use sha2::Sha256;
+
+    use crate::index;
+
+    const CACHE_VAR: &str = "CACACHE_LOCK_TEST_CACHE";
+    const ROLE_VAR: &str = "CACACHE_LOCK_TEST_ROLE";
+
+    /// Runs a copy of this test binary against `cache` for each of `roles`,
+    /// all at once, and waits for all of them to succeed.
+    fn run_children(cache: &Path, roles: &[&str]) {
+        let children: Vec<_> = roles
+            .iter()
+            .map(|role| {
+                Command::new(env::current_exe().unwrap())
+                    .args(vec!["lock::tests::child", "--exact", "--nocapture"])
+                    .env(CACHE_VAR, cache)
+                    .env(ROLE_VAR, role)
+                    .spawn()
+                    .unwrap()
+            })
+            .collect();
+        for mut child in children {
+            assert!(child.wait().unwrap().success());
+        }
+    }
+
+    fn data_for(id: &str, n: usize) -> Vec<u8> {
+        match n % 10 {
+            // Shared between processes, so they race to move it into place.
+            0 => b"shared-data".to_vec(),
+            // Big enough to take the in-memory write path.
+            5 => format!("{}-{}", id, n).repeat(150_000).into_bytes(),
+            _ => format!("{}-{}", id, n).repeat(100).into_bytes(),
+        }
+    }
+
+    /// Entry point for child processes. Does nothing when run as a normal
+    /// test.
+    #[test]
+    fn child() {
+        let (cache, role) = match (env::var(CACHE_VAR), env::var(ROLE_VAR)) {
+            (Ok(cache), Ok(role)) => (cache, role),
+            _ => return,
+        };
+        let cache = Path::new(&cache);
+        for n in 0..60 {
+            let key = format!("key-{}", n % 12);
+            match role.as_str() {
+                "clear" => {
+                    crate::clear_sync(cache).unwrap();
+                    continue;
+                }
+                "racy" => {
+                    // Writes racing with `clear` may fail outright, but must
+                    // never leave anything corrupt behind.
+                    let _ = crate::write_sync(cache, &key, data_for(&role, n));
+                }
+                _ => {
+                    crate::write_sync(cache, &key, data_for(&role, n)).unwrap();
+                    if n % 7 == 0 {
+                        crate::remove_sync(cache, &key).unwrap();
+                    }
+                }
+            }
+            // Anything that can be read must be complete and intact.
+            match crate::read_sync(cache, &key) {
+                Ok(_) | Err(crate::Error::EntryNotFound(..)) => {}
+                Err(crate::Error::IntegrityError { .. }) | Err(crate::Error::SizeError(..)) => {
+                    panic!("read corrupted content for {}", key)
+                }
+                // Content may have been cleared out from under the entry.
+                Err(_) if role == "racy" => {}
+                Err(err) => panic!("failed to read {}: {}", key, err),
+            }
+        }
+    }
+
+    fn assert_index_intact(cache: &Path) {
+        for bucket in index::bucket_paths(cache) {
+            let text = fs::read_to_string(bucket.unwrap()).unwrap();
+            for line in text.lines().filter(|line| !line.is_empty()) {
+                let mut parts = line.splitn(2, '\t');
+                let hash = parts.next().unwrap();
+                let json = parts.next().expect("index line without a hash");
+                let mut hasher = Sha256::new();
+                hasher.input(json);
+                assert_eq!(hash, hex::encode(hasher.result()));
+            }
+        }
+    }
+
+    #[test]
+    fn test_concurrent_processes() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        run_children(&dir, &["a", "b", "c", "d"]);
+
+        assert_index_intact(&dir);
+        let stats = crate::verify_sync(&dir).unwrap();
+        assert_eq!(stats.bad_content_count, 0);
+        assert_eq!(stats.missing_content, 0);
+        for n in 0..12 {
+            let key = format!("key-{}", n);
+            if let Some(entry) = crate::metadata_sync(&dir, &key).unwrap() {
+                assert_eq!(crate::read_sync(&dir, &key).unwrap().len(), entry.size);
+            }
+        }
+    }
+
+    #[test]
+    fn test_concurrent_processes_clear() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        run_children(&dir, &["racy", "racy", "racy", "clear"]);
+
+        assert_index_intact(&dir);
+        let stats = crate::verify_sync(&dir).unwrap();
+        assert_eq!(stats.bad_content_count, 0);
+    }
+}
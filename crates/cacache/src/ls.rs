@@ -1,18 +1,116 @@
 //! Functions for iterating over the cache.
 use std::path::Path;
 
+use futures::stream::Stream;
+use ssri::Integrity;
+
 use crate::errors::Result;
 use crate::index;
 
+/// Returns a stream of all cache index entries. Index buckets are read on a
+/// background thread, so this won't block the executor.
+///
+/// ## Example
+/// ```no_run
+/// use async_attributes;
+/// use futures::stream::StreamExt;
+///
+/// #[async_attributes::main]
+/// async fn main() -> cacache::Result<()> {
+///     let mut entries = cacache::list("./my-cache");
+///     while let Some(entry) = entries.next().await {
+///         println!("{}", entry?.key);
+///     }
+///     Ok(())
+/// }
+/// ```
+pub fn list<P: AsRef<Path>>(cache: P) -> impl Stream<Item = Result<index::Metadata>> {
+    smol::Unblock::new(index::ls(cache.as_ref()))
+}
+
+/// Returns a stream of the cache index entries whose keys start with
+/// `prefix`. Index lines for other keys are skipped before they're hashed or
+/// parsed, which makes this cheaper than filtering the output of `list`.
+///
+/// ## Example
+/// ```no_run
+/// use async_attributes;
+/// use futures::stream::StreamExt;
+///
+/// #[async_attributes::main]
+/// async fn main() -> cacache::Result<()> {
+///     let mut entries = cacache::list_prefix("./my-cache", "orogene::pkg::");
+///     while let Some(entry) = entries.next().await {
+///         println!("{}", entry?.key);
+///     }
+///     Ok(())
+/// }
+/// ```
+pub fn list_prefix<P, K>(cache: P, prefix: K) -> impl Stream<Item = Result<index::Metadata>>
+where
+    P: AsRef<Path>,
+    K: AsRef<str>,
+{
+    smol::Unblock::new(index::ls_prefix(cache.as_ref(), prefix.as_ref()))
+}
+
+/// Returns a stream of the cache index entries that point to the content for
+/// `sri`. Content written under several hashes matches any of them, so this
+/// also finds entries whose `Integrity` only shares one hash with `sri`.
+///
+/// ## Example
+/// ```no_run
+/// use async_attributes;
+/// use futures::stream::StreamExt;
+///
+/// #[async_attributes::main]
+/// async fn main() -> cacache::Result<()> {
+///     let sri = cacache::write("./my-cache", "my-key", b"hello").await?;
+///     let mut entries = cacache::list_hash("./my-cache", &sri);
+///     while let Some(entry) = entries.next().await {
+///         println!("{} shares this content", entry?.key);
+///     }
+///     Ok(())
+/// }
+/// ```
+pub fn list_hash<P: AsRef<Path>>(
+    cache: P,
+    sri: &Integrity,
+) -> impl Stream<Item = Result<index::Metadata>> {
+    smol::Unblock::new(index::ls_hash(cache.as_ref(), sri))
+}
+
 /// Returns a synchronous iterator that lists all cache index entries.
 pub fn list_sync<P: AsRef<Path>>(cache: P) -> impl Iterator<Item = Result<index::Metadata>> {
     index::ls(cache.as_ref())
 }
 
+/// Returns a synchronous iterator over the cache index entries whose keys
+/// start with `prefix`.
+pub fn list_prefix_sync<P, K>(cache: P, prefix: K) -> impl Iterator<Item = Result<index::Metadata>>
+where
+    P: AsRef<Path>,
+    K: AsRef<str>,
+{
+    index::ls_prefix(cache.as_ref(), prefix.as_ref())
+}
+
+/// Returns a synchronous iterator over the cache index entries that point to
+/// the content for `sri`.
+pub fn list_hash_sync<P: AsRef<Path>>(
+    cache: P,
+    sri: &Integrity,
+) -> impl Iterator<Item = Result<index::Metadata>> {
+    index::ls_hash(cache.as_ref(), sri)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use futures::stream::StreamExt;
+    use pretty_assertions::assert_eq;
+
     #[test]
     fn test_list_sync() {
         // check that the public interface to list elements can actually use the
@@ -20,9 +118,107 @@ mod tests {
         let tmp = tempfile::tempdir().unwrap();
         let dir = tmp.path().to_owned();
 
-        assert!(list_sync(dir)
+        // A cache that was never written to is just empty.
+        assert_eq!(
+            list_sync(dir)
+                .map(|x| Ok(x?.key))
+                .collect::<Result<Vec<_>>>()
+                .unwrap(),
+            Vec::<String>::new()
+        )
+    }
+
+    #[test]
+    fn test_list_sync_latest() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        crate::write_sync(&dir, "key", b"one").unwrap();
+        let sri = crate::write_sync(&dir, "key", b"two").unwrap();
+        crate::write_sync(&dir, "removed", b"removed").unwrap();
+        crate::remove_sync(&dir, "removed").unwrap();
+
+        let entries = list_sync(&dir).collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].key, "key");
+        assert_eq!(entries[0].integrity, sri);
+    }
+
+    #[test]
+    fn test_list_prefix_sync() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        crate::write_sync(&dir, "orogene::pkg::a", b"a").unwrap();
+        crate::write_sync(&dir, "orogene::pkg::b", b"b").unwrap();
+        crate::write_sync(&dir, "orogene::other", b"other").unwrap();
+        crate::write_sync(&dir, "\"orogene::pkg::quoted", b"quoted").unwrap();
+
+        let mut keys = list_prefix_sync(&dir, "orogene::pkg::")
             .map(|x| Ok(x?.key))
             .collect::<Result<Vec<_>>>()
-            .is_err())
+            .unwrap();
+        keys.sort();
+        assert_eq!(keys, vec!["orogene::pkg::a", "orogene::pkg::b"]);
+    }
+
+    #[async_attributes::test]
+    async fn test_list() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        crate::write(&dir, "hello", b"hello").await.unwrap();
+        crate::write(&dir, "world", b"world").await.unwrap();
+
+        let mut keys = list(&dir)
+            .map(|x| Ok(x?.key))
+            .collect::<Vec<Result<_>>>()
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        keys.sort();
+        assert_eq!(keys, vec![String::from("hello"), String::from("world")]);
+    }
+
+    #[async_attributes::test]
+    async fn test_list_prefix() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        crate::write(&dir, "orogene::pkg::a", b"a").await.unwrap();
+        crate::write(&dir, "other", b"other").await.unwrap();
+
+        let entries = list_prefix(&dir, "orogene::pkg::")
+            .collect::<Vec<Result<_>>>()
+            .await;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].as_ref().unwrap().key, "orogene::pkg::a");
+    }
+
+    #[test]
+    fn test_list_hash_sync() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri = crate::write_sync(&dir, "a", b"shared").unwrap();
+        crate::write_sync(&dir, "b", b"shared").unwrap();
+        crate::write_sync(&dir, "moved", b"shared").unwrap();
+        crate::write_sync(&dir, "moved", b"elsewhere").unwrap();
+        crate::write_sync(&dir, "other", b"other").unwrap();
+
+        let mut keys = list_hash_sync(&dir, &sri)
+            .map(|x| Ok(x?.key))
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        keys.sort();
+        assert_eq!(keys, vec!["a", "b"]);
+    }
+
+    #[async_attributes::test]
+    async fn test_list_hash() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri = crate::write(&dir, "a", b"shared").await.unwrap();
+        crate::write(&dir, "other", b"other").await.unwrap();
+
+        let entries = list_hash(&dir, &sri).collect::<Vec<Result<_>>>().await;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].as_ref().unwrap().key, "a");
     }
 }
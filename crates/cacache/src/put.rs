@@ -2,6 +2,7 @@
 use std::io::prelude::*;
 use std::path::{Path, PathBuf};
 use std::pin::Pin;
+use std::time::Duration;
 
 use futures::prelude::*;
 
@@ -153,6 +154,9 @@ impl Writer {
             if size != self.written {
                 return Err(Error::SizeError(size, self.written));
             }
+        } else {
+            // Record the size of streamed writes, too.
+            self.opts.size = Some(self.written);
         }
         if let Some(key) = self.key {
             index::insert_async(&cache, &key, self.opts).await
@@ -222,14 +226,32 @@ where
     writer.commit()
 }
 
+/// Compression to apply to content as it's written to disk.
+///
+/// Compression is transparent to readers: integrity hashes are always
+/// calculated over the uncompressed data, and every read function hands back
+/// the original bytes, no matter how the content was stored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    /// Store content as-is. This is the default, and is required for content
+    /// to be linked into place with `link`/`link_hash` without copying.
+    None,
+    /// Compress content using the snappy framing format. Cheap enough to
+    /// keep up with disk writes, and a good fit for compressible data such
+    /// as JSON documents.
+    Snappy,
+}
+
 /// Builder for options and flags for opening a new cache file to write data into.
 #[derive(Clone, Default)]
 pub struct WriteOpts {
-    pub(crate) algorithm: Option<Algorithm>,
+    pub(crate) algorithms: Vec<Algorithm>,
     pub(crate) sri: Option<Integrity>,
     pub(crate) size: Option<usize>,
     pub(crate) time: Option<u128>,
     pub(crate) metadata: Option<Value>,
+    pub(crate) ttl: Option<u128>,
+    pub(crate) compression: Option<Compression>,
 }
 
 impl WriteOpts {
@@ -238,6 +260,20 @@ impl WriteOpts {
         Default::default()
     }
 
+    /// Creates options that write a copy of an existing entry, keeping its
+    /// integrity, size, timestamp, metadata, and expiry time.
+    pub(crate) fn from_metadata(entry: &index::Metadata) -> WriteOpts {
+        let mut opts = WriteOpts::new()
+            .integrity(entry.integrity.clone())
+            .size(entry.size)
+            .time(entry.time)
+            .metadata(entry.metadata.clone());
+        opts.ttl = entry
+            .expires
+            .map(|expires| expires.saturating_sub(entry.time));
+        opts
+    }
+
     /// Opens the file handle for writing, returning an Writer instance.
     pub async fn open<P, K>(self, cache: P, key: K) -> Result<Writer>
     where
@@ -250,8 +286,9 @@ impl WriteOpts {
             written: 0,
             writer: write::Writer::new_async(
                 cache.as_ref().to_owned(),
-                *self.algorithm.as_ref().unwrap_or(&Algorithm::Sha256),
+                &self.algorithms,
                 None,
+                self.compression,
             )
             .await?,
             opts: self,
@@ -269,8 +306,9 @@ impl WriteOpts {
             written: 0,
             writer: write::Writer::new_async(
                 cache.as_ref().to_owned(),
-                *self.algorithm.as_ref().unwrap_or(&Algorithm::Sha256),
+                &self.algorithms,
                 self.size,
+                self.compression,
             )
             .await?,
             opts: self,
@@ -289,8 +327,9 @@ impl WriteOpts {
             written: 0,
             writer: write::Writer::new(
                 cache.as_ref().to_owned(),
-                *self.algorithm.as_ref().unwrap_or(&Algorithm::Sha256),
+                &self.algorithms,
                 self.size,
+                self.compression,
             )?,
             opts: self,
         })
@@ -307,16 +346,22 @@ impl WriteOpts {
             written: 0,
             writer: write::Writer::new(
                 cache.as_ref().to_owned(),
-                *self.algorithm.as_ref().unwrap_or(&Algorithm::Sha256),
+                &self.algorithms,
                 self.size,
+                self.compression,
             )?,
             opts: self,
         })
     }
 
-    /// Configures the algorithm to write data under.
+    /// Configures the algorithm to write data under. Can be called more than
+    /// once to calculate several hashes in a single pass, in which case the
+    /// resulting `Integrity` holds all of them, and the content can be read
+    /// back using any one of them on its own. Defaults to `Algorithm::Sha256`.
     pub fn algorithm(mut self, algo: Algorithm) -> Self {
-        self.algorithm = Some(algo);
+        if !self.algorithms.contains(&algo) {
+            self.algorithms.push(algo);
+        }
         self
     }
 
@@ -341,6 +386,21 @@ impl WriteOpts {
         self
     }
 
+    /// Sets the compression to apply to content as it's written to disk. See
+    /// `Compression` for the available options. Defaults to no compression.
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = Some(compression);
+        self
+    }
+
+    /// Sets how long the entry stays fresh after it's written. Once that's
+    /// passed, `read_fresh` and `metadata_fresh` report it as stale, though
+    /// it's still kept around and readable.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl.as_millis());
+        self
+    }
+
     /// Sets the expected integrity hash of the written data. If there's a
     /// mismatch between this Integrity and the one calculated by the write,
     /// `put.commit()` will error.
@@ -439,6 +499,9 @@ impl SyncWriter {
             if size != self.written {
                 return Err(Error::SizeError(size, self.written));
             }
+        } else {
+            // Record the size of streamed writes, too.
+            self.opts.size = Some(self.written);
         }
         if let Some(key) = self.key {
             index::insert(&cache, &key, self.opts)
@@ -469,4 +532,36 @@ mod tests {
         let data = crate::read_sync(&dir, "hello").unwrap();
         assert_eq!(data, b"hello");
     }
+
+    #[test]
+    fn multi_algorithm_sync() {
+        use std::io::Write;
+
+        use ssri::{Algorithm, IntegrityOpts};
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let mut fd = crate::WriteOpts::new()
+            .algorithm(Algorithm::Sha512)
+            .algorithm(Algorithm::Sha1)
+            .open_sync(&dir, "hello")
+            .unwrap();
+        fd.write_all(b"hello").unwrap();
+        let sri = fd.commit().unwrap();
+        assert_eq!(sri.hashes.len(), 2);
+
+        let mut sha1 = IntegrityOpts::new().algorithm(Algorithm::Sha1);
+        sha1.input(b"hello");
+        let sha1 = sha1.result();
+        let mut sha512 = IntegrityOpts::new().algorithm(Algorithm::Sha512);
+        sha512.input(b"hello");
+        let sha512 = sha512.result();
+        assert_eq!(crate::read_hash_sync(&dir, &sha1).unwrap(), b"hello");
+        assert_eq!(crate::read_hash_sync(&dir, &sha512).unwrap(), b"hello");
+        assert_eq!(crate::read_sync(&dir, "hello").unwrap(), b"hello");
+
+        crate::remove_hash_sync(&dir, &sri).unwrap();
+        assert!(!crate::exists_sync(&dir, &sha1));
+        assert!(!crate::exists_sync(&dir, &sha512));
+    }
 }
@@ -1,14 +1,15 @@
 //! Functions for removing things from the cache.
+use std::collections::HashSet;
 use std::fs;
+use std::io::ErrorKind;
 use std::path::Path;
 
-use async_std::fs as afs;
-
 use ssri::Integrity;
 
-use crate::content::rm;
+use crate::content::{read, rm};
 use crate::errors::{Internal, Result};
 use crate::index;
+use crate::lock;
 
 /// Removes an individual index metadata entry. The associated content will be
 /// left in the cache.
@@ -42,7 +43,8 @@ where
 }
 
 /// Removes an individual content entry. Any index entries pointing to this
-/// content will become invalidated.
+/// content will become invalidated. Use `RemoveOpts` to find out which ones,
+/// or to remove them along with the content.
 ///
 /// ## Example
 /// ```no_run
@@ -69,8 +71,37 @@ pub async fn remove_hash<P: AsRef<Path>>(cache: P, sri: &Integrity) -> Result<()
     Ok(rm::rm_async(cache.as_ref(), &sri).await?)
 }
 
+/// Removes every index entry for which `predicate` returns true, returning
+/// the removed entries. Their content is left in the cache. Use `RemoveOpts`
+/// to also remove content that's no longer referenced.
+///
+/// ## Example
+/// ```no_run
+/// use std::time::{SystemTime, UNIX_EPOCH};
+/// use async_attributes;
+///
+/// #[async_attributes::main]
+/// async fn main() -> cacache::Result<()> {
+///     let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis();
+///     let week = 7 * 24 * 60 * 60 * 1000;
+///     cacache::remove_where("./my-cache", move |entry| {
+///         entry.key.starts_with("packument:") && now - entry.time > week
+///     })
+///     .await?;
+///     Ok(())
+/// }
+/// ```
+pub async fn remove_where<P, F>(cache: P, predicate: F) -> Result<Vec<index::Metadata>>
+where
+    P: AsRef<Path>,
+    F: FnMut(&index::Metadata) -> bool + Send + 'static,
+{
+    RemoveOpts::new().remove_where(cache, predicate).await
+}
+
 /// Removes entire contents of the cache, including temporary files, the entry
-/// index, and all content data.
+/// index, and all content data. The cache's lock files are left in place, so
+/// other processes using the cache stay coordinated.
 ///
 /// ## Example
 /// ```no_run
@@ -92,12 +123,8 @@ pub async fn remove_hash<P: AsRef<Path>>(cache: P, sri: &Integrity) -> Result<()
 /// }
 /// ```
 pub async fn clear<P: AsRef<Path>>(cache: P) -> Result<()> {
-    for entry in cache.as_ref().read_dir().to_internal()? {
-        if let Ok(entry) = entry {
-            afs::remove_dir_all(entry.path()).await.to_internal()?;
-        }
-    }
-    Ok(())
+    let cache = cache.as_ref().to_owned();
+    smol::unblock!(clear_sync(&cache))
 }
 
 /// Removes an individual index entry synchronously. The associated content
@@ -155,6 +182,27 @@ pub fn remove_hash_sync<P: AsRef<Path>>(cache: P, sri: &Integrity) -> Result<()>
     Ok(rm::rm(cache.as_ref(), &sri)?)
 }
 
+/// Synchronously removes every index entry for which `predicate` returns
+/// true, returning the removed entries. Their content is left in the cache.
+/// Use `RemoveOpts` to also remove content that's no longer referenced.
+///
+/// ## Example
+/// ```no_run
+/// fn main() -> cacache::Result<()> {
+///     cacache::remove_where_sync("./my-cache", |entry| {
+///         entry.metadata["orogene-version"] == "0.1.0"
+///     })?;
+///     Ok(())
+/// }
+/// ```
+pub fn remove_where_sync<P, F>(cache: P, predicate: F) -> Result<Vec<index::Metadata>>
+where
+    P: AsRef<Path>,
+    F: FnMut(&index::Metadata) -> bool,
+{
+    RemoveOpts::new().remove_where_sync(cache, predicate)
+}
+
 /// Removes entire contents of the cache synchronously, including temporary
 /// files, the entry index, and all content data.
 ///
@@ -178,12 +226,176 @@ pub fn remove_hash_sync<P: AsRef<Path>>(cache: P, sri: &Integrity) -> Result<()>
 pub fn clear_sync<P: AsRef<Path>>(cache: P) -> Result<()> {
     for entry in cache.as_ref().read_dir().to_internal()? {
         if let Ok(entry) = entry {
-            fs::remove_dir_all(entry.path()).to_internal()?;
+            let path = entry.path();
+            if lock::is_lock_dir(&path) {
+                continue;
+            }
+            remove_dir_all(&path).to_internal()?;
         }
     }
     Ok(())
 }
 
+/// Removes a directory tree that other processes may be writing into or
+/// clearing at the same time.
+fn remove_dir_all(path: &Path) -> std::io::Result<()> {
+    let mut attempts = 0;
+    loop {
+        match fs::remove_dir_all(path) {
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+            // Something was written into the tree while it was being removed.
+            Err(_) if attempts < 3 => attempts += 1,
+            res => return res,
+        }
+    }
+}
+
+/// Builder for options used when removing content from the cache.
+#[derive(Clone, Debug, Default)]
+pub struct RemoveOpts {
+    pub(crate) remove_entries: bool,
+    pub(crate) gc_content: bool,
+}
+
+impl RemoveOpts {
+    /// Creates a default set of removal options. By default, index entries
+    /// pointing to removed content are left in place, and so is content
+    /// pointed to by removed entries.
+    pub fn new() -> RemoveOpts {
+        Default::default()
+    }
+
+    /// Sets whether index entries pointing to the removed content should be
+    /// removed along with it, instead of being left dangling.
+    pub fn remove_entries(mut self, remove_entries: bool) -> Self {
+        self.remove_entries = remove_entries;
+        self
+    }
+
+    /// Sets whether `remove_where` should also remove the content of the
+    /// entries it removes, if no remaining entry points to it.
+    ///
+    /// Content written for a new entry while this runs may be removed before
+    /// the entry is indexed, leaving the new entry dangling.
+    pub fn gc_content(mut self, gc_content: bool) -> Self {
+        self.gc_content = gc_content;
+        self
+    }
+
+    /// Removes the content for `sri`, returning the index entries that
+    /// pointed to it. Those entries have been removed as well if
+    /// `remove_entries` was set, and are otherwise now dangling.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// use async_attributes;
+    ///
+    /// #[async_attributes::main]
+    /// async fn main() -> cacache::Result<()> {
+    ///     let sri = cacache::write("./my-cache", "my-key", b"hello").await?;
+    ///
+    ///     let removed = cacache::RemoveOpts::new()
+    ///         .remove_entries(true)
+    ///         .remove_hash("./my-cache", &sri)
+    ///         .await?;
+    ///     assert_eq!(removed[0].key, "my-key");
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn remove_hash<P: AsRef<Path>>(
+        self,
+        cache: P,
+        sri: &Integrity,
+    ) -> Result<Vec<index::Metadata>> {
+        let cache = cache.as_ref().to_owned();
+        let sri = sri.clone();
+        smol::unblock!(self.remove_hash_sync(&cache, &sri))
+    }
+
+    /// Synchronously removes the content for `sri`, returning the index
+    /// entries that pointed to it. See `remove_hash` for details.
+    pub fn remove_hash_sync<P: AsRef<Path>>(
+        self,
+        cache: P,
+        sri: &Integrity,
+    ) -> Result<Vec<index::Metadata>> {
+        let cache = cache.as_ref();
+        let entries = index::ls_hash(cache, sri).collect::<Result<Vec<_>>>()?;
+        if self.remove_entries {
+            for entry in entries.iter() {
+                index::delete(cache, &entry.key)?;
+            }
+        }
+        rm::rm(cache, sri)?;
+        Ok(entries)
+    }
+
+    /// Removes every index entry for which `predicate` returns true,
+    /// returning the removed entries. If `gc_content` was set, content that
+    /// no remaining entry points to is removed as well.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// use async_attributes;
+    ///
+    /// #[async_attributes::main]
+    /// async fn main() -> cacache::Result<()> {
+    ///     let removed = cacache::RemoveOpts::new()
+    ///         .gc_content(true)
+    ///         .remove_where("./my-cache", |entry| entry.size > 1024 * 1024)
+    ///         .await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn remove_where<P, F>(self, cache: P, predicate: F) -> Result<Vec<index::Metadata>>
+    where
+        P: AsRef<Path>,
+        F: FnMut(&index::Metadata) -> bool + Send + 'static,
+    {
+        let cache = cache.as_ref().to_owned();
+        smol::unblock!(self.remove_where_sync(&cache, predicate))
+    }
+
+    /// Synchronously removes every index entry for which `predicate` returns
+    /// true, returning the removed entries. See `remove_where` for details.
+    pub fn remove_where_sync<P, F>(self, cache: P, mut predicate: F) -> Result<Vec<index::Metadata>>
+    where
+        P: AsRef<Path>,
+        F: FnMut(&index::Metadata) -> bool,
+    {
+        let cache = cache.as_ref();
+        let mut removed = Vec::new();
+        for entry in index::ls(cache) {
+            let entry = entry?;
+            if predicate(&entry) {
+                index::delete(cache, &entry.key)?;
+                removed.push(entry);
+            }
+        }
+        if self.gc_content && !removed.is_empty() {
+            let mut referenced = HashSet::new();
+            for entry in index::ls(cache) {
+                for hash in entry?.integrity.hashes {
+                    referenced.insert(hash.to_string());
+                }
+            }
+            for entry in removed.iter() {
+                let sri = &entry.integrity;
+                let in_use = sri
+                    .hashes
+                    .iter()
+                    .any(|hash| referenced.contains(&hash.to_string()));
+                // Several removed entries may share the same content.
+                if !in_use && read::has_content(cache, sri).is_some() {
+                    rm::rm(cache, sri)?;
+                }
+            }
+        }
+        Ok(removed)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use async_std::task;
@@ -284,4 +496,133 @@ mod tests {
         let data_exists = crate::exists_sync(&dir, &sri);
         assert_eq!(data_exists, false);
     }
+
+    #[test]
+    fn test_remove_hash_sync_dangling() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri = crate::write_sync(&dir, "key", b"my-data").unwrap();
+        crate::write_sync(&dir, "other", b"my-data").unwrap();
+
+        let dangling = crate::RemoveOpts::new()
+            .remove_hash_sync(&dir, &sri)
+            .unwrap();
+        assert_eq!(dangling.len(), 2);
+        assert!(crate::metadata_sync(&dir, "key").unwrap().is_some());
+        assert_eq!(crate::exists_sync(&dir, &sri), false);
+    }
+
+    #[test]
+    fn test_remove_hash_sync_shared_alias() {
+        use std::io::Write;
+
+        use ssri::Algorithm;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let write = |key: &str, algorithms: &[Algorithm]| {
+            let mut opts = crate::WriteOpts::new();
+            for algo in algorithms {
+                opts = opts.algorithm(*algo);
+            }
+            let mut fd = opts.open_sync(&dir, key).unwrap();
+            fd.write_all(b"my-data").unwrap();
+            fd.commit().unwrap()
+        };
+        let both = write("both", &[Algorithm::Sha512, Algorithm::Sha1]);
+        let sha1 = write("sha1", &[Algorithm::Sha1]);
+
+        crate::remove_hash_sync(&dir, &both).unwrap();
+        // "sha1" reads its content from what was also an alias of "both".
+        assert_eq!(crate::read_sync(&dir, "sha1").unwrap(), b"my-data");
+
+        let both = write("both", &[Algorithm::Sha512, Algorithm::Sha1]);
+        crate::remove_sync(&dir, "sha1").unwrap();
+        crate::remove_hash_sync(&dir, &both).unwrap();
+        assert_eq!(crate::exists_sync(&dir, &sha1), false);
+    }
+
+    #[test]
+    fn test_remove_where_sync() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let old = crate::WriteOpts::new()
+            .time(1000)
+            .open_sync(&dir, "packument:old")
+            .and_then(|mut fd| {
+                std::io::Write::write_all(&mut fd, b"old-data").unwrap();
+                fd.commit()
+            })
+            .unwrap();
+        let shared = crate::write_sync(&dir, "packument:new", b"shared-data").unwrap();
+        crate::write_sync(&dir, "tarball:new", b"shared-data").unwrap();
+
+        let removed = crate::remove_where_sync(&dir, |entry| entry.time < 2000).unwrap();
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].key, "packument:old");
+        assert_eq!(crate::metadata_sync(&dir, "packument:old").unwrap(), None);
+        assert_eq!(crate::exists_sync(&dir, &old), true);
+
+        let removed = crate::RemoveOpts::new()
+            .gc_content(true)
+            .remove_where_sync(&dir, |entry| entry.key.starts_with("packument:"))
+            .unwrap();
+        assert_eq!(removed.len(), 1);
+        // Still used by the tarball entry.
+        assert_eq!(crate::exists_sync(&dir, &shared), true);
+        assert!(crate::metadata_sync(&dir, "tarball:new").unwrap().is_some());
+
+        let removed = crate::RemoveOpts::new()
+            .gc_content(true)
+            .remove_where_sync(&dir, |_| true)
+            .unwrap();
+        assert_eq!(removed.len(), 1);
+        assert_eq!(crate::exists_sync(&dir, &shared), false);
+        // Only content of entries removed by this call is collected.
+        assert_eq!(crate::exists_sync(&dir, &old), true);
+    }
+
+    #[async_attributes::test]
+    async fn test_remove_where() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        for (key, version) in &[("a", "0.1.0"), ("b", "0.2.0"), ("c", "0.1.0")] {
+            crate::WriteOpts::new()
+                .metadata(serde_json::json!({ "orogene-version": version }))
+                .open(&dir, key)
+                .await
+                .unwrap()
+                .commit()
+                .await
+                .unwrap();
+        }
+
+        let removed =
+            crate::remove_where(&dir, |entry| entry.metadata["orogene-version"] == "0.1.0")
+                .await
+                .unwrap();
+        let mut keys: Vec<_> = removed.into_iter().map(|entry| entry.key).collect();
+        keys.sort();
+        assert_eq!(keys, vec!["a", "c"]);
+        assert!(crate::metadata(&dir, "b").await.unwrap().is_some());
+    }
+
+    #[async_attributes::test]
+    async fn test_remove_hash_cascade() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri = crate::write(&dir, "key", b"my-data").await.unwrap();
+        crate::write(&dir, "other", b"other-data").await.unwrap();
+
+        let removed = crate::RemoveOpts::new()
+            .remove_entries(true)
+            .remove_hash(&dir, &sri)
+            .await
+            .unwrap();
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].key, "key");
+        assert_eq!(crate::metadata(&dir, "key").await.unwrap(), None);
+        assert!(crate::metadata(&dir, "other").await.unwrap().is_some());
+        assert_eq!(crate::exists(&dir, &sri).await, false);
+    }
 }
@@ -0,0 +1,316 @@
+//! Functions for reporting on the state of a cache.
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+use serde_derive::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+use crate::content::path;
+use crate::errors::{Internal, Result};
+use crate::index::{self, Metadata};
+
+/// A report on what's in a cache and how much space it takes up. Serializes
+/// to JSON with `serde_json`.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct CacheStats {
+    /// Number of live index entries.
+    pub entries: usize,
+    /// Number of distinct pieces of content that live entries point to.
+    pub unique_content: usize,
+    /// Total size of the data that live entries point to, counting shared
+    /// content once per entry.
+    pub logical_size: u64,
+    /// Bytes taken up by content files on disk, including content no entry
+    /// points to. Hard-linked files are only counted once.
+    pub content_disk_size: u64,
+    /// Bytes taken up by index buckets on disk.
+    pub index_disk_size: u64,
+    /// `logical_size` divided by `content_disk_size`, or 0 for an empty
+    /// cache. Above 1, deduplication and compression are saving space.
+    pub dedupe_ratio: f64,
+    /// Live entries grouped by the part of their key before the prefix
+    /// separator. Keys without a separator are grouped under `""`.
+    pub prefixes: BTreeMap<String, PrefixStats>,
+    /// The live entry with the earliest write time.
+    pub oldest: Option<EntryStats>,
+    /// The live entry with the latest write time.
+    pub newest: Option<EntryStats>,
+    /// Number of superseded, deleted, or corrupted index lines that `compact`
+    /// would remove.
+    pub pending_compaction: usize,
+}
+
+/// Stats for the live entries sharing a key prefix.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct PrefixStats {
+    /// Number of live entries with this prefix.
+    pub entries: usize,
+    /// Total size of the data these entries point to.
+    pub logical_size: u64,
+}
+
+/// A short description of a single entry.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct EntryStats {
+    /// Key the entry is stored under.
+    pub key: String,
+    /// Timestamp in unix milliseconds when the entry was written.
+    pub time: u128,
+    /// Size of the data the entry points to.
+    pub size: usize,
+}
+
+impl From<&Metadata> for EntryStats {
+    fn from(entry: &Metadata) -> Self {
+        EntryStats {
+            key: entry.key.clone(),
+            time: entry.time,
+            size: entry.size,
+        }
+    }
+}
+
+/// Reports on the state of `cache`, grouping keys by the part before their
+/// first `:`. Use `StatsOpts` to group them differently.
+///
+/// ## Example
+/// ```no_run
+/// use async_attributes;
+///
+/// #[async_attributes::main]
+/// async fn main() -> cacache::Result<()> {
+///     let stats = cacache::stats("./my-cache").await?;
+///     println!("{}", serde_json::to_string_pretty(&stats).unwrap());
+///     Ok(())
+/// }
+/// ```
+pub async fn stats<P: AsRef<Path>>(cache: P) -> Result<CacheStats> {
+    StatsOpts::new().stats(cache).await
+}
+
+/// Synchronously reports on the state of `cache`. See `stats` for details.
+///
+/// ## Example
+/// ```no_run
+/// fn main() -> cacache::Result<()> {
+///     let stats = cacache::stats_sync("./my-cache")?;
+///     println!("{} entries, {} bytes of content", stats.entries, stats.content_disk_size);
+///     Ok(())
+/// }
+/// ```
+pub fn stats_sync<P: AsRef<Path>>(cache: P) -> Result<CacheStats> {
+    StatsOpts::new().stats_sync(cache)
+}
+
+/// Builder for options used when reporting on a cache.
+#[derive(Clone, Debug)]
+pub struct StatsOpts {
+    pub(crate) separator: String,
+}
+
+impl Default for StatsOpts {
+    fn default() -> Self {
+        StatsOpts {
+            separator: String::from(":"),
+        }
+    }
+}
+
+impl StatsOpts {
+    /// Creates a default set of options, grouping keys by the part before
+    /// their first `:`.
+    pub fn new() -> StatsOpts {
+        Default::default()
+    }
+
+    /// Sets the separator that ends the prefix keys are grouped by.
+    pub fn prefix_separator<S: AsRef<str>>(mut self, separator: S) -> Self {
+        self.separator = separator.as_ref().to_owned();
+        self
+    }
+
+    /// Reports on the state of `cache`.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// use async_attributes;
+    ///
+    /// #[async_attributes::main]
+    /// async fn main() -> cacache::Result<()> {
+    ///     let stats = cacache::StatsOpts::new()
+    ///         .prefix_separator("/")
+    ///         .stats("./my-cache")
+    ///         .await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn stats<P: AsRef<Path>>(self, cache: P) -> Result<CacheStats> {
+        let cache = cache.as_ref().to_owned();
+        smol::unblock!(self.stats_sync(&cache))
+    }
+
+    /// Synchronously reports on the state of `cache`.
+    pub fn stats_sync<P: AsRef<Path>>(self, cache: P) -> Result<CacheStats> {
+        let cache = cache.as_ref();
+        let mut stats = CacheStats::default();
+        let mut content = HashSet::new();
+        for bucket in index::bucket_paths(cache) {
+            let bucket = bucket?;
+            let (entries, stale) = index::bucket_usage(&bucket)?;
+            stats.pending_compaction += stale;
+            stats.index_disk_size += file_size(&bucket)?;
+            for entry in entries {
+                stats.entries += 1;
+                stats.logical_size += entry.size as u64;
+                content.insert(entry.integrity.to_string());
+                let prefix = match entry.key.find(self.separator.as_str()) {
+                    Some(end) if !self.separator.is_empty() => &entry.key[..end],
+                    _ => "",
+                };
+                let group = stats.prefixes.entry(prefix.to_owned()).or_default();
+                group.entries += 1;
+                group.logical_size += entry.size as u64;
+                if !matches!(&stats.oldest, Some(e) if entry.time >= e.time) {
+                    stats.oldest = Some(EntryStats::from(&entry));
+                }
+                if !matches!(&stats.newest, Some(e) if entry.time < e.time) {
+                    stats.newest = Some(EntryStats::from(&entry));
+                }
+            }
+        }
+        stats.unique_content = content.len();
+        stats.content_disk_size = content_disk_size(cache)?;
+        if stats.content_disk_size > 0 {
+            stats.dedupe_ratio = stats.logical_size as f64 / stats.content_disk_size as f64;
+        }
+        Ok(stats)
+    }
+}
+
+fn file_size(path: &Path) -> Result<u64> {
+    match fs::metadata(path) {
+        Ok(meta) => Ok(meta.len()),
+        // Removed since it was listed.
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(0),
+        Err(err) => Err(err).with_context(|| format!("Failed to read metadata for {:?}", path))?,
+    }
+}
+
+fn content_disk_size(cache: &Path) -> Result<u64> {
+    let mut seen = HashSet::new();
+    let mut size = 0;
+    for entry in WalkDir::new(path::content_dir(cache)) {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) if err.io_error().map(|e| e.kind()) == Some(ErrorKind::NotFound) => continue,
+            Err(err) => Err(err)
+                .with_context(|| format!("Failed to walk content directory in {:?}", cache))?,
+        };
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let meta = match entry.metadata() {
+            Ok(meta) => meta,
+            Err(_) => continue,
+        };
+        if is_new_file(&mut seen, &meta) {
+            size += meta.len();
+        }
+    }
+    Ok(size)
+}
+
+/// Whether this is the first time `meta`'s file has been seen, so hard links
+/// to it aren't counted twice.
+#[cfg(unix)]
+fn is_new_file(seen: &mut HashSet<(u64, u64)>, meta: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    meta.nlink() == 1 || seen.insert((meta.dev(), meta.ino()))
+}
+
+#[cfg(not(unix))]
+fn is_new_file(_seen: &mut HashSet<(u64, u64)>, _meta: &fs::Metadata) -> bool {
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::{EntryStats, PrefixStats};
+
+    #[test]
+    fn test_stats_sync() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let write = |key: &str, data: &[u8], time: u128| {
+            let mut fd = crate::WriteOpts::new()
+                .time(time)
+                .open_sync(&dir, key)
+                .unwrap();
+            std::io::Write::write_all(&mut fd, data).unwrap();
+            fd.commit().unwrap();
+        };
+        write("packument:a", b"aaaa", 3000);
+        write("packument:b", b"shared", 1000);
+        write("tarball:b", b"shared", 2000);
+        write("bare", b"bare", 4000);
+        write("bare", b"rewritten", 5000);
+        crate::write_hash_sync(&dir, b"unreferenced").unwrap();
+
+        let stats = crate::stats_sync(&dir).unwrap();
+        assert_eq!(stats.entries, 4);
+        assert_eq!(stats.unique_content, 3);
+        assert_eq!(stats.logical_size, 4 + 6 + 6 + 9);
+        assert_eq!(stats.content_disk_size, 4 + 6 + 9 + 4 + 12);
+        assert!(stats.index_disk_size > 0);
+        assert_eq!(stats.pending_compaction, 1);
+        assert_eq!(
+            stats.prefixes.get("packument"),
+            Some(&PrefixStats {
+                entries: 2,
+                logical_size: 10
+            })
+        );
+        assert_eq!(stats.prefixes.get("tarball").unwrap().entries, 1);
+        assert_eq!(stats.prefixes.get("").unwrap().entries, 1);
+        assert_eq!(
+            stats.oldest,
+            Some(EntryStats {
+                key: "packument:b".into(),
+                time: 1000,
+                size: 6
+            })
+        );
+        assert_eq!(stats.newest.as_ref().unwrap().key, "bare");
+
+        crate::compact_sync(&dir).unwrap();
+        assert_eq!(crate::stats_sync(&dir).unwrap().pending_compaction, 0);
+
+        let json: serde_json::Value =
+            serde_json::from_str(&serde_json::to_string(&stats).unwrap()).unwrap();
+        assert_eq!(json["entries"], 4);
+        assert_eq!(json["prefixes"]["packument"]["entries"], 2);
+    }
+
+    #[async_attributes::test]
+    async fn test_stats() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        assert_eq!(crate::stats(&dir).await.unwrap(), Default::default());
+
+        crate::write(&dir, "a/one", b"one").await.unwrap();
+        crate::write(&dir, "a/two", b"one").await.unwrap();
+        let stats = crate::StatsOpts::new()
+            .prefix_separator("/")
+            .stats(&dir)
+            .await
+            .unwrap();
+        assert_eq!(stats.unique_content, 1);
+        assert_eq!(stats.prefixes.get("a").unwrap().entries, 2);
+        assert_eq!(stats.dedupe_ratio, 2.0);
+    }
+}
@@ -0,0 +1,304 @@
+//! A cache handle over a pluggable storage backend.
+use std::path::Path;
+use std::sync::Arc;
+
+use serde_json::json;
+use ssri::Integrity;
+
+use crate::backend::{Backend, FsBackend};
+use crate::errors::{Error, Result};
+use crate::index::{self, Metadata};
+
+/// A cache stored in a `Backend` of your choice.
+///
+/// The path-based functions in this crate always use the on-disk layout.
+/// `Store` offers the same basic operations on top of any backend, such as
+/// `MemoryBackend` for tests, or `SledBackend` to keep the whole cache in a
+/// single file. Data is always checked against its integrity hash when read,
+/// whatever the backend.
+///
+/// Stores are cheap to clone, and clones share the same backend.
+///
+/// ## Example
+/// ```no_run
+/// use async_attributes;
+///
+/// #[async_attributes::main]
+/// async fn main() -> cacache::Result<()> {
+///     let store = cacache::Store::new(cacache::MemoryBackend::new());
+///     store.write("my-key", b"hello").await?;
+///     let data = store.read("my-key").await?;
+///     Ok(())
+/// }
+/// ```
+#[derive(Clone)]
+pub struct Store {
+    backend: Arc<dyn Backend>,
+}
+
+impl Store {
+    /// Creates a store on top of `backend`.
+    pub fn new<B: Backend + 'static>(backend: B) -> Self {
+        Store {
+            backend: Arc::new(backend),
+        }
+    }
+
+    /// Creates a store using the usual on-disk layout in `cache`. It's
+    /// interchangeable with the path-based functions.
+    pub fn open<P: AsRef<Path>>(cache: P) -> Self {
+        Store::new(FsBackend::new(cache))
+    }
+
+    /// Writes `data` to the store, indexing it under `key`.
+    pub async fn write<K, D>(&self, key: K, data: D) -> Result<Integrity>
+    where
+        K: AsRef<str>,
+        D: AsRef<[u8]>,
+    {
+        let this = self.clone();
+        let key = key.as_ref().to_owned();
+        let data = data.as_ref().to_vec();
+        smol::unblock!(this.write_sync(&key, &data))
+    }
+
+    /// Writes `data` to the store, skipping associating a key with it.
+    pub async fn write_hash<D: AsRef<[u8]>>(&self, data: D) -> Result<Integrity> {
+        let this = self.clone();
+        let data = data.as_ref().to_vec();
+        smol::unblock!(this.write_hash_sync(&data))
+    }
+
+    /// Reads the entire contents of the data indexed under `key`.
+    pub async fn read<K: AsRef<str>>(&self, key: K) -> Result<Vec<u8>> {
+        let this = self.clone();
+        let key = key.as_ref().to_owned();
+        smol::unblock!(this.read_sync(&key))
+    }
+
+    /// Reads the entire contents of the data stored for `sri`.
+    pub async fn read_hash(&self, sri: &Integrity) -> Result<Vec<u8>> {
+        let this = self.clone();
+        let sri = sri.clone();
+        smol::unblock!(this.read_hash_sync(&sri))
+    }
+
+    /// Gets the metadata entry for `key`, if there is one.
+    pub async fn metadata<K: AsRef<str>>(&self, key: K) -> Result<Option<Metadata>> {
+        let this = self.clone();
+        let key = key.as_ref().to_owned();
+        smol::unblock!(this.metadata_sync(&key))
+    }
+
+    /// Returns true if the store has content for `sri`.
+    pub async fn exists(&self, sri: &Integrity) -> bool {
+        let this = self.clone();
+        let sri = sri.clone();
+        smol::unblock!(this.exists_sync(&sri))
+    }
+
+    /// Removes the index entry for `key`, leaving its content in place.
+    pub async fn remove<K: AsRef<str>>(&self, key: K) -> Result<()> {
+        let this = self.clone();
+        let key = key.as_ref().to_owned();
+        smol::unblock!(this.remove_sync(&key))
+    }
+
+    /// Removes the content stored for `sri`. Any index entries pointing to it
+    /// become invalidated.
+    pub async fn remove_hash(&self, sri: &Integrity) -> Result<()> {
+        let this = self.clone();
+        let sri = sri.clone();
+        smol::unblock!(this.remove_hash_sync(&sri))
+    }
+
+    /// Lists every index entry in the store, in no particular order.
+    pub async fn list(&self) -> Result<Vec<Metadata>> {
+        let this = self.clone();
+        smol::unblock!(this.list_sync())
+    }
+
+    /// Synchronously writes `data` to the store, indexing it under `key`.
+    pub fn write_sync<K, D>(&self, key: K, data: D) -> Result<Integrity>
+    where
+        K: AsRef<str>,
+        D: AsRef<[u8]>,
+    {
+        let data = data.as_ref();
+        let sri = self.write_hash_sync(data)?;
+        let time = index::now();
+        self.backend.put_entry(&Metadata {
+            key: key.as_ref().to_owned(),
+            integrity: sri.clone(),
+            time,
+            atime: time,
+            expires: None,
+            size: data.len(),
+            metadata: json!(null),
+        })?;
+        Ok(sri)
+    }
+
+    /// Synchronously writes `data` to the store, skipping associating a key
+    /// with it.
+    pub fn write_hash_sync<D: AsRef<[u8]>>(&self, data: D) -> Result<Integrity> {
+        let data = data.as_ref();
+        let sri = Integrity::from(data);
+        if !self.backend.has_content(&sri)? {
+            self.backend.put_content(&sri, data)?;
+        }
+        Ok(sri)
+    }
+
+    /// Synchronously reads the entire contents of the data indexed under
+    /// `key`.
+    pub fn read_sync<K: AsRef<str>>(&self, key: K) -> Result<Vec<u8>> {
+        let key = key.as_ref();
+        let entry = self
+            .backend
+            .get_entry(key)?
+            .ok_or_else(|| Error::EntryNotFound(self.backend.path().to_owned(), key.into()))?;
+        let data = self.read_hash_sync(&entry.integrity)?;
+        if data.len() != entry.size {
+            return Err(Error::SizeError(entry.size, data.len()));
+        }
+        Ok(data)
+    }
+
+    /// Synchronously reads the entire contents of the data stored for `sri`.
+    pub fn read_hash_sync(&self, sri: &Integrity) -> Result<Vec<u8>> {
+        let data = self
+            .backend
+            .get_content(sri)?
+            .ok_or_else(|| Error::EntryNotFound(self.backend.path().to_owned(), sri.to_string()))?;
+        sri.check(&data)?;
+        Ok(data)
+    }
+
+    /// Synchronously gets the metadata entry for `key`, if there is one.
+    pub fn metadata_sync<K: AsRef<str>>(&self, key: K) -> Result<Option<Metadata>> {
+        self.backend.get_entry(key.as_ref())
+    }
+
+    /// Returns true if the store has content for `sri`.
+    pub fn exists_sync(&self, sri: &Integrity) -> bool {
+        self.backend.has_content(sri).unwrap_or(false)
+    }
+
+    /// Synchronously removes the index entry for `key`, leaving its content
+    /// in place.
+    pub fn remove_sync<K: AsRef<str>>(&self, key: K) -> Result<()> {
+        self.backend.remove_entry(key.as_ref())
+    }
+
+    /// Synchronously removes the content stored for `sri`. Any index entries
+    /// pointing to it become invalidated.
+    pub fn remove_hash_sync(&self, sri: &Integrity) -> Result<()> {
+        self.backend.remove_content(sri)
+    }
+
+    /// Synchronously lists every index entry in the store, in no particular
+    /// order.
+    pub fn list_sync(&self) -> Result<Vec<Metadata>> {
+        self.backend.entries()
+    }
+}
+
+impl std::fmt::Debug for Store {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Store")
+            .field("path", &self.backend.path())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::Store;
+    use crate::{FsBackend, MemoryBackend};
+
+    /// Runs the same checks against every backend, so they all behave alike.
+    fn check_backend(store: Store) {
+        let sri = store.write_sync("hello", b"hello").unwrap();
+        assert_eq!(store.read_sync("hello").unwrap(), b"hello");
+        assert_eq!(store.read_hash_sync(&sri).unwrap(), b"hello");
+        let entry = store.metadata_sync("hello").unwrap().unwrap();
+        assert_eq!(entry.integrity, sri);
+        assert_eq!(entry.size, 5);
+        assert!(store.exists_sync(&sri));
+
+        store.write_sync("hello", b"goodbye").unwrap();
+        assert_eq!(store.read_sync("hello").unwrap(), b"goodbye");
+        let other = store.write_hash_sync(b"other").unwrap();
+        let keys: Vec<_> = store
+            .list_sync()
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.key)
+            .collect();
+        assert_eq!(keys, vec!["hello"]);
+
+        store.remove_sync("hello").unwrap();
+        store.remove_sync("hello").unwrap();
+        assert!(matches!(
+            store.read_sync("hello"),
+            Err(crate::Error::EntryNotFound(..))
+        ));
+        assert_eq!(store.read_hash_sync(&sri).unwrap(), b"hello");
+
+        store.remove_hash_sync(&other).unwrap();
+        store.remove_hash_sync(&other).unwrap();
+        assert!(!store.exists_sync(&other));
+        assert!(store.read_hash_sync(&other).is_err());
+    }
+
+    #[test]
+    fn test_memory_backend() {
+        check_backend(Store::new(MemoryBackend::new()));
+    }
+
+    #[test]
+    fn test_fs_backend() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        check_backend(Store::new(FsBackend::new(&dir)));
+        // It's the same cache the path-based functions see.
+        let sri = Store::open(&dir).write_sync("shared", b"shared").unwrap();
+        assert_eq!(crate::read_sync(&dir, "shared").unwrap(), b"shared");
+        assert_eq!(crate::read_hash_sync(&dir, &sri).unwrap(), b"shared");
+    }
+
+    #[cfg(feature = "sled")]
+    #[test]
+    fn test_sled_backend() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("cache.db");
+        check_backend(Store::new(crate::SledBackend::open(&path).unwrap()));
+    }
+
+    #[cfg(feature = "sled")]
+    #[test]
+    fn test_sled_backend_reopen() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("cache.db");
+        let store = Store::new(crate::SledBackend::open(&path).unwrap());
+        store.write_sync("hello", b"hello").unwrap();
+        drop(store);
+        let store = Store::new(crate::SledBackend::open(&path).unwrap());
+        assert_eq!(store.read_sync("hello").unwrap(), b"hello");
+    }
+
+    #[async_attributes::test]
+    async fn test_store() {
+        let store = Store::new(MemoryBackend::new());
+        let sri = store.write("hello", b"hello").await.unwrap();
+        assert_eq!(store.read("hello").await.unwrap(), b"hello");
+        assert_eq!(store.read_hash(&sri).await.unwrap(), b"hello");
+        assert!(store.exists(&sri).await);
+        store.remove("hello").await.unwrap();
+        assert_eq!(store.metadata("hello").await.unwrap(), None);
+        assert_eq!(store.list().await.unwrap(), Vec::new());
+    }
+}
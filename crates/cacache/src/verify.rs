@@ -0,0 +1,348 @@
+//! Functions for verifying and garbage collecting the cache.
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use ssri::Integrity;
+use walkdir::WalkDir;
+
+use crate::content::{path, read};
+use crate::errors::{Internal, Result};
+use crate::index;
+use crate::lock;
+
+/// Statistics about a completed `verify` run.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VerifyStats {
+    /// Number of content files that were read back and passed their
+    /// integrity check.
+    pub verified_content: usize,
+    /// Number of content files removed, either because they were corrupted or
+    /// because no index entry referenced them anymore.
+    pub reclaimed_count: usize,
+    /// Bytes on disk freed by removing content files.
+    pub reclaimed_size: u64,
+    /// Number of content files that failed their integrity check.
+    pub bad_content_count: usize,
+    /// Bytes on disk taken up by content that was kept.
+    pub kept_size: u64,
+    /// Number of content files that index entries pointed to, but which did
+    /// not exist.
+    pub missing_content: usize,
+    /// Number of index entries removed because their content was missing or
+    /// corrupted.
+    pub rejected_entries: usize,
+    /// Number of index entries left in the cache.
+    pub total_entries: usize,
+    /// How long verification took.
+    pub run_time: Duration,
+}
+
+/// Verifies every entry in the cache and garbage collects anything that's no
+/// longer usable. Returns statistics about what was found and removed.
+///
+/// Every piece of content referenced by the index is read back and checked
+/// against its `Integrity`. Corrupted content is deleted, index entries
+/// pointing to missing or corrupted content are dropped, and any content not
+/// referenced by a live index entry is removed. Note that this includes data
+/// written with `write_hash`, since it has no index entry to keep it alive.
+/// The index and content directories left over from the previous version of
+/// cacache (`index-v5` and `content-v2`) are removed, too. Directories from
+/// any other version are left alone.
+///
+/// Content written while this runs may be collected before its index entry
+/// lands, so this is best run while nothing else is writing to the cache.
+///
+/// ## Example
+/// ```no_run
+/// use async_attributes;
+///
+/// #[async_attributes::main]
+/// async fn main() -> cacache::Result<()> {
+///     let stats = cacache::verify("./my-cache").await?;
+///     println!("Reclaimed {} bytes", stats.reclaimed_size);
+///     Ok(())
+/// }
+/// ```
+pub async fn verify<P: AsRef<Path>>(cache: P) -> Result<VerifyStats> {
+    let cache = cache.as_ref().to_owned();
+    smol::unblock!(verify_sync(&cache))
+}
+
+/// Verifies every entry in the cache synchronously and garbage collects
+/// anything that's no longer usable. See `verify` for details.
+///
+/// ## Example
+/// ```no_run
+/// fn main() -> cacache::Result<()> {
+///     let stats = cacache::verify_sync("./my-cache")?;
+///     println!("Reclaimed {} bytes", stats.reclaimed_size);
+///     Ok(())
+/// }
+/// ```
+pub fn verify_sync<P: AsRef<Path>>(cache: P) -> Result<VerifyStats> {
+    let cache = cache.as_ref();
+    let start = Instant::now();
+    let mut stats = VerifyStats::default();
+    // Shared content only needs to be checked once, no matter how many keys
+    // point at it.
+    let mut checked: HashMap<PathBuf, bool> = HashMap::new();
+
+    for bucket in index::bucket_paths(cache) {
+        let bucket = bucket?;
+        // Hold the bucket until it's been rewritten, so entries appended in
+        // the meantime don't get dropped.
+        let _lock = lock::lock(cache, &bucket)?;
+        let entries = index::bucket_metadata(&bucket)?;
+        let before = entries.len();
+        let mut kept = Vec::with_capacity(before);
+        for entry in entries {
+            let cpath = path::stored_content_path(cache, &entry.integrity)
+                .unwrap_or_else(|| path::content_path(cache, &entry.integrity));
+            let valid = match checked.get(&cpath) {
+                Some(valid) => *valid,
+                None => {
+                    let valid = verify_content(cache, &entry.integrity, &cpath, &mut stats)?;
+                    if valid {
+                        // Keep any copies linked under the entry's other
+                        // hashes, too.
+                        for alias in path::all_content_paths(cache, &entry.integrity) {
+                            checked.entry(alias).or_insert(true);
+                        }
+                    }
+                    checked.insert(cpath, valid);
+                    valid
+                }
+            };
+            if valid {
+                kept.push(entry);
+            } else {
+                stats.rejected_entries += 1;
+            }
+        }
+        stats.total_entries += kept.len();
+        if kept.len() != before {
+            index::write_bucket(cache, &bucket, &kept)?;
+        }
+    }
+
+    let content_dir = path::content_dir(cache);
+    if content_dir.exists() {
+        for entry in WalkDir::new(&content_dir) {
+            let entry = entry.to_internal()?;
+            if entry.file_type().is_dir() || checked.contains_key(entry.path()) {
+                continue;
+            }
+            let size = entry.metadata().map(|m| m.len()).unwrap_or(0);
+            fs::remove_file(entry.path()).with_context(|| {
+                format!("Failed to remove orphaned content at {:?}", entry.path())
+            })?;
+            stats.reclaimed_count += 1;
+            stats.reclaimed_size += size;
+        }
+    }
+
+    index::compact_hash_index(cache)?;
+    remove_legacy_dirs(cache, &mut stats)?;
+
+    stats.run_time = start.elapsed();
+    Ok(stats)
+}
+
+/// The index and content directories written by the previous version of
+/// this crate, which stored content compressed. Only these exact versions are
+/// removed, since other versions of cacache may share the same cache root.
+const LEGACY_DIRS: &[&str] = &["index-v5", "content-v2"];
+
+/// Removes the index and content directories listed in `LEGACY_DIRS`, which
+/// nothing reads anymore.
+fn remove_legacy_dirs(cache: &Path, stats: &mut VerifyStats) -> Result<()> {
+    for name in LEGACY_DIRS {
+        let dir = cache.join(name);
+        if !dir.is_dir() {
+            continue;
+        }
+        for entry in WalkDir::new(&dir) {
+            let entry = entry.to_internal()?;
+            if entry.file_type().is_file() {
+                stats.reclaimed_count += 1;
+                stats.reclaimed_size += entry.metadata().map(|m| m.len()).unwrap_or(0);
+            }
+        }
+        fs::remove_dir_all(&dir)
+            .with_context(|| format!("Failed to remove old cache directory {:?}", dir))?;
+    }
+    Ok(())
+}
+
+fn verify_content(
+    cache: &Path,
+    sri: &Integrity,
+    cpath: &Path,
+    stats: &mut VerifyStats,
+) -> Result<bool> {
+    let size = match fs::metadata(cpath) {
+        Ok(meta) => meta.len(),
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            stats.missing_content += 1;
+            return Ok(false);
+        }
+        Err(err) => {
+            return Err(err)
+                .with_context(|| format!("Failed to read content metadata at {:?}", cpath))?
+        }
+    };
+    if content_is_valid(cache, sri) {
+        stats.verified_content += 1;
+        stats.kept_size += size;
+        Ok(true)
+    } else {
+        fs::remove_file(cpath)
+            .with_context(|| format!("Failed to remove corrupted content at {:?}", cpath))?;
+        stats.bad_content_count += 1;
+        stats.reclaimed_count += 1;
+        stats.reclaimed_size += size;
+        Ok(false)
+    }
+}
+
+fn content_is_valid(cache: &Path, sri: &Integrity) -> bool {
+    let mut reader = match read::Reader::new(cache, sri) {
+        Ok(reader) => reader,
+        Err(_) => return false,
+    };
+    std::io::copy(&mut reader, &mut std::io::sink()).is_ok() && reader.check().is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use pretty_assertions::assert_eq;
+
+    use crate::content::path;
+
+    #[test]
+    fn test_verify_sync_clean() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        crate::write_sync(&dir, "key", b"my-data").unwrap();
+        crate::write_sync(&dir, "other", b"my-data").unwrap();
+
+        let stats = crate::verify_sync(&dir).unwrap();
+        assert_eq!(stats.verified_content, 1);
+        assert_eq!(stats.total_entries, 2);
+        assert_eq!(stats.reclaimed_count, 0);
+        assert_eq!(crate::read_sync(&dir, "key").unwrap(), b"my-data");
+    }
+
+    #[test]
+    fn test_verify_sync_corrupted() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri = crate::write_sync(&dir, "key", b"my-data").unwrap();
+        fs::write(path::content_path(&dir, &sri), b"garbage").unwrap();
+
+        let stats = crate::verify_sync(&dir).unwrap();
+        assert_eq!(stats.bad_content_count, 1);
+        assert_eq!(stats.rejected_entries, 1);
+        assert_eq!(stats.reclaimed_count, 1);
+        assert_eq!(stats.reclaimed_size, 7);
+        assert_eq!(crate::metadata_sync(&dir, "key").unwrap(), None);
+        assert_eq!(crate::exists_sync(&dir, &sri), false);
+    }
+
+    #[test]
+    fn test_verify_sync_missing_content() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri = crate::write_sync(&dir, "key", b"my-data").unwrap();
+        crate::write_sync(&dir, "other", b"other-data").unwrap();
+        crate::remove_hash_sync(&dir, &sri).unwrap();
+
+        let stats = crate::verify_sync(&dir).unwrap();
+        assert_eq!(stats.missing_content, 1);
+        assert_eq!(stats.rejected_entries, 1);
+        assert_eq!(stats.total_entries, 1);
+        assert_eq!(crate::metadata_sync(&dir, "key").unwrap(), None);
+        assert_eq!(crate::read_sync(&dir, "other").unwrap(), b"other-data");
+    }
+
+    #[test]
+    fn test_verify_sync_orphaned() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let orphan = crate::write_hash_sync(&dir, b"orphan").unwrap();
+        let sri = crate::write_sync(&dir, "key", b"my-data").unwrap();
+        crate::write_sync(&dir, "removed", b"removed-data").unwrap();
+        crate::remove_sync(&dir, "removed").unwrap();
+
+        let stats = crate::verify_sync(&dir).unwrap();
+        assert_eq!(stats.reclaimed_count, 2);
+        assert_eq!(crate::exists_sync(&dir, &orphan), false);
+        assert_eq!(crate::exists_sync(&dir, &sri), true);
+    }
+
+    #[test]
+    fn test_verify_sync_keeps_aliases() {
+        use std::io::Write;
+
+        use ssri::Algorithm;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let mut fd = crate::WriteOpts::new()
+            .algorithm(Algorithm::Sha512)
+            .algorithm(Algorithm::Sha1)
+            .open_sync(&dir, "key")
+            .unwrap();
+        fd.write_all(b"my-data").unwrap();
+        let sri = fd.commit().unwrap();
+
+        let stats = crate::verify_sync(&dir).unwrap();
+        assert_eq!(stats.reclaimed_count, 0);
+        for alias in path::aliases(&sri) {
+            assert_eq!(crate::read_hash_sync(&dir, &alias).unwrap(), b"my-data");
+        }
+    }
+
+    #[test]
+    fn test_verify_sync_legacy_dirs() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri = crate::write_sync(&dir, "key", b"my-data").unwrap();
+        for old in &[
+            "index-v5/ab/cd",
+            "content-v2/sha512/ab",
+            "index-v4/ab/cd",
+            "content-v1/sha512/ab",
+        ] {
+            fs::create_dir_all(dir.join(old)).unwrap();
+            fs::write(dir.join(old).join("file"), b"old").unwrap();
+        }
+
+        let stats = crate::verify_sync(&dir).unwrap();
+        assert_eq!(stats.reclaimed_count, 2);
+        assert_eq!(stats.reclaimed_size, 6);
+        assert!(!dir.join("index-v5").exists());
+        assert!(!dir.join("content-v2").exists());
+        assert!(dir.join("index-v4/ab/cd/file").exists());
+        assert!(dir.join("content-v1/sha512/ab/file").exists());
+        assert_eq!(crate::read_hash_sync(&dir, &sri).unwrap(), b"my-data");
+        assert_eq!(crate::read_sync(&dir, "key").unwrap(), b"my-data");
+    }
+
+    #[async_attributes::test]
+    async fn test_verify() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_owned();
+        let sri = crate::write(&dir, "key", b"my-data").await.unwrap();
+        fs::write(path::content_path(&dir, &sri), b"garbage").unwrap();
+
+        let stats = crate::verify(&dir).await.unwrap();
+        assert_eq!(stats.bad_content_count, 1);
+        assert_eq!(crate::metadata(&dir, "key").await.unwrap(), None);
+    }
+}
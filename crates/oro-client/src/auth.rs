@@ -0,0 +1,236 @@
+use std::fmt;
+
+use surf::Url;
+
+/// Credentials to send to a registry.
+#[derive(Clone, PartialEq, Eq)]
+pub enum Credentials {
+    /// A bearer token, like npm's `_authToken`.
+    Token(String),
+    /// A username and password, sent with basic auth.
+    Basic { username: String, password: String },
+    /// An already-encoded `username:password` pair, like npm's legacy
+    /// `_auth`. Sent with basic auth as-is.
+    Legacy(String),
+}
+
+impl Credentials {
+    /// The `Authorization` header value for these credentials.
+    pub(crate) fn header(&self) -> String {
+        match self {
+            Credentials::Token(token) => format!("Bearer {}", token),
+            Credentials::Basic { username, password } => format!(
+                "Basic {}",
+                base64::encode(format!("{}:{}", username, password))
+            ),
+            Credentials::Legacy(auth) => format!("Basic {}", auth),
+        }
+    }
+}
+
+// Keep secrets out of logs.
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Credentials::Token(_) => f.write_str("Token(<redacted>)"),
+            Credentials::Basic { username, .. } => f
+                .debug_struct("Basic")
+                .field("username", username)
+                .field("password", &"<redacted>")
+                .finish(),
+            Credentials::Legacy(_) => f.write_str("Legacy(<redacted>)"),
+        }
+    }
+}
+
+/// Credentials for registries, matched against request URLs by prefix, the
+/// way npm matches `//registry.example.com/path/:_authToken` entries.
+///
+/// Requests get the credentials with the longest prefix that matches their
+/// host, port, and path, so they're never sent to a different host, like a
+/// CDN serving a registry's tarballs.
+#[derive(Clone, Debug, Default)]
+pub struct RegistryAuth {
+    entries: Vec<(String, Credentials)>,
+}
+
+impl RegistryAuth {
+    /// Creates an empty set of credentials.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Reads credentials from npm-style config entries, like
+    /// `//registry.example.com/:_authToken`. Supports `_authToken`, `_auth`,
+    /// and `username` with a base64-encoded `_password`. Other entries are
+    /// ignored.
+    pub fn from_config<I, K, V>(config: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        let mut auth = Self::new();
+        let mut usernames = Vec::new();
+        let mut passwords = Vec::new();
+        for (key, value) in config {
+            let key = key.as_ref();
+            let value = value.as_ref().to_owned();
+            let idx = match key.rfind("/:") {
+                Some(idx) if key.starts_with("//") => idx,
+                _ => continue,
+            };
+            let (prefix, field) = (&key[..=idx], &key[idx + 2..]);
+            match field {
+                "_authToken" => auth = auth.add(prefix, Credentials::Token(value)),
+                "_auth" => auth = auth.add(prefix, Credentials::Legacy(value)),
+                "username" => usernames.push((prefix.to_owned(), value)),
+                "_password" => passwords.push((prefix.to_owned(), value)),
+                _ => {}
+            }
+        }
+        for (prefix, username) in usernames {
+            let password = passwords
+                .iter()
+                .find(|(key, _)| *key == prefix)
+                .and_then(|(_, password)| base64::decode(password).ok())
+                .map(|password| String::from_utf8_lossy(&password).into_owned());
+            if let Some(password) = password {
+                auth = auth.add(prefix, Credentials::Basic { username, password });
+            } else {
+                log::warn!("Ignoring username for {} without a valid _password", prefix);
+            }
+        }
+        auth
+    }
+
+    /// Adds credentials for requests under `prefix`, given either as a URL
+    /// or without its scheme, like `//registry.example.com/path/`. Replaces
+    /// any credentials already set for the same prefix.
+    pub fn add(mut self, prefix: impl AsRef<str>, credentials: Credentials) -> Self {
+        let prefix = prefix.as_ref();
+        let url = if prefix.starts_with("//") {
+            format!("https:{}", prefix).parse::<Url>()
+        } else {
+            prefix.parse::<Url>()
+        };
+        match url.ok().and_then(|url| nerf_dart(&url)) {
+            Some(prefix) => {
+                self.entries.retain(|(key, _)| *key != prefix);
+                self.entries.push((prefix, credentials));
+            }
+            None => log::warn!("Ignoring credentials for invalid registry URL {}", prefix),
+        }
+        self
+    }
+
+    /// The credentials to send along with a request to `url`, if any.
+    pub(crate) fn credentials_for(&self, url: &Url) -> Option<&Credentials> {
+        let target = nerf_dart(url)?;
+        self.entries
+            .iter()
+            .filter(|(prefix, _)| target.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, credentials)| credentials)
+    }
+}
+
+/// Reduces `url` to `//host[:port]/path/`, dropping its scheme, default
+/// port, query, and the last path segment, the way npm keys credentials.
+fn nerf_dart(url: &Url) -> Option<String> {
+    let host = url.host_str()?.to_ascii_lowercase();
+    let path = url.path();
+    let dir = &path[..=path.rfind('/')?];
+    Some(match url.port() {
+        Some(port) => format!("//{}:{}{}", host, port, dir),
+        None => format!("//{}{}", host, dir),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    fn header(auth: &RegistryAuth, url: &str) -> Option<String> {
+        auth.credentials_for(&url.parse().unwrap())
+            .map(|credentials| credentials.header())
+    }
+
+    #[test]
+    fn test_prefix_matching() {
+        let auth = RegistryAuth::new()
+            .add("//registry.corp/", Credentials::Token("root".into()))
+            .add(
+                "https://registry.corp/npm/private/",
+                Credentials::Token("private".into()),
+            )
+            .add(
+                "//registry.corp:8443/",
+                Credentials::Legacy("dXNlcjpwYXNz".into()),
+            );
+        let root = Some("Bearer root".to_string());
+        let private = Some("Bearer private".to_string());
+        assert_eq!(header(&auth, "https://registry.corp/lodash"), root);
+        assert_eq!(header(&auth, "https://REGISTRY.corp:443/lodash"), root);
+        assert_eq!(header(&auth, "https://registry.corp/npm/public/x"), root);
+        assert_eq!(
+            header(&auth, "https://registry.corp/npm/private/@scope%2fpkg"),
+            private
+        );
+        assert_eq!(
+            header(
+                &auth,
+                "https://registry.corp/npm/private/pkg/-/pkg-1.0.0.tgz"
+            ),
+            private
+        );
+        assert_eq!(
+            header(&auth, "https://registry.corp:8443/pkg"),
+            Some("Basic dXNlcjpwYXNz".into())
+        );
+        // Different hosts never get the token, however similar.
+        assert_eq!(header(&auth, "https://cdn.registry.corp/pkg.tgz"), None);
+        assert_eq!(header(&auth, "https://registry.corp.evil/pkg"), None);
+        assert_eq!(header(&auth, "https://registry.corpx/pkg"), None);
+        assert_eq!(header(&auth, "https://registry.corp:444/pkg"), None);
+    }
+
+    #[test]
+    fn test_from_config() {
+        let auth = RegistryAuth::from_config(vec![
+            ("registry", "https://registry.corp/"),
+            ("//registry.corp/:_authToken", "token"),
+            ("//legacy.corp/:_auth", "dXNlcjpwYXNz"),
+            ("//basic.corp/npm/:username", "user"),
+            // "pass"
+            ("//basic.corp/npm/:_password", "cGFzcw=="),
+            ("//nopass.corp/:username", "user"),
+        ]);
+        assert_eq!(
+            header(&auth, "https://registry.corp/pkg"),
+            Some("Bearer token".into())
+        );
+        assert_eq!(
+            header(&auth, "https://legacy.corp/pkg"),
+            Some("Basic dXNlcjpwYXNz".into())
+        );
+        assert_eq!(
+            header(&auth, "https://basic.corp/npm/pkg"),
+            Some("Basic dXNlcjpwYXNz".into())
+        );
+        assert_eq!(header(&auth, "https://basic.corp/pkg"), None);
+        assert_eq!(header(&auth, "https://nopass.corp/pkg"), None);
+    }
+
+    #[test]
+    fn test_redacted() {
+        let credentials = Credentials::Basic {
+            username: "user".into(),
+            password: "hunter2".into(),
+        };
+        assert!(!format!("{:?}", credentials).contains("hunter2"));
+        assert!(!format!("{:?}", Credentials::Token("secret".into())).contains("secret"));
+    }
+}
@@ -0,0 +1,158 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use surf::Client;
+
+use crate::auth::RegistryAuth;
+use crate::cache::{CacheMode, HttpCache};
+use crate::http_client::{PoolConfig, PoolingClient, ProxyConfig, TlsConfig};
+use crate::retry::RetryPolicy;
+use crate::OroClient;
+
+/// Builds an `OroClient` with custom settings.
+#[derive(Clone, Debug)]
+pub struct OroClientBuilder {
+    retry: RetryPolicy,
+    cache: Option<PathBuf>,
+    cache_mode: CacheMode,
+    auth: RegistryAuth,
+    pool: PoolConfig,
+    timeout: Option<Duration>,
+    max_redirects: usize,
+}
+
+impl Default for OroClientBuilder {
+    fn default() -> Self {
+        Self {
+            retry: RetryPolicy::default(),
+            cache: None,
+            cache_mode: CacheMode::default(),
+            auth: RegistryAuth::new(),
+            pool: PoolConfig {
+                proxy: ProxyConfig::from_env(),
+                ..Default::default()
+            },
+            timeout: Some(Duration::from_secs(5 * 60)),
+            max_redirects: 20,
+        }
+    }
+}
+
+impl OroClientBuilder {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Sets the policy for retrying requests that fail for transient reasons.
+    pub fn retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Caches `GET` responses in the cacache directory at `cache`, and uses
+    /// them to answer later requests according to the cache mode.
+    pub fn cache(mut self, cache: impl AsRef<Path>) -> Self {
+        self.cache = Some(cache.as_ref().to_owned());
+        self
+    }
+
+    /// Sets how the cache is used. Has no effect unless a cache directory is
+    /// set with `cache`.
+    pub fn cache_mode(mut self, cache_mode: CacheMode) -> Self {
+        self.cache_mode = cache_mode;
+        self
+    }
+
+    /// Sets the credentials to send to registries. Requests that already
+    /// have an `Authorization` header are left alone.
+    pub fn auth(mut self, auth: RegistryAuth) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    /// Sets the proxies to send requests through. By default, they're read
+    /// from the `HTTP_PROXY`, `HTTPS_PROXY` and `NO_PROXY` environment
+    /// variables.
+    pub fn proxy(mut self, proxy: ProxyConfig) -> Self {
+        self.pool.proxy = proxy;
+        self
+    }
+
+    /// Sets the TLS settings for HTTPS connections.
+    pub fn tls(mut self, tls: TlsConfig) -> Self {
+        self.pool.tls = tls;
+        self
+    }
+
+    /// Sets the most connections that can be in use at once, across all
+    /// hosts. Defaults to 200.
+    pub fn max_connections(mut self, max: usize) -> Self {
+        self.pool.max_connections = max;
+        self
+    }
+
+    /// Sets the most connections that can be open to a single host at once.
+    /// Defaults to 50.
+    pub fn max_connections_per_host(mut self, max: usize) -> Self {
+        self.pool.max_connections_per_host = max;
+        self
+    }
+
+    /// Sets how long an unused connection is kept around for reuse.
+    /// Defaults to 15 seconds. `None` keeps them forever.
+    pub fn idle_timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
+        self.pool.idle_timeout = timeout.into();
+        self
+    }
+
+    /// Sets how long to wait for a connection to be established. Defaults to
+    /// 30 seconds.
+    pub fn connect_timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
+        self.pool.connect_timeout = timeout.into();
+        self
+    }
+
+    /// Sets how long to wait for data on a connection before giving up on
+    /// it. Defaults to 60 seconds.
+    pub fn read_timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
+        self.pool.read_timeout = timeout.into();
+        self
+    }
+
+    /// Sets how long to remember what a hostname resolved to. Defaults to
+    /// 60 seconds.
+    pub fn dns_ttl(mut self, ttl: Duration) -> Self {
+        self.pool.dns_ttl = ttl;
+        self
+    }
+
+    /// Sets how long each attempt at a request waits for the response's
+    /// headers. Every retry and redirect gets a timeout of its own, and an
+    /// attempt that times out is retried like any other failure. Reading the
+    /// response body is only limited by the read timeout. Defaults to 5
+    /// minutes.
+    pub fn timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
+        self.timeout = timeout.into();
+        self
+    }
+
+    /// Sets how many redirects to follow before giving up on a request.
+    /// Defaults to 20. With 0, redirect responses are returned as-is.
+    pub fn max_redirects(mut self, max: usize) -> Self {
+        self.max_redirects = max;
+        self
+    }
+
+    pub fn build(self) -> OroClient {
+        OroClient {
+            client: Client::with_http_client(PoolingClient::with_config(self.pool.clone())),
+            retry: self.retry,
+            cache: self.cache.map(HttpCache::new),
+            cache_mode: self.cache_mode,
+            auth: self.auth,
+            pool: self.pool,
+            timeout: self.timeout,
+            max_redirects: self.max_redirects,
+        }
+    }
+}
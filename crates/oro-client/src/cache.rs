@@ -0,0 +1,717 @@
+use std::future::Future;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use futures::channel::{mpsc, oneshot};
+use futures::io::{AsyncRead, AsyncWriteExt, BufReader};
+use futures::ready;
+use futures::sink::Sink;
+use futures::stream::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use surf::http::{self, headers::HeaderValues};
+use surf::{Body, Request, Response, Url};
+
+use crate::redirect::FinalUrl;
+
+/// Controls how `OroClient` uses its HTTP cache. These work like the `cache`
+/// option of the Fetch standard.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CacheMode {
+    /// Use fresh cached responses, revalidate stale ones with the server,
+    /// and cache new responses.
+    #[default]
+    Default,
+    /// Don't use the cache at all, neither for reading nor for writing.
+    NoStore,
+    /// Always go to the network, ignoring cached responses, and cache the
+    /// new response.
+    Reload,
+    /// Revalidate cached responses with the server before using them, even
+    /// fresh ones.
+    NoCache,
+    /// Use cached responses no matter how stale they are, and only go to the
+    /// network if nothing is cached.
+    ForceCache,
+    /// Use cached responses no matter how stale they are, and fail if
+    /// nothing is cached.
+    OnlyIfCached,
+}
+
+/// Headers that only apply to a single connection, or that no longer
+/// describe the body once it's been read out of the cache.
+const UNCACHED_HEADERS: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+    "content-length",
+    "x-local-cache-status",
+];
+
+/// What gets stored in a cache entry's metadata, next to the response body.
+#[derive(Debug, Deserialize, Serialize)]
+struct CachedMeta {
+    /// Where the response came from, after any redirects.
+    url: String,
+    status: u16,
+    headers: Vec<(String, String)>,
+    /// Values of the request headers named by the response's `Vary` header,
+    /// which later requests have to match to use this response.
+    vary: Vec<(String, Option<String>)>,
+}
+
+/// A response read back out of the cache.
+#[derive(Debug)]
+pub(crate) struct CachedResponse {
+    meta: CachedMeta,
+    /// When the response was stored, in unix milliseconds.
+    time: u128,
+    body: Vec<u8>,
+}
+
+impl CachedResponse {
+    /// Whether the response can be used without checking with the server.
+    pub(crate) fn is_fresh(&self) -> bool {
+        let lifetime = match self.freshness_lifetime() {
+            Some(lifetime) => lifetime,
+            None => return false,
+        };
+        let stored_age = self
+            .header("age")
+            .and_then(|age| age.trim().parse::<u64>().ok())
+            .map(Duration::from_secs)
+            .unwrap_or_default();
+        let elapsed = Duration::from_millis(now().saturating_sub(self.time) as u64);
+        stored_age + elapsed < lifetime
+    }
+
+    /// Adds `If-None-Match` and `If-Modified-Since` headers to `req`, so the
+    /// server can answer with a `304 Not Modified` if this response is still
+    /// good.
+    pub(crate) fn add_validators(&self, req: &mut Request) {
+        if let Some(etag) = self.header("etag") {
+            req.insert_header("If-None-Match", etag);
+        }
+        if let Some(modified) = self.header("last-modified") {
+            req.insert_header("If-Modified-Since", modified);
+        }
+    }
+
+    /// Turns this into a regular response. `status` ends up in the
+    /// `X-Local-Cache-Status` header, to tell how the cache was used.
+    pub(crate) fn into_response(self, status: &str) -> Response {
+        let mut res = http::Response::new(self.meta.status);
+        for (name, value) in &self.meta.headers {
+            res.append_header(name.as_str(), value.as_str());
+        }
+        res.insert_header("X-Local-Cache-Status", status);
+        res.set_body(self.body);
+        let mut res = Response::from(res);
+        if let Ok(url) = self.meta.url.parse::<Url>() {
+            res.insert_ext(FinalUrl(url));
+        }
+        res
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.meta
+            .headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn freshness_lifetime(&self) -> Option<Duration> {
+        let cache_control = self.header("cache-control").unwrap_or("");
+        if has_directive(cache_control, "no-cache") {
+            return Some(Duration::from_secs(0));
+        }
+        if let Some(max_age) = directive_value(cache_control, "max-age") {
+            return max_age.parse::<u64>().ok().map(Duration::from_secs);
+        }
+        let expires = httpdate::parse_http_date(self.header("expires")?).ok()?;
+        let date = self
+            .header("date")
+            .and_then(|date| httpdate::parse_http_date(date).ok())
+            .unwrap_or_else(|| UNIX_EPOCH + Duration::from_millis(self.time as u64));
+        Some(expires.duration_since(date).unwrap_or_default())
+    }
+}
+
+/// An HTTP cache stored in a cacache directory.
+#[derive(Clone, Debug)]
+pub(crate) struct HttpCache {
+    path: PathBuf,
+}
+
+impl HttpCache {
+    pub(crate) fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_owned(),
+        }
+    }
+
+    /// Looks up a cached response that can answer `req`. Broken entries are
+    /// treated like missing ones.
+    pub(crate) async fn get(&self, req: &Request) -> Option<CachedResponse> {
+        let key = cache_key(req);
+        let entry = match cacache::metadata(&self.path, &key).await {
+            Ok(Some(entry)) => entry,
+            Ok(None) => return None,
+            Err(err) => {
+                log::warn!("Failed to read HTTP cache entry for {}: {}", req.url(), err);
+                return None;
+            }
+        };
+        let meta: CachedMeta = serde_json::from_value(entry.metadata).ok()?;
+        let vary_matches = meta
+            .vary
+            .iter()
+            .all(|(name, value)| header_string(req.header(name.as_str())) == *value);
+        if !vary_matches {
+            return None;
+        }
+        let body = match cacache::read_hash(&self.path, &entry.integrity).await {
+            Ok(body) => body,
+            Err(err) => {
+                log::warn!("Failed to read cached response for {}: {}", req.url(), err);
+                return None;
+            }
+        };
+        Some(CachedResponse {
+            meta,
+            time: entry.time,
+            body,
+        })
+    }
+
+    /// Stores `res` in the cache if it's cacheable. Returns an equivalent
+    /// response whose body gets written to the cache as it's read, so large
+    /// downloads still stream. The entry is only committed once the whole
+    /// body has been read.
+    pub(crate) fn put(&self, req: &Request, mut res: Response) -> Response {
+        if !is_storable(&res) {
+            return res;
+        }
+        let meta = CachedMeta {
+            url: match res.ext::<FinalUrl>() {
+                Some(FinalUrl(url)) => url.to_string(),
+                None => req.url().to_string(),
+            },
+            status: res.status().into(),
+            headers: res
+                .iter()
+                .filter(|(name, _)| !UNCACHED_HEADERS.contains(&name.as_str()))
+                .flat_map(|(name, values)| {
+                    values
+                        .iter()
+                        .map(move |value| (name.to_string(), value.to_string()))
+                })
+                .collect(),
+            vary: vary_headers(&res)
+                .map(|name| {
+                    let value = header_string(req.header(name.as_str()));
+                    (name, value)
+                })
+                .collect(),
+        };
+        let metadata = match metadata(req, &meta) {
+            Some(metadata) => metadata,
+            None => return res,
+        };
+        let (chunks, rx) = mpsc::channel(TEE_BUFFER);
+        let (committed_tx, committed) = oneshot::channel();
+        async_std::task::spawn(write_stream(
+            self.path.clone(),
+            cache_key(req),
+            req.url().clone(),
+            metadata,
+            rx,
+            committed_tx,
+        ));
+        let tee = Tee {
+            body: res.take_body(),
+            chunks: Some(chunks),
+            committed: Some(committed),
+        };
+        res.insert_header("X-Local-Cache-Status", "miss");
+        // No length, so readers keep going until the end of the body, which
+        // is what commits the entry.
+        res.set_body(Body::from_reader(BufReader::new(tee), None));
+        res
+    }
+
+    /// Updates `cached` with the headers of a `304 Not Modified` response to
+    /// revalidating it, and returns the updated response.
+    pub(crate) async fn revalidated(
+        &self,
+        req: &Request,
+        mut cached: CachedResponse,
+        res: &Response,
+    ) -> Response {
+        for (name, values) in res.iter() {
+            if UNCACHED_HEADERS.contains(&name.as_str()) {
+                continue;
+            }
+            cached
+                .meta
+                .headers
+                .retain(|(key, _)| !key.eq_ignore_ascii_case(name.as_str()));
+            for value in values.iter() {
+                cached
+                    .meta
+                    .headers
+                    .push((name.to_string(), value.to_string()));
+            }
+        }
+        self.write(req, &cached.meta, &cached.body).await;
+        cached.into_response("revalidated")
+    }
+
+    /// Writes an entry, logging failures instead of returning them: a broken
+    /// cache shouldn't break requests that otherwise succeeded.
+    async fn write(&self, req: &Request, meta: &CachedMeta, body: &[u8]) {
+        let metadata = match metadata(req, meta) {
+            Some(metadata) => metadata,
+            None => return,
+        };
+        let res: Result<_, Box<dyn std::error::Error + Send + Sync>> = async {
+            let mut writer = cacache::WriteOpts::new()
+                .size(body.len())
+                .metadata(metadata)
+                .open(&self.path, cache_key(req))
+                .await?;
+            writer.write_all(body).await?;
+            Ok(writer.commit().await?)
+        }
+        .await;
+        if let Err(err) = res {
+            log::warn!("Failed to cache response for {}: {}", req.url(), err);
+        }
+    }
+}
+
+/// How many chunks of a response body can be waiting to be written to the
+/// cache before reading the body waits for the cache to catch up.
+const TEE_BUFFER: usize = 16;
+
+enum Chunk {
+    Data(Vec<u8>),
+    End,
+}
+
+/// A response body that sends everything read from it to `write_stream`.
+struct Tee {
+    body: Body,
+    /// Where to send chunks, until the body ends or the cache write fails.
+    chunks: Option<mpsc::Sender<Chunk>>,
+    /// Resolves once the cache write is done, so the body only ends after
+    /// its entry has been committed.
+    committed: Option<oneshot::Receiver<()>>,
+}
+
+impl AsyncRead for Tee {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        if let Some(chunks) = this.chunks.as_mut() {
+            // Waiting here keeps a slow disk from piling up chunks in memory.
+            if ready!(Pin::new(&mut *chunks).poll_ready(cx)).is_err() {
+                this.chunks = None;
+            }
+        }
+        let read = ready!(Pin::new(&mut this.body).poll_read(cx, buf));
+        if let Some(mut chunks) = this.chunks.take() {
+            let chunk = match read {
+                Ok(0) => Some(Chunk::End),
+                Ok(n) => Some(Chunk::Data(buf[..n].to_vec())),
+                // Dropping the sender leaves the entry uncommitted.
+                Err(_) => None,
+            };
+            if let Some(chunk) = chunk {
+                let end = matches!(chunk, Chunk::End);
+                if Pin::new(&mut chunks).start_send(chunk).is_ok() && !end {
+                    this.chunks = Some(chunks);
+                }
+            }
+        }
+        if let Ok(0) = read {
+            if let Some(committed) = this.committed.as_mut() {
+                let _ = ready!(Pin::new(committed).poll(cx));
+                this.committed = None;
+            }
+        }
+        Poll::Ready(read)
+    }
+}
+
+/// Writes the chunks a `Tee` reads into a new cache entry, committing it if
+/// the whole body came through.
+async fn write_stream(
+    path: PathBuf,
+    key: String,
+    url: Url,
+    metadata: Value,
+    mut chunks: mpsc::Receiver<Chunk>,
+    committed: oneshot::Sender<()>,
+) {
+    let res: Result<bool, Box<dyn std::error::Error + Send + Sync>> = async {
+        let mut writer = cacache::WriteOpts::new()
+            .metadata(metadata)
+            .open(&path, key)
+            .await?;
+        while let Some(chunk) = chunks.next().await {
+            match chunk {
+                Chunk::Data(data) => writer.write_all(&data).await?,
+                Chunk::End => {
+                    writer.commit().await?;
+                    return Ok(true);
+                }
+            }
+        }
+        Ok(false)
+    }
+    .await;
+    match res {
+        Ok(true) => {}
+        Ok(false) => log::debug!("Not caching incomplete response for {}", url),
+        Err(err) => log::warn!("Failed to cache response for {}: {}", url, err),
+    }
+    let _ = committed.send(());
+}
+
+fn metadata(req: &Request, meta: &CachedMeta) -> Option<Value> {
+    match serde_json::to_value(meta) {
+        Ok(metadata) => Some(metadata),
+        Err(err) => {
+            log::warn!("Failed to serialize cache entry for {}: {}", req.url(), err);
+            None
+        }
+    }
+}
+
+fn cache_key(req: &Request) -> String {
+    format!("oro-client:request-cache:{}", req.url())
+}
+
+fn is_storable(res: &Response) -> bool {
+    let cache_control = res
+        .header("Cache-Control")
+        .map(|values| values.as_str().to_owned())
+        .unwrap_or_default();
+    res.status() == http::StatusCode::Ok
+        && !has_directive(&cache_control, "no-store")
+        && !vary_headers(res).any(|name| name == "*")
+}
+
+fn vary_headers(res: &Response) -> impl Iterator<Item = String> + '_ {
+    res.header("Vary")
+        .into_iter()
+        .flat_map(|values| values.iter())
+        .flat_map(|value| value.as_str().split(','))
+        .map(|name| name.trim().to_ascii_lowercase())
+        .filter(|name| !name.is_empty())
+}
+
+fn header_string(values: Option<&HeaderValues>) -> Option<String> {
+    values.map(|values| {
+        values
+            .iter()
+            .map(|value| value.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    })
+}
+
+fn directives(cache_control: &str) -> impl Iterator<Item = (String, Option<&str>)> {
+    cache_control.split(',').map(|directive| {
+        let mut parts = directive.splitn(2, '=');
+        let name = parts.next().unwrap_or("").trim().to_ascii_lowercase();
+        let value = parts.next().map(|value| value.trim().trim_matches('"'));
+        (name, value)
+    })
+}
+
+fn has_directive(cache_control: &str, name: &str) -> bool {
+    directives(cache_control).any(|(key, _)| key == name)
+}
+
+fn directive_value<'a>(cache_control: &'a str, name: &str) -> Option<&'a str> {
+    directives(cache_control)
+        .find(|(key, _)| key == name)
+        .and_then(|(_, value)| value)
+}
+
+fn now() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_millis())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use async_std::net::TcpListener;
+    use async_std::task;
+    use futures::io::AsyncReadExt;
+    use pretty_assertions::assert_eq;
+    use surf::http::Method;
+
+    use super::*;
+    use crate::{OroClient, OroClientError, RetryPolicy};
+
+    type Requests = Arc<Mutex<Vec<String>>>;
+
+    /// Starts a server that answers every request with `respond`, which gets
+    /// the lowercased request head. Returns the server's URL and the heads
+    /// of the requests it got.
+    async fn server(respond: fn(&str) -> String) -> (Url, Requests) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/oro", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        let requests = Requests::default();
+        let seen = requests.clone();
+        task::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let seen = seen.clone();
+                task::spawn(async move {
+                    loop {
+                        let mut head = Vec::new();
+                        let mut byte = [0u8];
+                        while !head.ends_with(b"\r\n\r\n") {
+                            if stream.read_exact(&mut byte).await.is_err() {
+                                return;
+                            }
+                            head.push(byte[0]);
+                        }
+                        let head = String::from_utf8(head).unwrap().to_ascii_lowercase();
+                        let res = respond(&head);
+                        seen.lock().unwrap().push(head);
+                        stream.write_all(res.as_bytes()).await.unwrap();
+                    }
+                });
+            }
+        });
+        (url, requests)
+    }
+
+    fn ok(headers: &str, body: &str) -> String {
+        format!(
+            "HTTP/1.1 200 OK\r\n{}Content-Length: {}\r\n\r\n{}",
+            headers,
+            body.len(),
+            body
+        )
+    }
+
+    fn not_modified() -> String {
+        "HTTP/1.1 304 Not Modified\r\nContent-Length: 0\r\n\r\n".into()
+    }
+
+    fn client(cache: &Path, mode: CacheMode) -> OroClient {
+        OroClient::builder()
+            .cache(cache)
+            .cache_mode(mode)
+            .retry_policy(RetryPolicy::none())
+            .build()
+    }
+
+    /// Sends a `GET` for `url`, returning the cache status and the body.
+    async fn get(client: &OroClient, url: &Url) -> (String, String) {
+        let mut res = client
+            .send(client.opts(Method::Get, url.clone()))
+            .await
+            .unwrap();
+        let status = res
+            .header("X-Local-Cache-Status")
+            .map(|status| status.as_str().to_owned())
+            .unwrap_or_default();
+        (status, res.body_string().await.unwrap())
+    }
+
+    #[async_attributes::test]
+    async fn test_default_fresh() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (url, requests) = server(|_| ok("Cache-Control: max-age=300\r\n", "fresh")).await;
+        let client = client(tmp.path(), CacheMode::Default);
+
+        assert_eq!(get(&client, &url).await, ("miss".into(), "fresh".into()));
+        assert_eq!(get(&client, &url).await, ("hit".into(), "fresh".into()));
+        assert_eq!(requests.lock().unwrap().len(), 1);
+    }
+
+    #[async_attributes::test]
+    async fn test_default_stale() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (url, requests) = server(|_| ok("Cache-Control: max-age=0\r\n", "stale")).await;
+        let client = client(tmp.path(), CacheMode::Default);
+
+        assert_eq!(get(&client, &url).await, ("miss".into(), "stale".into()));
+        assert_eq!(get(&client, &url).await, ("miss".into(), "stale".into()));
+        assert_eq!(requests.lock().unwrap().len(), 2);
+    }
+
+    #[async_attributes::test]
+    async fn test_revalidate_etag() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (url, requests) = server(|head| {
+            if head.contains("if-none-match: \"v1\"\r\n") {
+                not_modified()
+            } else {
+                ok("Cache-Control: no-cache\r\nETag: \"v1\"\r\n", "etag")
+            }
+        })
+        .await;
+        let client = client(tmp.path(), CacheMode::Default);
+
+        assert_eq!(get(&client, &url).await, ("miss".into(), "etag".into()));
+        assert_eq!(
+            get(&client, &url).await,
+            ("revalidated".into(), "etag".into())
+        );
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(!requests[0].contains("if-none-match"));
+    }
+
+    #[async_attributes::test]
+    async fn test_revalidate_last_modified() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (url, requests) = server(|head| {
+            if head.contains("if-modified-since: wed, 21 oct 2015 07:28:00 gmt\r\n") {
+                not_modified()
+            } else {
+                ok(
+                    "Cache-Control: max-age=0\r\nLast-Modified: Wed, 21 Oct 2015 07:28:00 GMT\r\n",
+                    "modified",
+                )
+            }
+        })
+        .await;
+        let client = client(tmp.path(), CacheMode::Default);
+
+        assert_eq!(get(&client, &url).await, ("miss".into(), "modified".into()));
+        assert_eq!(
+            get(&client, &url).await,
+            ("revalidated".into(), "modified".into())
+        );
+        assert_eq!(requests.lock().unwrap().len(), 2);
+    }
+
+    #[async_attributes::test]
+    async fn test_vary() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (url, requests) =
+            server(|_| ok("Cache-Control: max-age=300\r\nVary: Accept\r\n", "vary")).await;
+        let client = client(tmp.path(), CacheMode::Default);
+        let send = |accept: &'static str| {
+            let client = client.clone();
+            let url = url.clone();
+            async move {
+                let mut res = client
+                    .send(client.opts(Method::Get, url).header("Accept", accept))
+                    .await
+                    .unwrap();
+                assert_eq!(res.body_string().await.unwrap(), "vary");
+                res.header("X-Local-Cache-Status")
+                    .unwrap()
+                    .as_str()
+                    .to_owned()
+            }
+        };
+
+        assert_eq!(send("application/json").await, "miss");
+        assert_eq!(send("application/json").await, "hit");
+        assert_eq!(send("text/html").await, "miss");
+        assert_eq!(requests.lock().unwrap().len(), 2);
+    }
+
+    #[async_attributes::test]
+    async fn test_modes() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (url, requests) = server(|head| {
+            if head.contains("if-none-match") {
+                not_modified()
+            } else {
+                ok("Cache-Control: max-age=300\r\nETag: \"v1\"\r\n", "data")
+            }
+        })
+        .await;
+        let count = || requests.lock().unwrap().len();
+
+        let only = client(tmp.path(), CacheMode::OnlyIfCached);
+        let err = only
+            .send(only.opts(Method::Get, url.clone()))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err, OroClientError::NoCachedResponse { .. }),
+            "unexpected error: {:?}",
+            err
+        );
+        assert_eq!(count(), 0);
+
+        let no_store = client(tmp.path(), CacheMode::NoStore);
+        assert_eq!(get(&no_store, &url).await, ("".into(), "data".into()));
+        assert_eq!(count(), 1);
+        let key = cache_key(&Request::new(Method::Get, url.clone()));
+        assert_eq!(cacache::metadata(tmp.path(), key).await.unwrap(), None);
+
+        let force = client(tmp.path(), CacheMode::ForceCache);
+        assert_eq!(get(&force, &url).await, ("miss".into(), "data".into()));
+        assert_eq!(get(&force, &url).await, ("hit".into(), "data".into()));
+        assert_eq!(count(), 2);
+
+        assert_eq!(get(&only, &url).await, ("hit".into(), "data".into()));
+        assert_eq!(count(), 2);
+
+        let no_cache = client(tmp.path(), CacheMode::NoCache);
+        assert_eq!(
+            get(&no_cache, &url).await,
+            ("revalidated".into(), "data".into())
+        );
+        assert_eq!(count(), 3);
+
+        let reload = client(tmp.path(), CacheMode::Reload);
+        assert_eq!(get(&reload, &url).await, ("miss".into(), "data".into()));
+        assert_eq!(count(), 4);
+        assert!(!requests.lock().unwrap()[3].contains("if-none-match"));
+    }
+
+    #[async_attributes::test]
+    async fn test_partial_body_not_cached() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (url, _) = server(|_| ok("Cache-Control: max-age=300\r\n", "some long body")).await;
+        let client = client(tmp.path(), CacheMode::Default);
+
+        let mut res = client
+            .send(client.opts(Method::Get, url.clone()))
+            .await
+            .unwrap();
+        let mut start = [0u8; 4];
+        res.read_exact(&mut start).await.unwrap();
+        drop(res);
+        assert_eq!(get(&client, &url).await.0, "miss");
+        assert_eq!(
+            get(&client, &url).await,
+            ("hit".into(), "some long body".into())
+        );
+    }
+}
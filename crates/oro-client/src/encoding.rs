@@ -0,0 +1,180 @@
+use async_compression::futures::bufread::{BrotliDecoder, GzipDecoder, ZlibDecoder};
+use futures::io::{AsyncBufRead, BufReader};
+use surf::{Body, Request, Response, StatusCode};
+
+/// What `OroClient` sends in `Accept-Encoding` when the request doesn't
+/// already say.
+pub(crate) const ACCEPT_ENCODING: &str = "gzip, deflate, br";
+
+/// Asks for a compressed response, unless the request already picked its
+/// own encodings. Returns whether the response should be decoded.
+///
+/// Requests that set `Accept-Encoding` themselves get their responses
+/// as-is, so `Accept-Encoding: identity` keeps a download byte-exact even
+/// if a server compresses it anyway.
+pub(crate) fn negotiate(req: &mut Request) -> bool {
+    if req.header("Accept-Encoding").is_some() {
+        false
+    } else {
+        req.insert_header("Accept-Encoding", ACCEPT_ENCODING);
+        true
+    }
+}
+
+/// Replaces a compressed response body with a stream of the decoded body.
+/// Responses with encodings this can't decode are returned untouched.
+pub(crate) fn decode(mut res: Response) -> Response {
+    if res.status() == StatusCode::NoContent || res.status() == StatusCode::NotModified {
+        return res;
+    }
+    let encodings: Vec<String> = match res.header("Content-Encoding") {
+        Some(values) => values
+            .iter()
+            .flat_map(|value| value.as_str().split(','))
+            .map(|encoding| encoding.trim().to_ascii_lowercase())
+            .filter(|encoding| !encoding.is_empty() && encoding != "identity")
+            .collect(),
+        None => return res,
+    };
+    if !encodings
+        .iter()
+        .all(|encoding| matches!(encoding.as_str(), "gzip" | "x-gzip" | "deflate" | "br"))
+    {
+        log::warn!(
+            "Not decoding {} response with unsupported Content-Encoding {:?}",
+            res.status(),
+            encodings
+        );
+        return res;
+    }
+    // Encodings are listed in the order they were applied, so they're undone
+    // back to front.
+    let mut body: Box<dyn AsyncBufRead + Unpin + Send + Sync> = Box::new(res.take_body());
+    for encoding in encodings.iter().rev() {
+        body = match encoding.as_str() {
+            "gzip" | "x-gzip" => Box::new(BufReader::new(GzipDecoder::new(body))),
+            "deflate" => Box::new(BufReader::new(ZlibDecoder::new(body))),
+            "br" => Box::new(BufReader::new(BrotliDecoder::new(body))),
+            _ => unreachable!(),
+        };
+    }
+    // The headers describe the encoded body, not what gets read out of it.
+    res.remove_header("Content-Encoding");
+    res.remove_header("Content-Length");
+    res.set_body(Body::from_reader(body, None));
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use async_compression::futures::bufread::{BrotliEncoder, GzipEncoder, ZlibEncoder};
+    use async_std::net::TcpListener;
+    use async_std::task;
+    use futures::io::{AsyncReadExt, AsyncWriteExt};
+    use pretty_assertions::assert_eq;
+    use surf::http::{self, Method};
+
+    use super::*;
+    use crate::OroClient;
+
+    const PACKUMENT: &[u8] = br#"{"name":"oro","versions":{"1.0.0":{"name":"oro"}}}"#;
+
+    async fn compress(encoding: &str, data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        match encoding {
+            "gzip" => GzipEncoder::new(data).read_to_end(&mut out).await,
+            "deflate" => ZlibEncoder::new(data).read_to_end(&mut out).await,
+            "br" => BrotliEncoder::new(data).read_to_end(&mut out).await,
+            _ => unreachable!(),
+        }
+        .unwrap();
+        out
+    }
+
+    fn response(encoding: &str, body: Vec<u8>) -> Response {
+        let mut res = http::Response::new(StatusCode::Ok);
+        res.insert_header("Content-Encoding", encoding);
+        res.insert_header("Content-Length", body.len().to_string());
+        res.set_body(body);
+        res.into()
+    }
+
+    #[async_attributes::test]
+    async fn test_decode() {
+        for encoding in &["gzip", "deflate", "br"] {
+            let body = compress(encoding, PACKUMENT).await;
+            let mut res = decode(response(encoding, body));
+            assert!(res.header("Content-Encoding").is_none());
+            assert!(res.header("Content-Length").is_none());
+            assert_eq!(res.body_bytes().await.unwrap(), PACKUMENT);
+        }
+
+        let gzipped = compress("gzip", PACKUMENT).await;
+        let body = compress("br", &gzipped).await;
+        let mut res = decode(response("gzip, br", body));
+        assert_eq!(res.body_bytes().await.unwrap(), PACKUMENT);
+
+        let mut res = decode(response("zstd", PACKUMENT.to_vec()));
+        assert_eq!(res.header("Content-Encoding").unwrap(), "zstd");
+        assert_eq!(res.body_bytes().await.unwrap(), PACKUMENT);
+    }
+
+    #[async_attributes::test]
+    async fn test_send() {
+        let body = compress("gzip", PACKUMENT).await;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/oro", listener.local_addr().unwrap());
+        let server = task::spawn(async move {
+            let mut requests = Vec::new();
+            let (mut stream, _) = listener.accept().await.unwrap();
+            for _ in 0..2 {
+                let mut head = Vec::new();
+                let mut byte = [0u8];
+                while !head.ends_with(b"\r\n\r\n") {
+                    stream.read_exact(&mut byte).await.unwrap();
+                    head.push(byte[0]);
+                }
+                let head = String::from_utf8(head).unwrap().to_ascii_lowercase();
+                stream
+                    .write_all(
+                        format!(
+                            "HTTP/1.1 200 OK\r\nContent-Encoding: gzip\r\nContent-Length: {}\r\n\r\n",
+                            body.len()
+                        )
+                        .as_bytes(),
+                    )
+                    .await
+                    .unwrap();
+                stream.write_all(&body).await.unwrap();
+                requests.push(head);
+            }
+            requests
+        });
+
+        let client = OroClient::new();
+        let url: surf::Url = url.parse().unwrap();
+        let mut res = client
+            .send(client.opts(Method::Get, url.clone()))
+            .await
+            .unwrap();
+        assert_eq!(res.body_bytes().await.unwrap(), PACKUMENT);
+
+        // Tarballs ask for the raw bytes, so their integrity can be checked.
+        let mut res = client
+            .send(
+                client
+                    .opts(Method::Get, url)
+                    .header("Accept-Encoding", "identity"),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            res.body_bytes().await.unwrap(),
+            compress("gzip", PACKUMENT).await
+        );
+
+        let requests = server.await;
+        assert!(requests[0].contains("accept-encoding: gzip, deflate, br\r\n"));
+        assert!(requests[1].contains("accept-encoding: identity\r\n"));
+    }
+}
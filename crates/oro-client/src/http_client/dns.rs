@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_std::net::ToSocketAddrs;
+
+/// Most hosts to remember at once. Installs only talk to a handful of
+/// registries and CDNs, so this is plenty.
+const MAX_ENTRIES: usize = 256;
+
+struct Entry {
+    addrs: Vec<SocketAddr>,
+    expires: Instant,
+}
+
+/// Resolves hostnames, remembering the results for a while so every new
+/// connection to a registry doesn't wait on a DNS lookup.
+#[derive(Clone)]
+pub(crate) struct Resolver {
+    cache: Arc<Mutex<HashMap<(String, u16), Entry>>>,
+    ttl: Duration,
+}
+
+impl Resolver {
+    pub(crate) fn new(ttl: Duration) -> Self {
+        Self {
+            cache: Arc::new(Mutex::new(HashMap::new())),
+            ttl,
+        }
+    }
+
+    /// All the addresses `host` resolves to, in the order the system
+    /// resolver prefers them.
+    pub(crate) async fn resolve(&self, host: &str, port: u16) -> Result<Vec<SocketAddr>, Error> {
+        // IPv6 hosts come out of URLs in brackets.
+        let literal = host.trim_start_matches('[').trim_end_matches(']');
+        if let Ok(ip) = literal.parse::<IpAddr>() {
+            return Ok(vec![SocketAddr::new(ip, port)]);
+        }
+        let key = (host.to_ascii_lowercase(), port);
+        if let Some(entry) = self.cache.lock().unwrap().get(&key) {
+            if entry.expires > Instant::now() {
+                return Ok(entry.addrs.clone());
+            }
+        }
+        log::trace!("Resolving {}:{}", host, port);
+        let addrs: Vec<SocketAddr> = (host, port).to_socket_addrs().await?.collect();
+        if addrs.is_empty() {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("{} did not resolve to any addresses", host),
+            ));
+        }
+        let mut cache = self.cache.lock().unwrap();
+        let now = Instant::now();
+        cache.retain(|_, entry| entry.expires > now);
+        if cache.len() >= MAX_ENTRIES {
+            let oldest = cache
+                .iter()
+                .min_by_key(|(_, entry)| entry.expires)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                cache.remove(&oldest);
+            }
+        }
+        cache.insert(
+            key,
+            Entry {
+                addrs: addrs.clone(),
+                expires: now + self.ttl,
+            },
+        );
+        Ok(addrs)
+    }
+
+    /// Forgets what `host` resolved to, so the next connection looks it up
+    /// again. Used when none of its addresses worked.
+    pub(crate) fn forget(&self, host: &str, port: u16) {
+        self.cache
+            .lock()
+            .unwrap()
+            .remove(&(host.to_ascii_lowercase(), port));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[async_attributes::test]
+    async fn test_resolve() {
+        let resolver = Resolver::new(Duration::from_secs(60));
+        assert_eq!(
+            resolver.resolve("[::1]", 80).await.unwrap(),
+            vec!["[::1]:80".parse().unwrap()]
+        );
+        assert_eq!(
+            resolver.resolve("127.0.0.1", 80).await.unwrap(),
+            vec!["127.0.0.1:80".parse().unwrap()]
+        );
+        assert!(resolver.cache.lock().unwrap().is_empty());
+
+        // Cached entries are used without going to the system resolver, so
+        // a made-up host works.
+        let addrs: Vec<SocketAddr> = vec!["10.0.0.1:443".parse().unwrap()];
+        resolver.cache.lock().unwrap().insert(
+            ("registry.invalid".into(), 443),
+            Entry {
+                addrs: addrs.clone(),
+                expires: Instant::now() + Duration::from_secs(60),
+            },
+        );
+        assert_eq!(
+            resolver.resolve("REGISTRY.invalid", 443).await.unwrap(),
+            addrs
+        );
+        resolver.forget("registry.invalid", 443);
+        assert!(resolver.resolve("registry.invalid", 443).await.is_err());
+    }
+}
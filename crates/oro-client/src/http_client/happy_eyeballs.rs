@@ -0,0 +1,174 @@
+use std::io::{Error, ErrorKind};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use async_std::net::TcpStream;
+use futures::future::{self, Either};
+use futures::stream::{FuturesUnordered, StreamExt};
+
+use super::dns::Resolver;
+
+/// How long to give a connection attempt before starting the next one in
+/// parallel, as recommended by RFC 8305.
+const ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Connects to `host`, trying every address it resolves to.
+///
+/// This follows "Happy Eyeballs" (RFC 8305): IPv6 and IPv4 addresses are
+/// tried alternately, and if an attempt hasn't connected after a short
+/// delay, the next one starts alongside it. The first connection to succeed
+/// wins, so a host with broken IPv6, or a single dead address, only costs a
+/// little latency instead of failing the request.
+pub(crate) async fn connect(
+    resolver: &Resolver,
+    host: &str,
+    port: u16,
+) -> Result<TcpStream, Error> {
+    let addrs = resolver.resolve(host, port).await?;
+    let res = connect_addrs(&addrs).await;
+    if res.is_err() {
+        resolver.forget(host, port);
+    }
+    res
+}
+
+async fn connect_addrs(addrs: &[SocketAddr]) -> Result<TcpStream, Error> {
+    let mut addrs = interleave(addrs).into_iter();
+    let mut attempts = FuturesUnordered::new();
+    let mut last_err = None;
+    loop {
+        if attempts.is_empty() {
+            match addrs.next() {
+                Some(addr) => attempts.push(attempt(addr)),
+                None => {
+                    return Err(last_err.unwrap_or_else(|| {
+                        Error::new(ErrorKind::NotFound, "no addresses to connect to")
+                    }))
+                }
+            }
+        }
+        let delay = async_std::task::sleep(ATTEMPT_DELAY);
+        futures::pin_mut!(delay);
+        match future::select(attempts.next(), delay).await {
+            Either::Left((Some(Ok(stream)), _)) => return Ok(stream),
+            Either::Left((Some(Err(err)), _)) => {
+                last_err = Some(err);
+                // Don't wait out the delay when an attempt has already
+                // failed.
+                if let Some(addr) = addrs.next() {
+                    attempts.push(attempt(addr));
+                }
+            }
+            Either::Left((None, _)) => {}
+            Either::Right((_, next)) => {
+                drop(next);
+                if let Some(addr) = addrs.next() {
+                    attempts.push(attempt(addr));
+                }
+            }
+        }
+    }
+}
+
+async fn attempt(addr: SocketAddr) -> Result<TcpStream, Error> {
+    log::trace!("Creating new socket to {:?}", addr);
+    TcpStream::connect(addr).await.map_err(|err| {
+        log::debug!("Failed to connect to {}: {}", addr, err);
+        err
+    })
+}
+
+/// Orders `addrs` so address families alternate, starting with the family
+/// of the resolver's first pick.
+fn interleave(addrs: &[SocketAddr]) -> Vec<SocketAddr> {
+    let first_v6 = match addrs.first() {
+        Some(addr) => addr.is_ipv6(),
+        None => return Vec::new(),
+    };
+    let (preferred, other): (Vec<_>, Vec<_>) = addrs
+        .iter()
+        .cloned()
+        .partition(|addr| addr.is_ipv6() == first_v6);
+    let mut preferred = preferred.into_iter();
+    let mut other = other.into_iter();
+    let mut ordered = Vec::with_capacity(addrs.len());
+    loop {
+        match (preferred.next(), other.next()) {
+            (None, None) => return ordered,
+            (a, b) => ordered.extend(a.into_iter().chain(b)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_std::net::TcpListener;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    fn addrs(addrs: &[&str]) -> Vec<SocketAddr> {
+        addrs.iter().map(|addr| addr.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_interleave() {
+        assert_eq!(
+            interleave(&addrs(&[
+                "[::1]:80",
+                "[::2]:80",
+                "[::3]:80",
+                "10.0.0.1:80",
+                "10.0.0.2:80"
+            ])),
+            addrs(&[
+                "[::1]:80",
+                "10.0.0.1:80",
+                "[::2]:80",
+                "10.0.0.2:80",
+                "[::3]:80"
+            ])
+        );
+        assert_eq!(
+            interleave(&addrs(&["10.0.0.1:80", "10.0.0.2:80", "[::1]:80"])),
+            addrs(&["10.0.0.1:80", "[::1]:80", "10.0.0.2:80"])
+        );
+        assert_eq!(interleave(&[]), Vec::<SocketAddr>::new());
+    }
+
+    /// An address nothing is listening on.
+    async fn dead_addr() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        listener.local_addr().unwrap()
+    }
+
+    #[async_attributes::test]
+    async fn test_fallback() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let live = listener.local_addr().unwrap();
+        let dead = dead_addr().await;
+
+        let stream = connect_addrs(&[dead, live]).await.unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), live);
+        let stream = connect_addrs(&[live, dead]).await.unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), live);
+
+        let err = connect_addrs(&[dead, dead_addr().await]).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ConnectionRefused);
+        assert_eq!(
+            connect_addrs(&[]).await.unwrap_err().kind(),
+            ErrorKind::NotFound
+        );
+    }
+
+    #[async_attributes::test]
+    async fn test_connect_localhost() {
+        // localhost may resolve to both ::1 and 127.0.0.1, and only the
+        // latter is listening.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let resolver = Resolver::new(Duration::from_secs(60));
+        let stream = connect(&resolver, "localhost", port).await.unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), listener.local_addr().unwrap());
+    }
+}
@@ -1,5 +1,4 @@
 use std::collections::HashMap;
-use std::net::SocketAddr;
 use std::{fmt::Debug, sync::Arc};
 
 use async_h1::client;
@@ -7,29 +6,42 @@ use async_native_tls::TlsStream;
 use async_std::net::TcpStream;
 use async_std::sync::Mutex;
 use async_trait::async_trait;
-use deadpool::managed::Pool;
+use deadpool::managed::{Pool, PoolError};
 use http_types::StatusCode;
 use surf::http::{Request, Response};
 use surf::{Error, HttpClient};
 
-use tcp::{TcpConnWrapper, TcpConnection};
-use tls::{TlsConnWrapper, TlsConnection};
-
+use dns::Resolver;
+pub use pool::PoolConfig;
+use pool::{ConnWrapper, ConnectionLimit, Pooled};
+pub use proxy::ProxyConfig;
+use proxy::{proxy_auth, AbsoluteForm};
+use tcp::TcpConnection;
+pub use tls::TlsConfig;
+use tls::TlsConnection;
+
+mod dns;
+mod happy_eyeballs;
+mod pool;
+mod proxy;
 mod tcp;
 mod tls;
 
-// TODO: Move this to a parameter. This current number is based on a few
-// random benchmarks and see whatever gave decent perf vs resource use.
-static MAX_CONCURRENT_CONNECTIONS: usize = 50;
-
-type HttpPool = HashMap<SocketAddr, Pool<TcpStream, std::io::Error>>;
-type HttpsPool = HashMap<SocketAddr, Pool<TlsStream<TcpStream>, Error>>;
+// Pools are keyed by the host and port connections go to, which is the
+// proxy when there is one.
+type HttpPool = HashMap<(String, u16), Pool<Pooled<TcpStream>, std::io::Error>>;
+// HTTPS connections are keyed by the origin's `host:port` too, since
+// connections through a proxy all go to the same place.
+type HttpsPool = HashMap<((String, u16), String), Pool<Pooled<TlsStream<TcpStream>>, Error>>;
 
 /// Async-h1 based connection-pooling HTTP client.
 #[derive(Clone)]
 pub struct PoolingClient {
     http_pool: Arc<Mutex<HttpPool>>,
     https_pool: Arc<Mutex<HttpsPool>>,
+    limit: ConnectionLimit,
+    resolver: Resolver,
+    config: Arc<PoolConfig>,
 }
 
 impl Debug for PoolingClient {
@@ -47,9 +59,17 @@ impl Default for PoolingClient {
 impl PoolingClient {
     /// Create a new instance.
     pub fn new() -> Self {
+        Self::with_config(PoolConfig::default())
+    }
+
+    /// Create a new instance with custom connection settings.
+    pub fn with_config(config: PoolConfig) -> Self {
         Self {
             http_pool: Arc::new(Mutex::new(HashMap::new())),
             https_pool: Arc::new(Mutex::new(HashMap::new())),
+            limit: ConnectionLimit::new(config.max_connections),
+            resolver: Resolver::new(config.dns_ttl),
+            config: Arc::new(config),
         }
     }
 }
@@ -76,16 +96,28 @@ impl HttpClient for PoolingClient {
             ));
         }
 
-        let addr = req
-            .url()
-            .socket_addrs(|| match req.url().scheme() {
-                "http" => Some(80),
-                "https" => Some(443),
-                _ => None,
-            })?
-            .into_iter()
-            .next()
-            .ok_or_else(|| Error::from_str(StatusCode::BadRequest, "missing valid address"))?;
+        let proxy = self.config.proxy.proxy_for(req.url()).cloned();
+        if let Some(ref proxy) = proxy {
+            if proxy.scheme() != "http" {
+                return Err(Error::from_str(
+                    StatusCode::BadRequest,
+                    format!("unsupported proxy scheme '{}'", proxy.scheme()),
+                ));
+            }
+            log::trace!("> Proxy: {}", proxy);
+        }
+
+        // With a proxy, only the proxy itself gets connected to.
+        let target = proxy.as_ref().unwrap_or_else(|| req.url());
+        let addr = match (target.host_str(), target.port_or_known_default()) {
+            (Some(host), Some(port)) => (host.to_ascii_lowercase(), port),
+            _ => {
+                return Err(Error::from_str(
+                    StatusCode::BadRequest,
+                    "missing valid address",
+                ))
+            }
+        };
 
         log::trace!("> Scheme: {}", scheme);
 
@@ -95,39 +127,73 @@ impl HttpClient for PoolingClient {
                 let pool = if let Some(pool) = hash.get(&addr) {
                     pool
                 } else {
-                    let manager = TcpConnection::new(addr);
-                    let pool =
-                        Pool::<TcpStream, std::io::Error>::new(manager, MAX_CONCURRENT_CONNECTIONS);
-                    hash.insert(addr, pool);
+                    let manager = TcpConnection::new(
+                        addr.0.clone(),
+                        addr.1,
+                        self.resolver.clone(),
+                        &self.config,
+                    );
+                    let pool = Pool::<Pooled<TcpStream>, std::io::Error>::new(
+                        manager,
+                        self.config.max_connections_per_host,
+                    );
+                    hash.insert(addr.clone(), pool);
                     hash.get(&addr).expect("oh COME ON")
                 };
                 let pool = pool.clone();
                 std::mem::drop(hash);
-                let stream = pool.get().await?;
-                req.set_peer_addr(stream.peer_addr().ok());
-                req.set_local_addr(stream.local_addr().ok());
-                client::connect(TcpConnWrapper::new(stream), req).await
+                // Only take a global permit once this host has a connection
+                // to spare, so requests queued on a busy host don't hold
+                // permits other hosts could be using.
+                let conn = pool.get().await?;
+                let permit = self.limit.acquire().await;
+                req.set_peer_addr(conn.stream.peer_addr().ok());
+                req.set_local_addr(conn.stream.local_addr().ok());
+                let stream = ConnWrapper::new(conn, permit, self.config.read_timeout);
+                if proxy.is_some() {
+                    if let Some(auth) = proxy.as_ref().and_then(proxy_auth) {
+                        req.insert_header("Proxy-Authorization", auth);
+                    }
+                    let url = req.url().clone();
+                    client::connect(AbsoluteForm::new(stream, &url), req).await
+                } else {
+                    client::connect(stream, req).await
+                }
             }
             "https" => {
+                let port = req.url().port_or_known_default().unwrap_or(443);
+                let key = (addr, format!("{}:{}", host, port));
                 let mut hash = https_pool.lock().await;
-                let pool = if let Some(pool) = hash.get(&addr) {
+                let pool = if let Some(pool) = hash.get(&key) {
                     pool
                 } else {
-                    let manager = TlsConnection::new(host.clone(), addr);
-                    let pool = Pool::<TlsStream<TcpStream>, Error>::new(
+                    let mut manager = TlsConnection::new(
+                        host.clone(),
+                        key.0.clone(),
+                        self.resolver.clone(),
+                        &self.config,
+                    );
+                    if let Some(ref proxy) = proxy {
+                        manager = manager.through_proxy(key.1.clone(), proxy_auth(proxy));
+                    }
+                    let pool = Pool::<Pooled<TlsStream<TcpStream>>, Error>::new(
                         manager,
-                        MAX_CONCURRENT_CONNECTIONS,
+                        self.config.max_connections_per_host,
                     );
-                    hash.insert(addr, pool);
-                    hash.get(&addr).expect("oh COME ON")
+                    hash.insert(key.clone(), pool);
+                    hash.get(&key).expect("oh COME ON")
                 };
                 let pool = pool.clone();
                 std::mem::drop(hash);
-                let stream = pool.get().await.unwrap(); // TODO: remove unwrap
-                req.set_peer_addr(stream.get_ref().peer_addr().ok());
-                req.set_local_addr(stream.get_ref().local_addr().ok());
-
-                client::connect(TlsConnWrapper::new(stream), req).await
+                let conn = pool.get().await.map_err(|e| match e {
+                    PoolError::Backend(e) => e,
+                    e => Error::from_str(StatusCode::ServiceUnavailable, e.to_string()),
+                })?;
+                let permit = self.limit.acquire().await;
+                req.set_peer_addr(conn.stream.get_ref().peer_addr().ok());
+                req.set_local_addr(conn.stream.get_ref().local_addr().ok());
+                let stream = ConnWrapper::new(conn, permit, self.config.read_timeout);
+                client::connect(stream, req).await
             }
             _ => unreachable!(),
         }
@@ -1,68 +1,52 @@
-use std::fmt::Debug;
-use std::net::SocketAddr;
-use std::pin::Pin;
+use std::fmt::{self, Debug};
+use std::time::Duration;
 
 use async_std::net::TcpStream;
 use async_trait::async_trait;
-use deadpool::managed::{Manager, Object, RecycleResult};
-use futures::io::{AsyncRead, AsyncWrite};
-use futures::task::{Context, Poll};
+use deadpool::managed::{Manager, RecycleResult};
 
-pub struct TcpConnWrapper {
-    conn: Object<TcpStream, std::io::Error>,
-}
-impl TcpConnWrapper {
-    pub fn new(conn: Object<TcpStream, std::io::Error>) -> Self {
-        Self { conn }
-    }
-}
-
-impl AsyncRead for TcpConnWrapper {
-    fn poll_read(
-        mut self: Pin<&mut Self>,
-        cx: &mut Context,
-        buf: &mut [u8],
-    ) -> Poll<Result<usize, std::io::Error>> {
-        Pin::new(&mut *self.conn).poll_read(cx, buf)
-    }
-}
-
-impl AsyncWrite for TcpConnWrapper {
-    fn poll_write(
-        mut self: Pin<&mut Self>,
-        cx: &mut Context<'_>,
-        buf: &[u8],
-    ) -> Poll<std::io::Result<usize>> {
-        let amt = futures::ready!(Pin::new(&mut *self.conn).poll_write(cx, buf))?;
-        Poll::Ready(Ok(amt))
-    }
+use super::dns::Resolver;
+use super::happy_eyeballs;
+use super::pool::{connect_with_timeout, PoolConfig, Pooled};
 
-    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
-        Pin::new(&mut *self.conn).poll_flush(cx)
-    }
-
-    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
-        Pin::new(&mut *self.conn).poll_close(cx)
-    }
-}
-
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct TcpConnection {
-    addr: SocketAddr,
+    host: String,
+    port: u16,
+    resolver: Resolver,
+    connect_timeout: Option<Duration>,
+    idle_timeout: Option<Duration>,
 }
 impl TcpConnection {
-    pub fn new(addr: SocketAddr) -> Self {
-        Self { addr }
+    pub fn new(host: String, port: u16, resolver: Resolver, config: &PoolConfig) -> Self {
+        Self {
+            host,
+            port,
+            resolver,
+            connect_timeout: config.connect_timeout,
+            idle_timeout: config.idle_timeout,
+        }
     }
 }
 
-#[async_trait]
-impl Manager<TcpStream, std::io::Error> for TcpConnection {
-    async fn create(&self) -> Result<TcpStream, std::io::Error> {
-        Ok(TcpStream::connect(self.addr).await?)
+impl Debug for TcpConnection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "TcpConnection({}:{})", self.host, self.port)
     }
+}
 
-    async fn recycle(&self, _conn: &mut TcpStream) -> RecycleResult<std::io::Error> {
-        Ok(())
+#[async_trait]
+impl Manager<Pooled<TcpStream>, std::io::Error> for TcpConnection {
+    async fn create(&self) -> Result<Pooled<TcpStream>, std::io::Error> {
+        let stream = connect_with_timeout(
+            self.connect_timeout,
+            happy_eyeballs::connect(&self.resolver, &self.host, self.port),
+        )
+        .await?;
+        Ok(Pooled::new(stream))
+    }
+
+    async fn recycle(&self, conn: &mut Pooled<TcpStream>) -> RecycleResult<std::io::Error> {
+        conn.recycle(self.idle_timeout)
     }
 }
@@ -1,73 +1,375 @@
-use std::fmt::Debug;
-use std::net::SocketAddr;
-use std::pin::Pin;
+use std::collections::HashMap;
+use std::fmt::{self, Debug};
+use std::path::Path;
+use std::time::Duration;
 
-use async_native_tls::TlsStream;
+use async_native_tls::{Certificate, Identity, TlsConnector, TlsStream};
 use async_std::net::TcpStream;
 use async_trait::async_trait;
-use deadpool::managed::{Manager, Object, RecycleResult};
-use futures::io::{AsyncRead, AsyncWrite};
-use futures::task::{Context, Poll};
+use deadpool::managed::{Manager, RecycleResult};
 use http_client::Error;
 
-#[derive(Clone, Debug)]
+use super::dns::Resolver;
+use super::happy_eyeballs;
+use super::pool::{connect_with_timeout, PoolConfig, Pooled};
+use super::proxy::connect_tunnel;
+use crate::OroClientError;
+
+/// TLS settings for HTTPS connections: extra certificate authorities to
+/// trust, hosts to skip verification for, and a client certificate.
+#[derive(Clone)]
+pub struct TlsConfig {
+    ca: Vec<Certificate>,
+    strict_ssl: bool,
+    host_strict_ssl: HashMap<String, bool>,
+    identity: Option<Identity>,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self {
+            ca: Vec::new(),
+            strict_ssl: true,
+            host_strict_ssl: HashMap::new(),
+            identity: None,
+        }
+    }
+}
+
+impl Debug for TlsConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TlsConfig")
+            .field("ca", &self.ca.len())
+            .field("strict_ssl", &self.strict_ssl)
+            .field("host_strict_ssl", &self.host_strict_ssl)
+            .field("identity", &self.identity.is_some())
+            .finish()
+    }
+}
+
+impl TlsConfig {
+    /// Creates a config that only trusts the system's certificate
+    /// authorities, and verifies every host.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Trusts every certificate in `pem`, which can hold a whole bundle of
+    /// PEM-encoded certificates, on top of the system's certificate
+    /// authorities.
+    pub fn ca(mut self, pem: impl AsRef<[u8]>) -> Result<Self, OroClientError> {
+        let pem = String::from_utf8_lossy(pem.as_ref());
+        let end = "-----END CERTIFICATE-----";
+        let mut found = false;
+        let mut rest = &pem[..];
+        while let Some(idx) = rest.find(end) {
+            let (block, next) = rest.split_at(idx + end.len());
+            let cert = Certificate::from_pem(block.trim().as_bytes())
+                .map_err(OroClientError::BadCertificate)?;
+            self.ca.push(cert);
+            rest = next;
+            found = true;
+        }
+        if !found {
+            // Let the TLS library explain what's wrong with it.
+            let cert =
+                Certificate::from_pem(pem.as_bytes()).map_err(OroClientError::BadCertificate)?;
+            self.ca.push(cert);
+        }
+        Ok(self)
+    }
+
+    /// Trusts every certificate in the PEM file at `path`. See `ca`.
+    pub fn ca_file(self, path: impl AsRef<Path>) -> Result<Self, OroClientError> {
+        let pem = read_file(path.as_ref())?;
+        self.ca(pem)
+    }
+
+    /// Sets whether to verify server certificates. Defaults to true.
+    pub fn strict_ssl(mut self, strict_ssl: bool) -> Self {
+        self.strict_ssl = strict_ssl;
+        self
+    }
+
+    /// Sets whether to verify server certificates for `host` in particular,
+    /// overriding `strict_ssl`.
+    pub fn host_strict_ssl(mut self, host: impl AsRef<str>, strict_ssl: bool) -> Self {
+        self.host_strict_ssl
+            .insert(host.as_ref().to_ascii_lowercase(), strict_ssl);
+        self
+    }
+
+    /// Presents the client certificate in `pkcs12`, a DER-encoded PKCS #12
+    /// archive holding the certificate and its private key, to servers that
+    /// ask for one.
+    pub fn identity(
+        mut self,
+        pkcs12: impl AsRef<[u8]>,
+        password: impl AsRef<str>,
+    ) -> Result<Self, OroClientError> {
+        let identity = Identity::from_pkcs12(pkcs12.as_ref(), password.as_ref())
+            .map_err(OroClientError::BadCertificate)?;
+        self.identity = Some(identity);
+        Ok(self)
+    }
+
+    /// Presents the client certificate in the PKCS #12 file at `path`. See
+    /// `identity`.
+    pub fn identity_file(
+        self,
+        path: impl AsRef<Path>,
+        password: impl AsRef<str>,
+    ) -> Result<Self, OroClientError> {
+        let pkcs12 = read_file(path.as_ref())?;
+        self.identity(pkcs12, password)
+    }
+
+    fn connector(&self, host: &str) -> TlsConnector {
+        let strict_ssl = self
+            .host_strict_ssl
+            .get(&host.to_ascii_lowercase())
+            .copied()
+            .unwrap_or(self.strict_ssl);
+        let mut connector = TlsConnector::new()
+            .danger_accept_invalid_certs(!strict_ssl)
+            .danger_accept_invalid_hostnames(!strict_ssl);
+        for cert in &self.ca {
+            connector = connector.add_root_certificate(cert.clone());
+        }
+        if let Some(ref identity) = self.identity {
+            connector = connector.identity(identity.clone());
+        }
+        connector
+    }
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>, OroClientError> {
+    std::fs::read(path).map_err(|source| OroClientError::CertificateReadError {
+        source,
+        path: path.to_owned(),
+    })
+}
+
+#[derive(Clone)]
 pub struct TlsConnection {
     host: String,
-    addr: SocketAddr,
+    /// Where to connect to: either `host` itself, or a proxy.
+    addr: (String, u16),
+    resolver: Resolver,
+    tls: TlsConfig,
+    connect_timeout: Option<Duration>,
+    idle_timeout: Option<Duration>,
+    /// The `host:port` to tunnel to when `addr` is a proxy, and the
+    /// `Proxy-Authorization` to send it.
+    tunnel: Option<(String, Option<String>)>,
 }
 impl TlsConnection {
-    pub fn new(host: String, addr: SocketAddr) -> Self {
-        Self { host, addr }
+    pub fn new(host: String, addr: (String, u16), resolver: Resolver, config: &PoolConfig) -> Self {
+        Self {
+            host,
+            addr,
+            resolver,
+            tls: config.tls.clone(),
+            connect_timeout: config.connect_timeout,
+            idle_timeout: config.idle_timeout,
+            tunnel: None,
+        }
     }
-}
 
-pub struct TlsConnWrapper {
-    conn: Object<TlsStream<TcpStream>, Error>,
+    pub fn through_proxy(mut self, authority: String, auth: Option<String>) -> Self {
+        self.tunnel = Some((authority, auth));
+        self
+    }
+
+    async fn connect(&self) -> Result<TlsStream<TcpStream>, Error> {
+        let mut raw_stream =
+            happy_eyeballs::connect(&self.resolver, &self.addr.0, self.addr.1).await?;
+        if let Some((ref authority, ref auth)) = self.tunnel {
+            log::trace!("Tunneling to {} through proxy", authority);
+            connect_tunnel(&mut raw_stream, authority, auth.as_deref()).await?;
+        }
+        let stream = self
+            .tls
+            .connector(&self.host)
+            .connect(&self.host, raw_stream)
+            .await?;
+        Ok(stream)
+    }
 }
-impl TlsConnWrapper {
-    pub fn new(conn: Object<TlsStream<TcpStream>, Error>) -> Self {
-        Self { conn }
+
+impl Debug for TlsConnection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "TlsConnection({} via {}:{})",
+            self.host, self.addr.0, self.addr.1
+        )
     }
 }
 
-impl AsyncRead for TlsConnWrapper {
-    fn poll_read(
-        mut self: Pin<&mut Self>,
-        cx: &mut Context,
-        buf: &mut [u8],
-    ) -> Poll<Result<usize, std::io::Error>> {
-        Pin::new(&mut *self.conn).poll_read(cx, buf)
+#[async_trait]
+impl Manager<Pooled<TlsStream<TcpStream>>, Error> for TlsConnection {
+    async fn create(&self) -> Result<Pooled<TlsStream<TcpStream>>, Error> {
+        let stream = connect_with_timeout(self.connect_timeout, self.connect()).await?;
+        Ok(Pooled::new(stream))
+    }
+
+    async fn recycle(&self, conn: &mut Pooled<TlsStream<TcpStream>>) -> RecycleResult<Error> {
+        conn.recycle(self.idle_timeout)
     }
 }
 
-impl AsyncWrite for TlsConnWrapper {
-    fn poll_write(
-        mut self: Pin<&mut Self>,
-        cx: &mut Context<'_>,
-        buf: &[u8],
-    ) -> Poll<std::io::Result<usize>> {
-        let amt = futures::ready!(Pin::new(&mut *self.conn).poll_write(cx, buf))?;
-        Poll::Ready(Ok(amt))
+#[cfg(test)]
+mod tests {
+    use async_native_tls::TlsAcceptor;
+    use async_std::net::TcpListener;
+    use async_std::task;
+    use futures::io::{AsyncReadExt, AsyncWriteExt};
+    use surf::http::{Method, Request};
+    use surf::HttpClient;
+
+    use super::*;
+    use crate::http_client::PoolingClient;
+
+    /// Starts an HTTPS server with a certificate signed by the test CA in
+    /// `fixtures/ca.pem`, and returns its port.
+    async fn private_ca_server() -> u16 {
+        let acceptor =
+            TlsAcceptor::new(&include_bytes!("../../fixtures/server.p12")[..], "orogene")
+                .await
+                .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        task::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut stream = match acceptor.accept(stream).await {
+                    Ok(stream) => stream,
+                    Err(_) => continue,
+                };
+                let mut head = Vec::new();
+                let mut byte = [0u8];
+                while !head.ends_with(b"\r\n\r\n") {
+                    stream.read_exact(&mut byte).await.unwrap();
+                    head.push(byte[0]);
+                }
+                stream
+                    .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 6\r\n\r\nsecure")
+                    .await
+                    .unwrap();
+            }
+        });
+        port
     }
 
-    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
-        Pin::new(&mut *self.conn).poll_flush(cx)
+    /// Starts an HTTPS server like `private_ca_server`'s, which also refuses
+    /// clients that don't present a certificate signed by the test CA.
+    #[cfg(target_os = "linux")]
+    fn client_cert_server() -> u16 {
+        use std::io::{Read, Write};
+
+        use openssl::pkcs12::Pkcs12;
+        use openssl::ssl::{SslAcceptor, SslMethod, SslVerifyMode};
+
+        let der = include_bytes!("../../fixtures/server.p12");
+        #[allow(deprecated)]
+        let server = Pkcs12::from_der(der).unwrap().parse("orogene").unwrap();
+        let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
+        acceptor.set_private_key(&server.pkey).unwrap();
+        acceptor.set_certificate(&server.cert).unwrap();
+        acceptor.set_ca_file("fixtures/ca.pem").unwrap();
+        acceptor.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+        let acceptor = acceptor.build();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = match acceptor.accept(stream.unwrap()) {
+                    Ok(stream) => stream,
+                    Err(_) => continue,
+                };
+                let mut head = Vec::new();
+                let mut byte = [0u8];
+                while !head.ends_with(b"\r\n\r\n") {
+                    if stream.read_exact(&mut byte).is_err() {
+                        break;
+                    }
+                    head.push(byte[0]);
+                }
+                let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 6\r\n\r\nsecure");
+            }
+        });
+        port
     }
 
-    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
-        Pin::new(&mut *self.conn).poll_close(cx)
+    async fn get(tls: TlsConfig, port: u16) -> Result<String, Error> {
+        let client = PoolingClient::with_config(PoolConfig {
+            tls,
+            ..Default::default()
+        });
+        let url = format!("https://localhost:{}/", port);
+        let mut res = client.send(Request::new(Method::Get, url.as_str())).await?;
+        res.body_string().await
     }
-}
-#[async_trait]
-impl Manager<TlsStream<TcpStream>, Error> for TlsConnection {
-    async fn create(&self) -> Result<TlsStream<TcpStream>, Error> {
-        log::trace!("Creating new socket to {:?}", self.addr);
-        let raw_stream = async_std::net::TcpStream::connect(self.addr).await?;
-        let stream = async_native_tls::connect(&self.host, raw_stream).await?;
-        Ok(stream)
+
+    #[test]
+    fn test_ca() {
+        let pem = include_str!("../../fixtures/ca.pem");
+        let config = TlsConfig::new().ca(format!("{}\n{}", pem, pem)).unwrap();
+        assert_eq!(config.ca.len(), 2);
+        let config = config.ca_file("fixtures/ca.pem").unwrap();
+        assert_eq!(config.ca.len(), 3);
+
+        assert!(matches!(
+            TlsConfig::new().ca("not a certificate"),
+            Err(OroClientError::BadCertificate(_))
+        ));
+        assert!(matches!(
+            TlsConfig::new().ca_file("fixtures/missing.pem"),
+            Err(OroClientError::CertificateReadError { .. })
+        ));
+    }
+
+    #[test]
+    fn test_identity() {
+        let config = TlsConfig::new()
+            .identity_file("fixtures/client.p12", "orogene")
+            .unwrap();
+        assert!(config.identity.is_some());
+        assert!(matches!(
+            TlsConfig::new().identity_file("fixtures/client.p12", "wrong"),
+            Err(OroClientError::BadCertificate(_))
+        ));
+    }
+
+    #[async_attributes::test]
+    async fn test_private_ca() {
+        let port = private_ca_server().await;
+        assert!(get(TlsConfig::new(), port).await.is_err());
+        let tls = TlsConfig::new().ca_file("fixtures/ca.pem").unwrap();
+        assert_eq!(get(tls, port).await.unwrap(), "secure");
+    }
+
+    #[async_attributes::test]
+    async fn test_strict_ssl() {
+        let port = private_ca_server().await;
+        let tls = TlsConfig::new().host_strict_ssl("registry.internal", false);
+        assert!(get(tls, port).await.is_err());
+        let tls = TlsConfig::new().host_strict_ssl("LOCALHOST", false);
+        assert_eq!(get(tls, port).await.unwrap(), "secure");
+        let tls = TlsConfig::new()
+            .strict_ssl(false)
+            .host_strict_ssl("localhost", true);
+        assert!(get(tls, port).await.is_err());
     }
 
-    async fn recycle(&self, _conn: &mut TlsStream<TcpStream>) -> RecycleResult<Error> {
-        Ok(())
+    #[cfg(target_os = "linux")]
+    #[async_attributes::test]
+    async fn test_client_certificate() {
+        let port = client_cert_server();
+        let tls = TlsConfig::new().ca_file("fixtures/ca.pem").unwrap();
+        assert!(get(tls.clone(), port).await.is_err());
+        let tls = tls.identity_file("fixtures/client.p12", "orogene").unwrap();
+        assert_eq!(get(tls, port).await.unwrap(), "secure");
     }
 }
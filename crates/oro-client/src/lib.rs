@@ -1,3 +1,6 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
 use oro_diagnostics::{Diagnostic, DiagnosticCategory, Explain, Meta};
 use serde::Deserialize;
 use surf::Client;
@@ -5,12 +8,25 @@ use thiserror::Error;
 
 pub use surf::{
     http::{url::ParseError, Method, StatusCode, Url},
-    Error as SurfError, RequestBuilder, Response,
+    Error as SurfError, Request, RequestBuilder, Response,
 };
 
-use crate::http_client::PoolingClient;
+pub use crate::auth::{Credentials, RegistryAuth};
+pub use crate::builder::OroClientBuilder;
+pub use crate::cache::CacheMode;
+use crate::cache::HttpCache;
+use crate::http_client::{PoolConfig, PoolingClient};
+pub use crate::http_client::{ProxyConfig, TlsConfig};
+pub use crate::redirect::FinalUrl;
+pub use crate::retry::RetryPolicy;
 
+mod auth;
+mod builder;
+mod cache;
+mod encoding;
 mod http_client;
+mod redirect;
+mod retry;
 
 #[derive(Debug, Error, Diagnostic)]
 pub enum OroClientError {
@@ -28,6 +44,43 @@ pub enum OroClientError {
         status_code: StatusCode,
         message: Option<String>,
     },
+
+    #[error("Failed to read TLS certificate file at {path:?}:\n\t{source}")]
+    #[category(Fs)]
+    #[label("client::certificate_read_error")]
+    CertificateReadError {
+        source: std::io::Error,
+        path: PathBuf,
+    },
+
+    #[error("Invalid TLS certificate or key:\n\t{0}")]
+    #[category(Parse)]
+    #[label("client::bad_certificate")]
+    #[advice("CA certificates should be PEM-encoded, and client certificates should be PKCS #12 archives.")]
+    BadCertificate(#[source] async_native_tls::Error),
+
+    #[error("No cached response is available for this request, and the cache mode does not allow fetching it.")]
+    #[category(Net)]
+    #[label("client::no_cached_response")]
+    #[advice("Make this request once with network access, so it gets cached.")]
+    NoCachedResponse { url: Url },
+
+    #[error("Registry request timed out: {message}")]
+    #[category(Net)]
+    #[label("client::timeout")]
+    #[advice("The registry or network may be overloaded. Try again later, or raise the client's timeouts.")]
+    Timeout { url: Url, message: String },
+
+    #[error("Gave up after following {count} redirects, starting from {url}.")]
+    #[category(Net)]
+    #[label("client::too_many_redirects")]
+    #[advice("The registry may be misconfigured and redirecting in a loop.")]
+    TooManyRedirects { url: Url, count: usize },
+
+    #[error("Registry sent an invalid redirect location: {location}")]
+    #[category(Net)]
+    #[label("client::bad_redirect")]
+    BadRedirect { url: Url, location: String },
 }
 
 impl Explain for OroClientError {
@@ -40,6 +93,20 @@ impl Explain for OroClientError {
             ResponseError { ref url, .. } => Some(Meta::Net {
                 url: Some(url.clone()),
             }),
+            NoCachedResponse { ref url } => Some(Meta::Net {
+                url: Some(url.clone()),
+            }),
+            Timeout { ref url, .. } => Some(Meta::Net {
+                url: Some(url.clone()),
+            }),
+            TooManyRedirects { ref url, .. } => Some(Meta::Net {
+                url: Some(url.clone()),
+            }),
+            BadRedirect { ref url, .. } => Some(Meta::Net {
+                url: Some(url.clone()),
+            }),
+            CertificateReadError { ref path, .. } => Some(Meta::Fs { path: path.clone() }),
+            BadCertificate(_) => None,
         }
     }
 }
@@ -52,13 +119,18 @@ struct NpmError {
 #[derive(Clone, Debug)]
 pub struct OroClient {
     client: Client,
+    retry: RetryPolicy,
+    cache: Option<HttpCache>,
+    cache_mode: CacheMode,
+    auth: RegistryAuth,
+    pool: PoolConfig,
+    timeout: Option<Duration>,
+    max_redirects: usize,
 }
 
 impl Default for OroClient {
     fn default() -> Self {
-        Self {
-            client: Client::with_http_client(PoolingClient::new()),
-        }
+        OroClientBuilder::new().build()
     }
 }
 
@@ -67,21 +139,90 @@ impl OroClient {
         Default::default()
     }
 
+    pub fn builder() -> OroClientBuilder {
+        OroClientBuilder::new()
+    }
+
+    /// Sets the policy for retrying requests that fail for transient reasons.
+    pub fn retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Sets the proxies to send requests through. By default, they're read
+    /// from the `HTTP_PROXY`, `HTTPS_PROXY` and `NO_PROXY` environment
+    /// variables.
+    pub fn proxy(mut self, proxy: ProxyConfig) -> Self {
+        self.pool.proxy = proxy;
+        self.rebuild_client();
+        self
+    }
+
+    /// Sets the TLS settings for HTTPS connections.
+    pub fn tls(mut self, tls: TlsConfig) -> Self {
+        self.pool.tls = tls;
+        self.rebuild_client();
+        self
+    }
+
+    /// Sets the credentials to send to registries. Requests that already
+    /// have an `Authorization` header are left alone.
+    pub fn auth(mut self, auth: RegistryAuth) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    fn rebuild_client(&mut self) {
+        self.client = Client::with_http_client(PoolingClient::with_config(self.pool.clone()));
+    }
+
+    /// Caches `GET` responses in the cacache directory at `cache`, and uses
+    /// them to answer later requests according to the cache mode.
+    pub fn cache(mut self, cache: impl AsRef<Path>) -> Self {
+        self.cache = Some(HttpCache::new(cache));
+        self
+    }
+
+    /// Sets how the cache is used. Has no effect unless a cache directory is
+    /// set with `cache`.
+    pub fn cache_mode(mut self, cache_mode: CacheMode) -> Self {
+        self.cache_mode = cache_mode;
+        self
+    }
+
     pub fn opts(&self, method: Method, uri: Url) -> RequestBuilder {
         RequestBuilder::new(method, uri)
     }
 
     pub async fn send(&self, request: RequestBuilder) -> Result<Response, OroClientError> {
-        let req = request.build();
+        let mut req = request.build();
+        if req.header("Authorization").is_none() {
+            if let Some(credentials) = self.auth.credentials_for(req.url()) {
+                req.insert_header("Authorization", credentials.header());
+            }
+        }
+        let decode = encoding::negotiate(&mut req) && req.method() != Method::Head;
         let url = req.url().clone();
-        let mut res = self
-            .client
-            .send(req)
-            .await
-            .map_err(|e| OroClientError::RequestError {
-                surf_err: e,
-                url: url.clone(),
-            })?;
+        let mut res = match self.cache {
+            Some(ref cache)
+                if req.method() == Method::Get && self.cache_mode != CacheMode::NoStore =>
+            {
+                self.send_cached(cache, req).await?
+            }
+            _ => self.fetch(req).await?,
+        };
+        // Cached responses are stored still encoded, so this also decodes
+        // those.
+        if decode {
+            res = encoding::decode(res);
+        }
+        let url = match res.ext::<FinalUrl>() {
+            Some(FinalUrl(url)) => url.clone(),
+            None => {
+                res.insert_ext(FinalUrl(url.clone()));
+                url
+            }
+        };
         if res.status().is_client_error() || res.status().is_server_error() {
             let msg = match res.body_json::<NpmError>().await {
                 Ok(err) => err.message,
@@ -105,4 +246,206 @@ impl OroClient {
             Ok(res)
         }
     }
+
+    async fn send_cached(
+        &self,
+        cache: &HttpCache,
+        req: Request,
+    ) -> Result<Response, OroClientError> {
+        let cached = if self.cache_mode == CacheMode::Reload {
+            None
+        } else {
+            cache.get(&req).await
+        };
+        match (self.cache_mode, cached) {
+            (CacheMode::OnlyIfCached, None) => Err(OroClientError::NoCachedResponse {
+                url: req.url().clone(),
+            }),
+            (CacheMode::ForceCache, Some(cached)) | (CacheMode::OnlyIfCached, Some(cached)) => {
+                Ok(cached.into_response("hit"))
+            }
+            (CacheMode::Default, Some(cached)) if cached.is_fresh() => {
+                Ok(cached.into_response("hit"))
+            }
+            (mode, Some(cached)) => {
+                let mut revalidate = req.clone();
+                cached.add_validators(&mut revalidate);
+                match self.fetch(revalidate).await {
+                    Ok(res) if res.status() == StatusCode::NotModified => {
+                        Ok(cache.revalidated(&req, cached, &res).await)
+                    }
+                    // Better a stale packument than no packument at all.
+                    Ok(res) if mode == CacheMode::Default && res.status().is_server_error() => {
+                        log::warn!(
+                            "{} {} failed with status {}. Using stale cached response.",
+                            req.method(),
+                            req.url(),
+                            res.status()
+                        );
+                        Ok(cached.into_response("stale"))
+                    }
+                    Err(err) if mode == CacheMode::Default => {
+                        log::warn!(
+                            "{} {} failed: {}. Using stale cached response.",
+                            req.method(),
+                            req.url(),
+                            err
+                        );
+                        Ok(cached.into_response("stale"))
+                    }
+                    res => Ok(cache.put(&req, res?)),
+                }
+            }
+            (_, None) => {
+                let res = self.fetch(req.clone()).await?;
+                Ok(cache.put(&req, res))
+            }
+        }
+    }
+
+    /// Sends `req` over the network, following redirects and retrying
+    /// failed attempts according to the retry policy.
+    async fn fetch(&self, mut req: Request) -> Result<Response, OroClientError> {
+        let start = req.url().clone();
+        // Bodies are streams, so buffer them up front to be able to send
+        // them more than once.
+        let mut body = req
+            .take_body()
+            .into_bytes()
+            .await
+            .map_err(|e| request_error(e, start.clone()))?;
+        let mut redirects = 0;
+        loop {
+            let mut res = self.fetch_with_retries(&req, &body).await?;
+            let location = match redirect::location(req.url(), &res) {
+                Some(_) if self.max_redirects == 0 => None,
+                location => location,
+            };
+            let location = match location {
+                Some(Ok(location)) => location,
+                Some(Err(location)) => {
+                    return Err(OroClientError::BadRedirect {
+                        url: req.url().clone(),
+                        location,
+                    })
+                }
+                None => {
+                    res.insert_ext(FinalUrl(req.url().clone()));
+                    return Ok(res);
+                }
+            };
+            if redirects == self.max_redirects {
+                return Err(OroClientError::TooManyRedirects {
+                    url: start,
+                    count: redirects,
+                });
+            }
+            redirects += 1;
+            log::debug!(
+                "{} {} redirected to {} ({})",
+                req.method(),
+                req.url(),
+                location,
+                res.status()
+            );
+            // Read the rest of the response, so its connection can be
+            // reused.
+            let _ = res.body_bytes().await;
+            req = redirect::follow(&req, &mut body, res.status(), location, &self.auth);
+        }
+    }
+
+    /// Sends `req` with `body`, retrying it according to the retry policy.
+    async fn fetch_with_retries(
+        &self,
+        req: &Request,
+        body: &[u8],
+    ) -> Result<Response, OroClientError> {
+        let url = req.url().clone();
+        let method = req.method();
+        let attempts = if method == Method::Post {
+            1
+        } else {
+            self.retry.attempts()
+        };
+        let mut attempt = 1;
+        loop {
+            log::debug!("{} {} (attempt {}/{})", method, url, attempt, attempts);
+            let mut attempt_req = req.clone();
+            if !body.is_empty() {
+                attempt_req.set_body(body.to_vec());
+            }
+            let mut res = self.send_attempt(attempt_req).await;
+            let delay = if attempt < attempts {
+                self.retry.delay(attempt, &res)
+            } else {
+                None
+            };
+            match delay {
+                Some(delay) => {
+                    match &res {
+                        Ok(res) => log::warn!(
+                            "{} {} failed with status {} (attempt {}/{}). Retrying in {:?}.",
+                            method,
+                            url,
+                            res.status(),
+                            attempt,
+                            attempts,
+                            delay
+                        ),
+                        Err(err) => log::warn!(
+                            "{} {} failed: {} (attempt {}/{}). Retrying in {:?}.",
+                            method,
+                            url,
+                            err,
+                            attempt,
+                            attempts,
+                            delay
+                        ),
+                    }
+                    // Read the rest of the response, so its connection can be
+                    // reused.
+                    if let Ok(res) = &mut res {
+                        let _ = res.body_bytes().await;
+                    }
+                    async_std::task::sleep(delay).await;
+                    attempt += 1;
+                }
+                None => return res.map_err(|e| request_error(e, url.clone())),
+            }
+        }
+    }
+
+    /// Sends a single attempt at a request. The timeout covers getting the
+    /// response's headers, not reading its body, and a timed out attempt can
+    /// be retried like any other.
+    async fn send_attempt(&self, req: Request) -> Result<Response, SurfError> {
+        let timeout = match self.timeout {
+            Some(timeout) => timeout,
+            None => return self.client.send(req).await,
+        };
+        async_std::future::timeout(timeout, self.client.send(req))
+            .await
+            .unwrap_or_else(|_| {
+                Err(SurfError::new(
+                    StatusCode::RequestTimeout,
+                    std::io::Error::new(
+                        std::io::ErrorKind::TimedOut,
+                        format!("no response within {:?}", timeout),
+                    ),
+                ))
+            })
+    }
+}
+
+/// Wraps a failed request's error, telling timeouts apart from other
+/// failures.
+fn request_error(err: SurfError, url: Url) -> OroClientError {
+    match err.downcast_ref::<std::io::Error>() {
+        Some(io_err) if io_err.kind() == std::io::ErrorKind::TimedOut => OroClientError::Timeout {
+            url,
+            message: io_err.to_string(),
+        },
+        _ => OroClientError::RequestError { surf_err: err, url },
+    }
 }
@@ -0,0 +1,254 @@
+use surf::http::{Method, StatusCode, Url};
+use surf::{Request, Response};
+
+use crate::auth::RegistryAuth;
+
+/// The URL a response came from, after following any redirects. `OroClient`
+/// attaches it to every response it returns, so it can be read with
+/// `res.ext::<FinalUrl>()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FinalUrl(pub Url);
+
+/// Headers that shouldn't follow a request to a different origin.
+const CREDENTIAL_HEADERS: &[&str] = &["authorization", "cookie"];
+
+/// Headers that describe a request body, which go away along with the body.
+const BODY_HEADERS: &[&str] = &["content-type", "content-length", "content-encoding"];
+
+/// The redirect target of `res`, a response to a request for `url`, if it's
+/// a redirect `OroClient` follows. Relative locations are resolved against
+/// `url`.
+pub(crate) fn location(url: &Url, res: &Response) -> Option<Result<Url, String>> {
+    match res.status() {
+        StatusCode::MovedPermanently
+        | StatusCode::Found
+        | StatusCode::SeeOther
+        | StatusCode::TemporaryRedirect
+        | StatusCode::PermanentRedirect => {}
+        _ => return None,
+    }
+    let location = res.header("Location")?.last().as_str();
+    Some(match url.join(location) {
+        Ok(next) if next.scheme() == "http" || next.scheme() == "https" => Ok(next),
+        _ => Err(location.to_owned()),
+    })
+}
+
+/// Turns `req` into the request that follows a redirect to `location` with
+/// `status`.
+///
+/// Like browsers do, `303 See Other` turns anything but a `HEAD` into a
+/// `GET`, and so do `301` and `302` for `POST`s. Those drop the body, while
+/// `307` and `308` resend everything as-is. Credentials are dropped when the
+/// redirect goes to a different origin, and replaced by whatever `auth` has
+/// for the new location.
+pub(crate) fn follow(
+    req: &Request,
+    body: &mut Vec<u8>,
+    status: StatusCode,
+    location: Url,
+    auth: &RegistryAuth,
+) -> Request {
+    let method = match (status, req.method()) {
+        (StatusCode::SeeOther, Method::Head) => Method::Head,
+        (StatusCode::SeeOther, _) => Method::Get,
+        (StatusCode::MovedPermanently, Method::Post) | (StatusCode::Found, Method::Post) => {
+            Method::Get
+        }
+        (_, method) => method,
+    };
+    let drop_body = method != req.method();
+    let cross_origin = location.origin() != req.url().origin();
+    let mut next = Request::new(method, location);
+    for (name, values) in req.iter() {
+        let name_str = name.as_str().to_ascii_lowercase();
+        if name_str == "host"
+            || (drop_body && BODY_HEADERS.contains(&name_str.as_str()))
+            || (cross_origin && CREDENTIAL_HEADERS.contains(&name_str.as_str()))
+        {
+            continue;
+        }
+        for value in values.iter() {
+            next.append_header(name.clone(), value.clone());
+        }
+    }
+    if drop_body {
+        body.clear();
+    }
+    if cross_origin {
+        if let Some(credentials) = auth.credentials_for(next.url()) {
+            next.insert_header("Authorization", credentials.header());
+        }
+    }
+    next
+}
+
+#[cfg(test)]
+mod tests {
+    use async_std::net::TcpListener;
+    use async_std::task;
+    use futures::io::{AsyncReadExt, AsyncWriteExt};
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::{Credentials, OroClient, OroClientError};
+
+    fn redirect(status: StatusCode, location: &str) -> Response {
+        let mut res = surf::http::Response::new(status);
+        res.insert_header("Location", location);
+        res.into()
+    }
+
+    #[test]
+    fn test_location() {
+        let url: Url = "https://registry.corp/npm/pkg".parse().unwrap();
+        let res = redirect(StatusCode::Found, "/-/pkg-1.0.0.tgz");
+        assert_eq!(
+            location(&url, &res),
+            Some(Ok("https://registry.corp/-/pkg-1.0.0.tgz".parse().unwrap()))
+        );
+        let res = redirect(StatusCode::PermanentRedirect, "https://cdn.corp/pkg.tgz");
+        assert_eq!(
+            location(&url, &res),
+            Some(Ok("https://cdn.corp/pkg.tgz".parse().unwrap()))
+        );
+        let res = redirect(StatusCode::Found, "file:///etc/passwd");
+        assert_eq!(location(&url, &res), Some(Err("file:///etc/passwd".into())));
+        let res = redirect(StatusCode::NotModified, "/elsewhere");
+        assert_eq!(location(&url, &res), None);
+    }
+
+    #[test]
+    fn test_follow() {
+        let auth = RegistryAuth::new()
+            .add("//registry.corp/", Credentials::Token("registry".into()))
+            .add("//mirror.corp/", Credentials::Token("mirror".into()));
+        let mut req = Request::new(Method::Post, "https://registry.corp/a".parse().unwrap());
+        req.insert_header("Authorization", "Bearer registry");
+        req.insert_header("Content-Type", "application/json");
+        req.insert_header("Accept", "application/json");
+
+        let mut body = b"{}".to_vec();
+        let next = follow(
+            &req,
+            &mut body,
+            StatusCode::TemporaryRedirect,
+            "https://registry.corp/b".parse().unwrap(),
+            &auth,
+        );
+        assert_eq!(next.method(), Method::Post);
+        assert_eq!(body, b"{}");
+        assert_eq!(next.header("Authorization").unwrap(), "Bearer registry");
+        assert_eq!(next.header("Content-Type").unwrap(), "application/json");
+
+        let next = follow(
+            &req,
+            &mut body,
+            StatusCode::Found,
+            "https://cdn.corp/b".parse().unwrap(),
+            &auth,
+        );
+        assert_eq!(next.method(), Method::Get);
+        assert!(body.is_empty());
+        assert!(next.header("Authorization").is_none());
+        assert!(next.header("Content-Type").is_none());
+        assert_eq!(next.header("Accept").unwrap(), "application/json");
+
+        let next = follow(
+            &req,
+            &mut Vec::new(),
+            StatusCode::PermanentRedirect,
+            "https://mirror.corp/b".parse().unwrap(),
+            &auth,
+        );
+        assert_eq!(next.header("Authorization").unwrap(), "Bearer mirror");
+
+        // Same host, different origin.
+        let next = follow(
+            &req,
+            &mut Vec::new(),
+            StatusCode::MovedPermanently,
+            "https://registry.corp:8443/b".parse().unwrap(),
+            &auth,
+        );
+        assert!(next.header("Authorization").is_none());
+    }
+
+    /// Starts a server that answers each connection's requests with
+    /// `respond`, which gets the request path.
+    async fn server(respond: fn(&str, &str) -> String) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let origin = format!("http://{}", listener.local_addr().unwrap());
+        let base = origin.clone();
+        task::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let base = base.clone();
+                task::spawn(async move {
+                    loop {
+                        let mut head = Vec::new();
+                        let mut byte = [0u8];
+                        while !head.ends_with(b"\r\n\r\n") {
+                            if stream.read_exact(&mut byte).await.is_err() {
+                                return;
+                            }
+                            head.push(byte[0]);
+                        }
+                        let head = String::from_utf8(head).unwrap();
+                        let path = head.split(' ').nth(1).unwrap().to_owned();
+                        let res = respond(&base, &path);
+                        stream.write_all(res.as_bytes()).await.unwrap();
+                    }
+                });
+            }
+        });
+        origin
+    }
+
+    #[async_attributes::test]
+    async fn test_send() {
+        let origin = server(|base, path| {
+            let (status, location) = match path {
+                "/start" => ("302 Found", format!("{}/middle", base)),
+                "/middle" => ("301 Moved Permanently", "/end".to_string()),
+                "/loop" => ("307 Temporary Redirect", "/loop".to_string()),
+                _ => {
+                    return format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+                        path.len(),
+                        path
+                    )
+                }
+            };
+            format!(
+                "HTTP/1.1 {}\r\nLocation: {}\r\nContent-Length: 5\r\n\r\nmoved",
+                status, location
+            )
+        })
+        .await;
+
+        let client = OroClient::builder().max_redirects(3).build();
+        let url: Url = format!("{}/start", origin).parse().unwrap();
+        let mut res = client.send(client.opts(Method::Get, url)).await.unwrap();
+        assert_eq!(res.body_string().await.unwrap(), "/end");
+        assert_eq!(
+            res.ext::<FinalUrl>(),
+            Some(&FinalUrl(format!("{}/end", origin).parse().unwrap()))
+        );
+
+        let url: Url = format!("{}/loop", origin).parse().unwrap();
+        let err = client
+            .send(client.opts(Method::Get, url))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err, OroClientError::TooManyRedirects { count: 3, .. }),
+            "unexpected error: {:?}",
+            err
+        );
+
+        let client = OroClient::builder().max_redirects(0).build();
+        let url: Url = format!("{}/start", origin).parse().unwrap();
+        let res = client.send(client.opts(Method::Get, url)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::Found);
+    }
+}
@@ -0,0 +1,384 @@
+use std::io::ErrorKind;
+use std::time::{Duration, SystemTime};
+
+use surf::{Response, StatusCode};
+
+use crate::SurfError;
+
+/// Controls how `OroClient` retries requests that fail for reasons that are
+/// likely to go away on their own, like dropped connections, timeouts, rate
+/// limiting, and overloaded servers.
+///
+/// Failed attempts are retried after an exponentially growing delay, with
+/// random jitter so many clients don't retry in lockstep. If the registry
+/// sends a `Retry-After` header, it's used instead, up to `max_delay`.
+///
+/// `POST` requests are never retried, since they may not be safe to repeat.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    max_attempts: usize,
+    min_delay: Duration,
+    max_delay: Duration,
+    factor: u32,
+    jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            min_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            factor: 2,
+            jitter: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Creates the default policy: up to 3 attempts, starting with a 1 second
+    /// delay that doubles each time, with jitter.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// A policy that never retries anything.
+    pub fn none() -> Self {
+        Self::new().max_attempts(1)
+    }
+
+    /// Sets the total number of attempts to make, including the first one.
+    pub fn max_attempts(mut self, max_attempts: usize) -> Self {
+        self.max_attempts = std::cmp::max(max_attempts, 1);
+        self
+    }
+
+    /// Sets the delay before the first retry.
+    pub fn min_delay(mut self, min_delay: Duration) -> Self {
+        self.min_delay = min_delay;
+        self
+    }
+
+    /// Sets the longest delay between two attempts, including delays asked
+    /// for with `Retry-After`.
+    pub fn max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// Sets how much the delay grows after each retry.
+    pub fn factor(mut self, factor: u32) -> Self {
+        self.factor = factor;
+        self
+    }
+
+    /// Sets whether delays are randomized. Defaults to true.
+    pub fn jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    pub(crate) fn attempts(&self) -> usize {
+        self.max_attempts
+    }
+
+    /// How long to wait before retrying after attempt number `attempt`
+    /// (starting from 1) got `res`, or `None` if it shouldn't be retried.
+    /// Callers are responsible for stopping after `attempts()` attempts.
+    pub(crate) fn delay(
+        &self,
+        attempt: usize,
+        res: &Result<Response, SurfError>,
+    ) -> Option<Duration> {
+        match res {
+            Ok(res) if is_retriable_status(res.status()) => Some(
+                retry_after(res)
+                    .map(|delay| std::cmp::min(delay, self.max_delay))
+                    .unwrap_or_else(|| self.backoff(attempt)),
+            ),
+            Ok(_) => None,
+            Err(err) if is_retriable_error(err) => Some(self.backoff(attempt)),
+            Err(_) => None,
+        }
+    }
+
+    fn backoff(&self, attempt: usize) -> Duration {
+        let exp = std::cmp::min(attempt - 1, 32) as u32;
+        let delay = self
+            .min_delay
+            .checked_mul(self.factor.saturating_pow(exp))
+            .map_or(self.max_delay, |delay| std::cmp::min(delay, self.max_delay));
+        if self.jitter {
+            // Keep at least half the delay, and randomize the rest.
+            let half = delay / 2;
+            half + half.mul_f64(fastrand::f64())
+        } else {
+            delay
+        }
+    }
+}
+
+fn is_retriable_status(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::RequestTimeout
+            | StatusCode::TooManyRequests
+            | StatusCode::InternalServerError
+            | StatusCode::BadGateway
+            | StatusCode::ServiceUnavailable
+            | StatusCode::GatewayTimeout
+    )
+}
+
+fn is_retriable_error(err: &SurfError) -> bool {
+    match err.downcast_ref::<std::io::Error>() {
+        Some(err) => matches!(
+            err.kind(),
+            ErrorKind::ConnectionReset
+                | ErrorKind::ConnectionAborted
+                | ErrorKind::BrokenPipe
+                | ErrorKind::UnexpectedEof
+                | ErrorKind::TimedOut
+                | ErrorKind::Interrupted
+        ),
+        None => false,
+    }
+}
+
+/// Reads a `Retry-After` header, given either as a number of seconds or as an
+/// HTTP date.
+fn retry_after(res: &Response) -> Option<Duration> {
+    let value = res.header("Retry-After")?.last().as_str().trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or_else(|_| Duration::from_secs(0)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use async_std::net::TcpListener;
+    use async_std::task;
+    use futures::io::{AsyncReadExt, AsyncWriteExt};
+    use pretty_assertions::assert_eq;
+    use surf::http::{self, Method};
+
+    use super::*;
+    use crate::{OroClient, OroClientError};
+
+    fn response(status: StatusCode, retry_after: Option<&str>) -> Result<Response, SurfError> {
+        let mut res = http::Response::new(status);
+        if let Some(retry_after) = retry_after {
+            res.insert_header("Retry-After", retry_after);
+        }
+        Ok(res.into())
+    }
+
+    fn io_error(kind: ErrorKind) -> Result<Response, SurfError> {
+        Err(SurfError::new(
+            StatusCode::InternalServerError,
+            std::io::Error::new(kind, "oops"),
+        ))
+    }
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy::new()
+            .min_delay(Duration::from_secs(1))
+            .max_delay(Duration::from_secs(10))
+            .factor(2)
+            .jitter(false)
+    }
+
+    #[test]
+    fn test_backoff() {
+        let policy = policy();
+        assert_eq!(policy.backoff(1), Duration::from_secs(1));
+        assert_eq!(policy.backoff(2), Duration::from_secs(2));
+        assert_eq!(policy.backoff(3), Duration::from_secs(4));
+        assert_eq!(policy.backoff(5), Duration::from_secs(10));
+        assert_eq!(policy.backoff(1000), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_jitter() {
+        let policy = policy().jitter(true);
+        for _ in 0..100 {
+            let delay = policy.backoff(3);
+            assert!(delay >= Duration::from_secs(2), "{:?} is too short", delay);
+            assert!(delay <= Duration::from_secs(4), "{:?} is too long", delay);
+        }
+    }
+
+    #[test]
+    fn test_max_attempts() {
+        assert_eq!(RetryPolicy::new().attempts(), 3);
+        assert_eq!(RetryPolicy::none().attempts(), 1);
+        assert_eq!(RetryPolicy::new().max_attempts(0).attempts(), 1);
+    }
+
+    #[test]
+    fn test_delay_statuses() {
+        let policy = policy();
+        for status in &[
+            StatusCode::RequestTimeout,
+            StatusCode::TooManyRequests,
+            StatusCode::InternalServerError,
+            StatusCode::BadGateway,
+            StatusCode::ServiceUnavailable,
+            StatusCode::GatewayTimeout,
+        ] {
+            assert_eq!(
+                policy.delay(2, &response(*status, None)),
+                Some(Duration::from_secs(2)),
+                "{} should be retried",
+                status
+            );
+        }
+        for status in &[
+            StatusCode::Ok,
+            StatusCode::NotModified,
+            StatusCode::NotFound,
+            StatusCode::Unauthorized,
+            StatusCode::NotImplemented,
+        ] {
+            assert_eq!(
+                policy.delay(1, &response(*status, None)),
+                None,
+                "{} shouldn't be retried",
+                status
+            );
+        }
+    }
+
+    #[test]
+    fn test_delay_errors() {
+        let policy = policy();
+        for kind in &[
+            ErrorKind::ConnectionReset,
+            ErrorKind::ConnectionAborted,
+            ErrorKind::BrokenPipe,
+            ErrorKind::UnexpectedEof,
+            ErrorKind::TimedOut,
+            ErrorKind::Interrupted,
+        ] {
+            assert_eq!(
+                policy.delay(1, &io_error(*kind)),
+                Some(Duration::from_secs(1)),
+                "{:?} should be retried",
+                kind
+            );
+        }
+        assert_eq!(
+            policy.delay(1, &io_error(ErrorKind::PermissionDenied)),
+            None
+        );
+        let err = Err(SurfError::from_str(StatusCode::BadRequest, "bad url"));
+        assert_eq!(policy.delay(1, &err), None);
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let policy = policy();
+        let res = response(StatusCode::TooManyRequests, Some("3"));
+        assert_eq!(policy.delay(1, &res), Some(Duration::from_secs(3)));
+        let res = response(StatusCode::ServiceUnavailable, Some("3600"));
+        assert_eq!(policy.delay(1, &res), Some(Duration::from_secs(10)));
+
+        let later = httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(6));
+        let res = response(StatusCode::ServiceUnavailable, Some(&later));
+        let delay = policy.delay(1, &res).unwrap();
+        assert!(
+            delay > Duration::from_secs(4) && delay <= Duration::from_secs(6),
+            "unexpected delay {:?}",
+            delay
+        );
+        let res = response(
+            StatusCode::ServiceUnavailable,
+            Some("Wed, 21 Oct 2015 07:28:00 GMT"),
+        );
+        assert_eq!(policy.delay(1, &res), Some(Duration::from_secs(0)));
+
+        // Garbage falls back to the usual backoff.
+        let res = response(StatusCode::ServiceUnavailable, Some("soon"));
+        assert_eq!(policy.delay(2, &res), Some(Duration::from_secs(2)));
+    }
+
+    /// Starts a server that answers the first request with a `503` and every
+    /// later one with a `200`. Returns its URL and a count of the requests it
+    /// got.
+    async fn flaky_server() -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/pkg", listener.local_addr().unwrap());
+        let count = Arc::new(AtomicUsize::new(0));
+        let seen = count.clone();
+        task::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let seen = seen.clone();
+                task::spawn(async move {
+                    loop {
+                        let mut head = Vec::new();
+                        let mut byte = [0u8];
+                        while !head.ends_with(b"\r\n\r\n") {
+                            if stream.read_exact(&mut byte).await.is_err() {
+                                return;
+                            }
+                            head.push(byte[0]);
+                        }
+                        let res = if seen.fetch_add(1, Ordering::SeqCst) == 0 {
+                            "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 4\r\n\r\nbusy"
+                        } else {
+                            "HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok"
+                        };
+                        stream.write_all(res.as_bytes()).await.unwrap();
+                    }
+                });
+            }
+        });
+        (url, count)
+    }
+
+    #[async_attributes::test]
+    async fn test_send_retries() {
+        let (url, count) = flaky_server().await;
+        let client = OroClient::builder()
+            .retry_policy(RetryPolicy::new().min_delay(Duration::from_millis(10)))
+            .build();
+        let mut res = client
+            .send(client.opts(Method::Get, url.parse().unwrap()))
+            .await
+            .unwrap();
+        assert_eq!(res.body_string().await.unwrap(), "ok");
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+    }
+
+    #[async_attributes::test]
+    async fn test_post_not_retried() {
+        let (url, count) = flaky_server().await;
+        let client = OroClient::builder()
+            .retry_policy(RetryPolicy::new().min_delay(Duration::from_millis(10)))
+            .build();
+        let err = client
+            .send(client.opts(Method::Post, url.parse().unwrap()).body("{}"))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(
+                err,
+                OroClientError::ResponseError {
+                    status_code: StatusCode::ServiceUnavailable,
+                    ..
+                }
+            ),
+            "unexpected error: {:?}",
+            err
+        );
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+    }
+}
@@ -79,7 +79,9 @@ impl GitFetcher {
 
     async fn fetch_tarball(&self, dir: &Path, tarball: &Url) -> Result<()> {
         let client = self.client.lock().await.clone();
-        let opts = client.opts(Method::Get, tarball.clone());
+        let opts = client
+            .opts(Method::Get, tarball.clone())
+            .header("Accept-Encoding", "identity");
         let tarball = client
             .send(opts)
             .await
@@ -149,9 +149,14 @@ impl PackageFetcher for NpmFetcher {
             PackageResolution::Npm { ref tarball, .. } => tarball,
             _ => panic!("How did a non-Npm resolution get here?"),
         };
+        // Tarballs are checked against their integrity, so they have to
+        // come through byte-for-byte.
+        let opts = client
+            .opts(Method::Get, url.clone())
+            .header("Accept-Encoding", "identity");
         Ok(Box::new(
             client
-                .send(client.opts(Method::Get, url.clone()))
+                .send(opts)
                 .await
                 .map_err(RoggaError::OroClientError)?,
         ))
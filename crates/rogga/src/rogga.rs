@@ -5,6 +5,7 @@ use async_std::sync::{Arc, Mutex};
 use oro_client::OroClient;
 use url::Url;
 
+pub use oro_client::{CacheMode, Credentials, RegistryAuth};
 pub use oro_package_spec::{PackageSpec, VersionSpec};
 
 use crate::error::Result;
@@ -15,7 +16,9 @@ use crate::request::PackageRequest;
 #[derive(Default)]
 pub struct RoggaOpts {
     cache: Option<PathBuf>,
+    cache_mode: CacheMode,
     registries: HashMap<String, Url>,
+    auth: RegistryAuth,
     use_corgi: Option<bool>,
 }
 
@@ -29,18 +32,37 @@ impl RoggaOpts {
         self
     }
 
+    /// Sets how cached registry responses are used. Has no effect unless a
+    /// cache directory is set.
+    pub fn cache_mode(mut self, cache_mode: CacheMode) -> Self {
+        self.cache_mode = cache_mode;
+        self
+    }
+
     pub fn add_registry(mut self, scope: impl AsRef<str>, registry: Url) -> Self {
         self.registries.insert(scope.as_ref().into(), registry);
         self
     }
 
+    /// Sets the credentials to send to registries, matched by URL prefix.
+    pub fn auth(mut self, auth: RegistryAuth) -> Self {
+        self.auth = auth;
+        self
+    }
+
     pub fn use_corgi(mut self, use_corgi: bool) -> Self {
         self.use_corgi = Some(use_corgi);
         self
     }
 
     pub fn build(self) -> Rogga {
-        let client = Arc::new(Mutex::new(OroClient::new()));
+        let mut client = OroClient::builder()
+            .cache_mode(self.cache_mode)
+            .auth(self.auth);
+        if let Some(ref cache) = self.cache {
+            client = client.cache(cache);
+        }
+        let client = Arc::new(Mutex::new(client.build()));
         let use_corgi = self.use_corgi.unwrap_or(false);
         Rogga {
             // cache: self.cache,